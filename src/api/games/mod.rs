@@ -0,0 +1,162 @@
+use actix_web::{web, HttpResponse};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::{
+    users::user::{PlayedGameInfo, PlayedMove, PublicUserOther, RatingChange, UserId},
+    ApiError, ApiResponse,
+};
+use crate::{
+    database::DatabaseManager,
+    game::game_info::{BoardConfig, GameResult, Player},
+    logging::{GameEndReason, GameOId},
+};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/{game_id}", web::get().to(get_game));
+}
+
+/// A stored game with everything needed to step through a replay
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicGame {
+    pub id: String,
+    pub player_one: Option<PublicUserOther>,
+    pub player_two: Option<PublicUserOther>,
+    pub starting_player: Player,
+    pub board: BoardConfig,
+    pub moves: Vec<PlayedMove>,
+    pub result: Option<GameResult>,
+    pub end_reason: GameEndReason,
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub rating_change: Option<RatingChange>,
+    pub bot_level: Option<i32>,
+}
+
+/// Entry of a user's match history, seen from that user's perspective
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicGameSummary {
+    pub id: String,
+    pub opponent: Option<PublicUserOther>,
+    pub you_started: bool,
+    pub board: BoardConfig,
+    pub move_count: usize,
+    pub outcome: Option<GameOutcome>,
+    pub end_reason: GameEndReason,
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub rating_change: Option<i32>,
+    pub bot_level: Option<i32>, // Set if the opponent was a server bot
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum GameOutcome {
+    Won,
+    Lost,
+    Draw,
+}
+
+impl PublicGameSummary {
+    async fn from(game: PlayedGameInfo, user_id: UserId, db: &DatabaseManager) -> Self {
+        let me = if game.player_one == Some(user_id) {
+            Player::One
+        } else {
+            Player::Two
+        };
+        let opponent_id = me.other().select(game.player_one, game.player_two);
+        PublicGameSummary {
+            id: game.id.to_string(),
+            opponent: get_public_user(opponent_id, db).await,
+            you_started: game.starting_player == me,
+            board: game.board,
+            move_count: game.moves.len(),
+            outcome: game.result.map(|result| match result {
+                GameResult::Winner(winner) if winner == me => GameOutcome::Won,
+                GameResult::Winner(_) => GameOutcome::Lost,
+                GameResult::Draw => GameOutcome::Draw,
+            }),
+            end_reason: game.end_reason,
+            started_at: game.started_at,
+            ended_at: game.ended_at,
+            rating_change: game
+                .rating_change
+                .map(|change| me.select(change.player_one, change.player_two)),
+            bot_level: game.bot_level,
+        }
+    }
+}
+
+impl PublicGame {
+    async fn from(game: PlayedGameInfo, db: &DatabaseManager) -> Self {
+        let (player_one, player_two) = futures::join!(
+            get_public_user(game.player_one, db),
+            get_public_user(game.player_two, db)
+        );
+        PublicGame {
+            id: game.id.to_string(),
+            player_one,
+            player_two,
+            starting_player: game.starting_player,
+            board: game.board,
+            moves: game.moves,
+            result: game.result,
+            end_reason: game.end_reason,
+            started_at: game.started_at,
+            ended_at: game.ended_at,
+            rating_change: game.rating_change,
+            bot_level: game.bot_level,
+        }
+    }
+}
+
+/// `None` for anonymous players and deleted accounts
+async fn get_public_user(id: Option<UserId>, db: &DatabaseManager) -> Option<PublicUserOther> {
+    match id {
+        Some(id) => db.users.get_id_public(id).await,
+        None => None,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BeforeIdQuery {
+    before_id: Option<String>,
+}
+
+pub async fn get_games_of_user(
+    db_mgr: web::Data<Arc<DatabaseManager>>,
+    web::Path(user_id): web::Path<UserId>,
+    web::Query(query): web::Query<BeforeIdQuery>,
+) -> HttpResponse {
+    let before_id = match query.before_id {
+        Some(before_id) => match GameOId::parse(&before_id) {
+            Some(before_id) => Some(before_id),
+            None => return ApiResponse::from(ApiError::InvalidGameId),
+        },
+        None => None,
+    };
+    let games = db_mgr.games.get_games_of_user(user_id, before_id).await;
+    let db = db_mgr.get_ref();
+    HttpResponse::Ok().json(
+        join_all(
+            games
+                .into_iter()
+                .map(|game| PublicGameSummary::from(game, user_id, db)),
+        )
+        .await,
+    )
+}
+
+async fn get_game(
+    db_mgr: web::Data<Arc<DatabaseManager>>,
+    web::Path(game_id): web::Path<String>,
+) -> HttpResponse {
+    let game_id = match GameOId::parse(&game_id) {
+        Some(game_id) => game_id,
+        None => return ApiResponse::from(ApiError::InvalidGameId),
+    };
+    match db_mgr.games.get(&game_id).await {
+        Some(game) => HttpResponse::Ok().json(PublicGame::from(game, db_mgr.get_ref()).await),
+        None => ApiResponse::from(ApiError::GameNotFound),
+    }
+}
@@ -0,0 +1,85 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::future::join_all;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use super::{
+    get_session_token,
+    users::user::{LeaderboardEntry, UserId},
+    ApiError, ApiResponse,
+};
+use crate::database::DatabaseManager;
+
+const LEADERBOARD_PAGE_SIZE: usize = 50;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("", web::get().to(get_leaderboard));
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    page: Option<usize>,
+}
+
+async fn get_leaderboard(
+    db_mgr: web::Data<Arc<DatabaseManager>>,
+    web::Query(query): web::Query<PageQuery>,
+) -> HttpResponse {
+    let skip = match query
+        .page
+        .unwrap_or(0)
+        .checked_mul(LEADERBOARD_PAGE_SIZE)
+        .filter(|skip| *skip <= i64::MAX as usize)
+    {
+        Some(skip) => skip,
+        None => return ApiResponse::from(ApiError::InvalidPage),
+    };
+    let users = db_mgr
+        .users
+        .get_leaderboard(skip as i64, LEADERBOARD_PAGE_SIZE as i64)
+        .await;
+    HttpResponse::Ok().json(
+        users
+            .into_iter()
+            .enumerate()
+            .map(|(i, user)| LeaderboardEntry::new(skip + i + 1, user))
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// The caller and their friends, ranked among each other
+pub async fn get_friends_leaderboard(
+    req: HttpRequest,
+    db_mgr: web::Data<Arc<DatabaseManager>>,
+) -> HttpResponse {
+    let session_token = match get_session_token(&req) {
+        Some(session_token) => session_token,
+        None => return ApiResponse::from(ApiError::MissingSessionToken),
+    };
+    let me = match db_mgr
+        .users
+        .get_session_token(session_token, &db_mgr.friendships)
+        .await
+    {
+        Some(me) => me,
+        None => return ApiResponse::from(ApiError::IncorrectCredentials),
+    };
+
+    let ids: Vec<UserId> = std::iter::once(me.id)
+        .chain(me.friendships.friends().map(|friend| friend.other_id))
+        .collect();
+    let mut users: Vec<_> = join_all(ids.into_iter().map(|id| db_mgr.users.get_id_public(id)))
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+    users.sort_by_key(|user| std::cmp::Reverse(user.game_info.skill_rating));
+
+    HttpResponse::Ok().json(
+        users
+            .into_iter()
+            .enumerate()
+            .map(|(i, user)| LeaderboardEntry::new(i + 1, user))
+            .collect::<Vec<_>>(),
+    )
+}
@@ -1,5 +1,10 @@
 pub mod chat;
 mod feedback;
+pub mod games;
+mod leaderboard;
+mod protocol;
+mod shutdown;
+mod tournaments;
 pub mod users;
 
 use actix_web::{dev::HttpResponseBuilder, web, HttpRequest, HttpResponse};
@@ -16,6 +21,11 @@ pub fn config(cfg: &mut web::ServiceConfig) {
     )
     .service(web::scope("/users").configure(users::config))
     .service(web::scope("/chat").configure(chat::config))
+    .service(web::scope("/games").configure(games::config))
+    .service(web::scope("/leaderboard").configure(leaderboard::config))
+    .service(web::scope("/tournaments").configure(tournaments::config))
+    .service(web::scope("/protocol").configure(protocol::config))
+    .service(web::scope("/shutdown").configure(shutdown::config))
     .service(web::scope("/feedback").configure(feedback::config));
 }
 
@@ -48,6 +58,22 @@ impl ApiResponse<()> {
             ApiError::AlreadyPlaying => (HR::BadRequest, "user is already playing"),
             ApiError::MissingSessionToken => (HR::Unauthorized, "missing header session_token"),
             ApiError::IncorrectCredentials => (HR::Forbidden, "the credentials are incorrect"),
+            ApiError::InvalidGameId => (HR::BadRequest, "invalid game id"),
+            ApiError::GameNotFound => (HR::NotFound, "game not found"),
+            ApiError::InvalidTournamentId => (HR::BadRequest, "invalid tournament id"),
+            ApiError::TournamentNotFound => (HR::NotFound, "tournament not found"),
+            ApiError::InvalidTournamentSettings => (HR::BadRequest, "invalid tournament settings"),
+            ApiError::TournamentStarted => (HR::BadRequest, "tournament already started"),
+            ApiError::TournamentFull => (HR::BadRequest, "tournament is full"),
+            ApiError::AlreadyJoined => (HR::BadRequest, "already joined the tournament"),
+            ApiError::NotEnoughPlayers => (HR::BadRequest, "not enough players"),
+            ApiError::NotTournamentCreator => {
+                (HR::Forbidden, "only the creator can start the tournament")
+            }
+            ApiError::NotAdmin => (HR::Forbidden, "missing or incorrect admin token"),
+            ApiError::InvalidSessionId => (HR::BadRequest, "invalid session id"),
+            ApiError::SessionNotFound => (HR::NotFound, "session not found"),
+            ApiError::InvalidPage => (HR::BadRequest, "invalid page"),
             ApiError::InternalServerError => (HR::InternalServerError, "internal server error"),
         };
         http_response().json(ApiResponse::new(prefix + description))
@@ -66,6 +92,7 @@ impl<T> ApiResponse<T> {
 
 #[allow(dead_code)]
 #[non_exhaustive]
+#[derive(Debug)]
 pub enum ApiError {
     UsernameInUse,
     EmailInUse,
@@ -74,6 +101,20 @@ pub enum ApiError {
     IncorrectCredentials,
     AlreadyPlaying,
     MissingSessionToken,
+    InvalidGameId,
+    GameNotFound,
+    InvalidTournamentId,
+    TournamentNotFound,
+    InvalidTournamentSettings,
+    TournamentStarted,
+    TournamentFull,
+    AlreadyJoined,
+    NotEnoughPlayers,
+    NotTournamentCreator,
+    NotAdmin,
+    InvalidSessionId,
+    SessionNotFound,
+    InvalidPage,
     InternalServerError,
 }
 
@@ -0,0 +1,12 @@
+use actix_web::{web, HttpResponse};
+
+use crate::game::msg_json::protocol_schema;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("", web::get().to(schema));
+}
+
+/// JSON Schema of the websocket protocol, used to generate the client's message classes
+async fn schema() -> HttpResponse {
+    HttpResponse::Ok().json(protocol_schema())
+}
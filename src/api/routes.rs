@@ -3,14 +3,6 @@ use crate::game::lobby_mgr::*;
 use actix::Addr;
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 
-// pub async fn shutdown(
-//     _req: HttpRequest,
-//     lobby_mgr: web::Data<Addr<LobbyManager>>,
-// ) -> Result<HttpResponse, Error> {
-//     lobby_mgr.do_send(LobbyManagerMsg::Shutdown);
-//     Ok("Okay".into())
-// }
-
 // pub async fn stats(
 //     _req: HttpRequest,
 //     lobby_mgr: web::Data<Addr<LobbyManager>>,
@@ -0,0 +1,53 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sha3::{Digest, Sha3_256};
+
+use super::{ApiError, ApiResponse};
+use crate::shutdown::ShutdownTrigger;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("", web::post().to(shutdown));
+}
+
+/// Drains the server like SIGTERM does. The `AdminToken` header has to match the ADMIN_TOKEN
+/// environment variable, without it the route is disabled.
+async fn shutdown(req: HttpRequest, trigger: web::Data<ShutdownTrigger>) -> HttpResponse {
+    let admin_token = std::env::var("ADMIN_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty());
+    let given_token = req
+        .headers()
+        .get("AdminToken")
+        .and_then(|token| token.to_str().ok());
+    match (admin_token, given_token) {
+        (Some(admin_token), Some(given_token)) if tokens_match(&admin_token, given_token) => {
+            trigger.trigger();
+            HttpResponse::Ok().json(ApiResponse::new("Shutting down"))
+        }
+        _ => ApiResponse::from(ApiError::NotAdmin),
+    }
+}
+
+/// Compares digests in constant time, so that the response time doesn't reveal
+/// how much of the token is right
+fn tokens_match(admin_token: &str, given_token: &str) -> bool {
+    let admin_digest = Sha3_256::digest(admin_token.as_bytes());
+    let given_digest = Sha3_256::digest(given_token.as_bytes());
+    admin_digest
+        .iter()
+        .zip(given_digest.iter())
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_tokens() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secret", "secreT"));
+        assert!(!tokens_match("secret", "secret "));
+        assert!(!tokens_match("secret", ""));
+    }
+}
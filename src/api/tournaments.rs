@@ -0,0 +1,164 @@
+use actix::Addr;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use super::{
+    get_session_token,
+    users::{user::UserId, user_mgr},
+    ApiError, ApiResponse,
+};
+use crate::{
+    game::{
+        lobby_mgr::LobbySettings,
+        tournament::{TournamentFormat, TournamentId, MAX_SWISS_ROUNDS},
+        tournament_mgr::*,
+    },
+    logging::GameOId,
+};
+
+const MAX_NAME_LEN: usize = 40;
+const DEFAULT_SWISS_ROUNDS: usize = 5;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("", web::get().to(list))
+        .route("", web::post().to(create))
+        .route("/{tournament_id}", web::get().to(get))
+        .route("/{tournament_id}/join", web::post().to(join))
+        .route("/{tournament_id}/start", web::post().to(start));
+}
+
+#[derive(Deserialize)]
+pub struct NewTournament {
+    name: String,
+    format: String,           // "single_elimination" or "swiss"
+    rounds: Option<usize>,    // Swiss only
+    settings: Option<String>, // Lobby settings for all games, e.g. ":BOARD=7X6X4:TIME=300+5"
+}
+
+impl NewTournament {
+    fn parse(&self) -> Option<(TournamentFormat, LobbySettings)> {
+        let name_len = self.name.trim().chars().count();
+        if name_len == 0 || name_len > MAX_NAME_LEN {
+            return None;
+        }
+        let format = match (self.format.as_str(), self.rounds) {
+            ("single_elimination", None) => TournamentFormat::SingleElimination,
+            ("swiss", rounds) => {
+                let rounds = rounds.unwrap_or(DEFAULT_SWISS_ROUNDS);
+                if rounds == 0 || rounds > MAX_SWISS_ROUNDS {
+                    return None;
+                }
+                TournamentFormat::Swiss { rounds }
+            }
+            _ => return None,
+        };
+        let settings = match &self.settings {
+            Some(settings) => LobbySettings::parse(&settings.to_uppercase())?,
+            None => LobbySettings::default(),
+        };
+        // Every pairing plays a single game
+        if settings.best_of.is_some() {
+            return None;
+        }
+        Some((format, settings))
+    }
+}
+
+async fn list(tournament_mgr: web::Data<Addr<TournamentManager>>) -> HttpResponse {
+    match tournament_mgr.send(ListTournaments).await {
+        Ok(tournaments) => HttpResponse::Ok().json(tournaments),
+        Err(_) => ApiResponse::from(ApiError::InternalServerError),
+    }
+}
+
+async fn create(
+    req: HttpRequest,
+    user_mgr: web::Data<Addr<user_mgr::UserManager>>,
+    tournament_mgr: web::Data<Addr<TournamentManager>>,
+    payload: web::Form<NewTournament>,
+) -> HttpResponse {
+    let creator = match get_user_id(&req, &user_mgr).await {
+        Ok(creator) => creator,
+        Err(api_err) => return ApiResponse::from(api_err),
+    };
+    let (format, settings) = match payload.parse() {
+        Some(parsed) => parsed,
+        None => return ApiResponse::from(ApiError::InvalidTournamentSettings),
+    };
+    let msg = CreateTournament {
+        creator,
+        name: payload.into_inner().name.trim().to_owned(),
+        format,
+        settings,
+    };
+    respond(tournament_mgr.send(msg).await)
+}
+
+async fn get(
+    tournament_mgr: web::Data<Addr<TournamentManager>>,
+    web::Path(tournament_id): web::Path<String>,
+) -> HttpResponse {
+    match parse_id(&tournament_id) {
+        Ok(id) => respond(tournament_mgr.send(TournamentRequest::Get(id)).await),
+        Err(api_err) => ApiResponse::from(api_err),
+    }
+}
+
+async fn join(
+    req: HttpRequest,
+    user_mgr: web::Data<Addr<user_mgr::UserManager>>,
+    tournament_mgr: web::Data<Addr<TournamentManager>>,
+    web::Path(tournament_id): web::Path<String>,
+) -> HttpResponse {
+    let (id, user_id) = match (parse_id(&tournament_id), get_user_id(&req, &user_mgr).await) {
+        (Ok(id), Ok(user_id)) => (id, user_id),
+        (Err(api_err), _) | (_, Err(api_err)) => return ApiResponse::from(api_err),
+    };
+    respond(
+        tournament_mgr
+            .send(TournamentRequest::Join(id, user_id))
+            .await,
+    )
+}
+
+async fn start(
+    req: HttpRequest,
+    user_mgr: web::Data<Addr<user_mgr::UserManager>>,
+    tournament_mgr: web::Data<Addr<TournamentManager>>,
+    web::Path(tournament_id): web::Path<String>,
+) -> HttpResponse {
+    let (id, user_id) = match (parse_id(&tournament_id), get_user_id(&req, &user_mgr).await) {
+        (Ok(id), Ok(user_id)) => (id, user_id),
+        (Err(api_err), _) | (_, Err(api_err)) => return ApiResponse::from(api_err),
+    };
+    respond(
+        tournament_mgr
+            .send(TournamentRequest::Start(id, user_id))
+            .await,
+    )
+}
+
+fn parse_id(tournament_id: &str) -> Result<TournamentId, ApiError> {
+    GameOId::parse(tournament_id).ok_or(ApiError::InvalidTournamentId)
+}
+
+/// Only registered users take part in tournaments
+async fn get_user_id(
+    req: &HttpRequest,
+    user_mgr: &Addr<user_mgr::UserManager>,
+) -> Result<UserId, ApiError> {
+    let session_token = get_session_token(req).ok_or(ApiError::MissingSessionToken)?;
+    match user_mgr.send(user_mgr::msg::GetUserMe(session_token)).await {
+        Ok(Some(user)) => Ok(user.id),
+        Ok(None) => Err(ApiError::IncorrectCredentials),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+fn respond(res: Result<Result<PublicTournament, ApiError>, actix::MailboxError>) -> HttpResponse {
+    match res {
+        Ok(Ok(tournament)) => HttpResponse::Ok().json(tournament),
+        Ok(Err(api_err)) => ApiResponse::from(api_err),
+        Err(_) => ApiResponse::from(ApiError::InternalServerError),
+    }
+}
@@ -1,3 +1,4 @@
+pub mod rating;
 pub mod session_token;
 pub mod user;
 pub mod user_mgr;
@@ -14,20 +15,46 @@ pub fn config(cfg: &mut web::ServiceConfig) {
         .service(
             web::scope("/me")
                 .route("", web::get().to(me))
-                .service(web::scope("/friends").configure(friends::config)),
+                .route("", web::delete().to(delete_me))
+                .route(
+                    "/leaderboard",
+                    web::get().to(super::leaderboard::get_friends_leaderboard),
+                )
+                .service(web::scope("/friends").configure(friends::config))
+                .service(web::scope("/sessions").configure(sessions::config)),
         )
         .route("/register", web::post().to(register))
         .route("/login", web::post().to(login))
         .route("/logout", web::post().to(logout))
-        .route("/{user_id}", web::get().to(get_user));
+        .route("/{user_id}", web::get().to(get_user))
+        .route(
+            "/{user_id}/games",
+            web::get().to(super::games::get_games_of_user),
+        );
+}
+
+/// Sessions are labelled with the user agent unless the client names its device
+fn with_device(req: &HttpRequest, mut auth: user_mgr::UserAuth) -> user_mgr::UserAuth {
+    if auth.device.is_none() {
+        auth.device = req
+            .headers()
+            .get(http::header::USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .map(|user_agent| user_agent.to_owned());
+    }
+    auth
 }
 
 async fn register(
+    req: HttpRequest,
     user_mgr: web::Data<Addr<user_mgr::UserManager>>,
     payload: web::Form<user_mgr::UserAuth>,
 ) -> HttpResponse {
     match user_mgr
-        .send(user_mgr::msg::Register(payload.into_inner()))
+        .send(user_mgr::msg::Register(with_device(
+            &req,
+            payload.into_inner(),
+        )))
         .await
     {
         Ok(Ok(session_token)) => HR::Ok().json(ApiResponse::with_content(
@@ -40,11 +67,15 @@ async fn register(
 }
 
 async fn login(
+    req: HttpRequest,
     user_mgr: web::Data<Addr<user_mgr::UserManager>>,
     payload: web::Form<user_mgr::UserAuth>,
 ) -> HttpResponse {
     match user_mgr
-        .send(user_mgr::msg::Login(payload.into_inner()))
+        .send(user_mgr::msg::Login(with_device(
+            &req,
+            payload.into_inner(),
+        )))
         .await
     {
         Ok(Ok(session_token)) => HR::Ok().json(ApiResponse::with_content(
@@ -119,6 +150,32 @@ async fn me(req: HttpRequest, user_mgr: web::Data<Addr<user_mgr::UserManager>>)
     }
 }
 
+#[derive(Deserialize)]
+struct PasswordConfirmation {
+    password: String,
+}
+
+async fn delete_me(
+    req: HttpRequest,
+    user_mgr: web::Data<Addr<user_mgr::UserManager>>,
+    payload: web::Form<PasswordConfirmation>,
+) -> HR {
+    match get_session_token(&req) {
+        Some(session_token) => match user_mgr
+            .send(user_mgr::msg::DeleteAccount {
+                session_token,
+                password: payload.into_inner().password,
+            })
+            .await
+        {
+            Ok(Ok(())) => HR::Ok().json(ApiResponse::new("Account deleted.")),
+            Ok(Err(api_err)) => ApiResponse::from(api_err),
+            Err(_) => ApiResponse::from(ApiError::InternalServerError),
+        },
+        None => ApiResponse::from(ApiError::MissingSessionToken),
+    }
+}
+
 mod friends {
     use crate::api::get_session_token;
 
@@ -173,6 +230,59 @@ mod friends {
     }
 }
 
+mod sessions {
+    use crate::api::get_session_token;
+
+    use super::*;
+    use session_token::SessionId;
+    use user_mgr::msg::*;
+
+    pub fn config(cfg: &mut web::ServiceConfig) {
+        cfg.route("", web::get().to(sessions::get))
+            .route("/{id}", web::delete().to(sessions::delete));
+    }
+
+    pub async fn get(req: HttpRequest, user_mgr: web::Data<Addr<user_mgr::UserManager>>) -> HR {
+        match get_session_token(&req) {
+            Some(session_token) => match user_mgr.send(GetSessions(session_token)).await {
+                Ok(Some(sessions)) => HR::Ok().json(sessions),
+                Ok(None) => ApiResponse::from(ApiError::IncorrectCredentials),
+                Err(_) => ApiResponse::from(ApiError::InternalServerError),
+            },
+            None => ApiResponse::from(ApiError::MissingSessionToken),
+        }
+    }
+
+    /// `id` is either the id of a session or "others" for all but the current one
+    pub async fn delete(
+        req: HttpRequest,
+        user_mgr: web::Data<Addr<user_mgr::UserManager>>,
+        id: web::Path<String>,
+    ) -> HR {
+        let target = match id.as_str() {
+            "others" => RevokeTarget::Others,
+            id => match SessionId::parse(id) {
+                Some(session_id) => RevokeTarget::Session(session_id),
+                None => return ApiResponse::from(ApiError::InvalidSessionId),
+            },
+        };
+        match get_session_token(&req) {
+            Some(session_token) => match user_mgr
+                .send(RevokeSessions {
+                    session_token,
+                    target,
+                })
+                .await
+            {
+                Ok(Ok(())) => HR::Ok().json(ApiResponse::new("Session revoked.")),
+                Ok(Err(api_err)) => ApiResponse::from(api_err),
+                Err(_) => ApiResponse::from(ApiError::InternalServerError),
+            },
+            None => ApiResponse::from(ApiError::MissingSessionToken),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct UserIdQuery {
     id: user::UserId,
@@ -0,0 +1,267 @@
+//! Glicko-2 rating system, see http://www.glicko.net/glicko/glicko2.pdf
+//! Every rated game is treated as its own rating period.
+
+use std::f64::consts::PI;
+
+use super::user::UserGameInfo;
+use crate::game::game_info::GameResult;
+
+pub const DEFAULT_RATING: i32 = 1000;
+pub const DEFAULT_RATING_DEVIATION: f64 = 350.0;
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+
+/// Keeps ratings of very active players from freezing, as there are no idle rating periods
+const MIN_RATING_DEVIATION: f64 = 50.0;
+/// Constrains the change in volatility over time
+const TAU: f64 = 0.5;
+const SCALE: f64 = 173.7178;
+const CONVERGENCE_TOLERANCE: f64 = 0.000_001;
+
+/// Number of rated games during which a new account's rating is provisional.
+/// Established players' ratings are not affected by games against provisional players.
+pub const PROVISIONAL_GAMES: i32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glicko2Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Glicko2Rating {
+    fn mu(&self) -> f64 {
+        (self.rating - DEFAULT_RATING as f64) / SCALE
+    }
+    fn phi(&self) -> f64 {
+        self.deviation / SCALE
+    }
+
+    /// Computes the new rating after playing against all `opponents` with the given scores
+    /// (1.0 win, 0.5 draw, 0.0 loss) in one rating period.
+    pub fn update(&self, opponents: &[(Glicko2Rating, f64)]) -> Glicko2Rating {
+        if opponents.is_empty() {
+            let phi = (self.phi().powi(2) + self.volatility.powi(2)).sqrt();
+            return Glicko2Rating {
+                deviation: phi * SCALE,
+                ..*self
+            };
+        }
+        let mu = self.mu();
+        let phi = self.phi();
+
+        let mut inv_v = 0.0;
+        let mut delta_sum = 0.0;
+        for (opponent, score) in opponents {
+            let g = g(opponent.phi());
+            let e = expected_score(mu, opponent.mu(), g);
+            inv_v += g * g * e * (1.0 - e);
+            delta_sum += g * (score - e);
+        }
+        let v = 1.0 / inv_v;
+        let delta = v * delta_sum;
+
+        let volatility = self.new_volatility(phi, v, delta);
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi * new_phi * delta_sum;
+
+        Glicko2Rating {
+            rating: new_mu * SCALE + DEFAULT_RATING as f64,
+            deviation: new_phi * SCALE,
+            volatility,
+        }
+    }
+
+    /// Step 5 of the paper (Illinois algorithm)
+    fn new_volatility(&self, phi: f64, v: f64, delta: f64) -> f64 {
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2))
+                - (x - a) / (TAU * TAU)
+        };
+
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let mut f_a = f(big_a);
+        let mut f_b = f(big_b);
+        while (big_b - big_a).abs() > CONVERGENCE_TOLERANCE {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+            if f_c * f_b <= 0.0 {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+            big_b = big_c;
+            f_b = f_c;
+        }
+        (big_a / 2.0).exp()
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected_score(mu: f64, opponent_mu: f64, g: f64) -> f64 {
+    1.0 / (1.0 + (-g * (mu - opponent_mu)).exp())
+}
+
+impl UserGameInfo {
+    pub fn glicko2(&self) -> Glicko2Rating {
+        Glicko2Rating {
+            rating: self.skill_rating as f64,
+            deviation: self.rating_deviation,
+            volatility: self.volatility,
+        }
+    }
+
+    pub fn is_provisional(&self) -> bool {
+        self.games_played < PROVISIONAL_GAMES
+    }
+
+    /// Applies the game to this player's rating and returns the rating change
+    fn apply(&mut self, opponent: &UserGameInfo, score: f64) -> i32 {
+        let old_rating = self.skill_rating;
+        let unaffected = opponent.is_provisional() && !self.is_provisional();
+        self.games_played += 1;
+        if score > 0.5 {
+            self.games_won += 1;
+        }
+        if unaffected {
+            return 0;
+        }
+        let new = self.glicko2().update(&[(opponent.glicko2(), score)]);
+        self.skill_rating = new.rating.round() as i32;
+        self.rating_deviation = new.deviation.max(MIN_RATING_DEVIATION);
+        self.volatility = new.volatility;
+        self.skill_rating - old_rating
+    }
+}
+
+/// Updates the game info of both players and returns their rating changes
+pub fn rate_game(one: &mut UserGameInfo, two: &mut UserGameInfo, result: GameResult) -> (i32, i32) {
+    let score_one = match result {
+        GameResult::Winner(winner) => winner.select(1.0, 0.0),
+        GameResult::Draw => 0.5,
+    };
+    // Both updates are based on the ratings before the game
+    let (before_one, before_two) = (one.clone(), two.clone());
+    (
+        one.apply(&before_two, score_one),
+        two.apply(&before_one, 1.0 - score_one),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::game_info::Player;
+
+    fn rating(rating: f64, deviation: f64) -> Glicko2Rating {
+        Glicko2Rating {
+            rating,
+            deviation,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+
+    fn established(skill_rating: i32) -> UserGameInfo {
+        UserGameInfo {
+            skill_rating,
+            rating_deviation: 100.0,
+            volatility: DEFAULT_VOLATILITY,
+            games_played: PROVISIONAL_GAMES,
+            games_won: 0,
+        }
+    }
+
+    /// Example calculation from the paper, shifted to our rating center
+    #[test]
+    fn paper_example() {
+        let offset = DEFAULT_RATING as f64 - 1500.0;
+        let player = rating(1500.0 + offset, 200.0);
+        let new = player.update(&[
+            (rating(1400.0 + offset, 30.0), 1.0),
+            (rating(1550.0 + offset, 100.0), 0.0),
+            (rating(1700.0 + offset, 300.0), 0.0),
+        ]);
+        assert!((new.rating - (1464.06 + offset)).abs() < 0.01, "{:?}", new);
+        assert!((new.deviation - 151.52).abs() < 0.01, "{:?}", new);
+        assert!((new.volatility - 0.05999).abs() < 0.00001, "{:?}", new);
+    }
+
+    #[test]
+    fn no_games_increases_deviation() {
+        let player = rating(1200.0, 100.0);
+        let new = player.update(&[]);
+        assert_eq!(new.rating, player.rating);
+        assert!(new.deviation > player.deviation);
+    }
+
+    #[test]
+    fn beating_stronger_player_is_worth_more() {
+        let mut weak_winner = established(1000);
+        let mut strong_loser = established(1400);
+        let (upset, _) = rate_game(
+            &mut weak_winner,
+            &mut strong_loser,
+            GameResult::Winner(Player::One),
+        );
+
+        let mut strong_winner = established(1400);
+        let mut weak_loser = established(1000);
+        let (expected, _) = rate_game(
+            &mut strong_winner,
+            &mut weak_loser,
+            GameResult::Winner(Player::One),
+        );
+
+        assert!(upset > 0 && expected > 0);
+        assert!(upset > expected);
+    }
+
+    #[test]
+    fn winner_gains_and_loser_loses() {
+        let mut one = established(1000);
+        let mut two = established(1000);
+        let (change_one, change_two) =
+            rate_game(&mut one, &mut two, GameResult::Winner(Player::Two));
+        assert!(change_one < 0);
+        assert!(change_two > 0);
+        assert_eq!(one.skill_rating, 1000 + change_one);
+        assert_eq!(two.skill_rating, 1000 + change_two);
+        assert_eq!(one.games_played, PROVISIONAL_GAMES + 1);
+        assert_eq!((one.games_won, two.games_won), (0, 1));
+    }
+
+    #[test]
+    fn draw_between_equals_changes_nothing() {
+        let mut one = established(1000);
+        let mut two = established(1000);
+        assert_eq!(rate_game(&mut one, &mut two, GameResult::Draw), (0, 0));
+    }
+
+    #[test]
+    fn provisional_opponent_does_not_affect_established_player() {
+        let mut veteran = established(1500);
+        let mut newcomer = UserGameInfo::new();
+        let (change_veteran, change_newcomer) =
+            rate_game(&mut veteran, &mut newcomer, GameResult::Winner(Player::Two));
+        assert_eq!(change_veteran, 0);
+        assert_eq!(veteran.skill_rating, 1500);
+        assert!(change_newcomer > 0);
+        assert_eq!(newcomer.games_played, 1);
+        assert!(newcomer.rating_deviation < DEFAULT_RATING_DEVIATION);
+    }
+}
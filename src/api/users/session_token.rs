@@ -1,9 +1,20 @@
 use std::{fmt, time::SystemTime};
 
+use mongodb::bson::oid::ObjectId;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
 
-#[derive(Deserialize, Serialize, Clone, Debug, Hash, PartialEq, Eq)]
+use crate::game::game_info::now_millis;
+
+/// Sessions that weren't used for that long have to log in again
+const SESSION_LIFETIME_MS: i64 = 60 * 24 * 60 * 60 * 1000;
+/// `last_used_at` is only updated after this, so that not every request writes to the database
+const SESSION_TOUCH_INTERVAL_MS: i64 = 60 * 60 * 1000;
+const MAX_DEVICE_LEN: usize = 100;
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, Hash, PartialEq, Eq)]
 pub struct SessionToken(String);
 
 impl SessionToken {
@@ -32,9 +43,141 @@ impl SessionToken {
         // }
         SessionToken(text.to_string())
     }
+
+    /// What is stored instead of the token. Tokens are random enough to not need a salt.
+    pub fn hash(&self) -> String {
+        Sha3_256::digest(self.0.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Millis, from the seconds appended to the token
+    fn created_at(&self) -> Option<i64> {
+        let seconds: i64 = self.0.rsplit("##").next()?.parse().ok()?;
+        Some(seconds * 1000)
+    }
 }
 impl fmt::Display for SessionToken {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str(&self.0)
     }
 }
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionId(ObjectId);
+
+impl SessionId {
+    /// Parses the hex representation used in the REST api
+    pub fn parse(text: &str) -> Option<SessionId> {
+        ObjectId::with_string(text).ok().map(SessionId)
+    }
+}
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.to_hex())
+    }
+}
+
+/// A login of a user, stored with the user. Times are millis.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub id: SessionId,
+    pub token_hash: String,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub expires_at: i64, // Moves along with `last_used_at`
+    pub device: Option<String>,
+}
+
+impl Session {
+    pub fn new(token: &SessionToken, device: Option<String>) -> Session {
+        let now = now_millis();
+        Session {
+            id: SessionId(ObjectId::new()),
+            token_hash: token.hash(),
+            created_at: now,
+            last_used_at: now,
+            expires_at: now + SESSION_LIFETIME_MS,
+            device: device
+                .map(|device| device.chars().take(MAX_DEVICE_LEN).collect())
+                .filter(|device: &String| !device.is_empty()),
+        }
+    }
+
+    /// For tokens that were stored in plaintext before sessions were introduced
+    pub fn migrate(token: &SessionToken) -> Session {
+        let mut session = Session::new(token, None);
+        session.created_at = token.created_at().unwrap_or(session.created_at);
+        session
+    }
+
+    /// Whether `last_used_at` and `expires_at` are due to be updated
+    pub fn needs_touch(&self, now: i64) -> bool {
+        now - self.last_used_at > SESSION_TOUCH_INTERVAL_MS
+    }
+
+    pub fn touch(&mut self, now: i64) {
+        self.last_used_at = now;
+        self.expires_at = now + SESSION_LIFETIME_MS;
+    }
+
+    pub fn to_public(&self, current: bool) -> PublicSession {
+        PublicSession {
+            id: self.id.to_string(),
+            created_at: self.created_at,
+            last_used_at: self.last_used_at,
+            expires_at: self.expires_at,
+            device: self.device.clone(),
+            current,
+        }
+    }
+}
+
+/// What `/api/users/me/sessions` returns
+#[derive(Clone, Debug, Serialize)]
+pub struct PublicSession {
+    pub id: String,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub expires_at: i64,
+    pub device: Option<String>,
+    pub current: bool, // The session of the request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_hash_is_stored() {
+        let token = SessionToken::new();
+        let session = Session::new(&token, Some("Pixel 4".to_owned()));
+        assert_eq!(session.token_hash, token.hash());
+        assert_eq!(session.token_hash.len(), 64);
+        assert!(!session.token_hash.contains(&token.to_string()));
+        assert_ne!(SessionToken::new().hash(), token.hash());
+    }
+
+    #[test]
+    fn migrated_sessions_keep_creation_time() {
+        let token = SessionToken::parse("abcdefghijklmnopqrstuvwxyz0123##1600000000");
+        let session = Session::migrate(&token);
+        assert_eq!(session.created_at, 1_600_000_000_000);
+        assert!(session.expires_at > now_millis());
+        let session = Session::migrate(&SessionToken::parse("no timestamp"));
+        assert_eq!(session.created_at, session.last_used_at);
+    }
+
+    #[test]
+    fn using_a_session_extends_it() {
+        let mut session = Session::new(&SessionToken::new(), Some(String::new()));
+        assert_eq!(session.device, None);
+        let now = session.last_used_at;
+        assert!(!session.needs_touch(now + 1000));
+        let later = now + SESSION_TOUCH_INTERVAL_MS + 1;
+        assert!(session.needs_touch(later));
+        session.touch(later);
+        assert_eq!(session.expires_at, later + SESSION_LIFETIME_MS);
+    }
+}
@@ -1,14 +1,18 @@
 use actix::Addr;
 use futures::future::join_all;
 use rand::{thread_rng, Rng};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{de, Deserialize, Serialize, Serializer};
 use std::fmt;
 use std::slice::Iter;
 
 
+use super::rating;
 use crate::api::chat::ChatThreadId;
 use crate::database::DatabaseManager;
 use crate::game::client_state::ClientState;
+use crate::game::game_info::{string_schema, BoardConfig, GameResult, Player};
+use crate::logging::{GameEndReason, GameOId};
 
 const USER_ID_LEN: usize = 12;
 const VALID_USER_ID_CHARS: &str = "0123456789abcdef";
@@ -105,6 +109,18 @@ impl<'de> Deserialize<'de> for UserId {
         UserId::from_str(&s).map_err(de::Error::custom)
     }
 }
+impl JsonSchema for UserId {
+    fn schema_name() -> String {
+        "UserId".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_schema(
+            &format!("^[{}]{{{}}}$", VALID_USER_ID_CHARS, USER_ID_LEN),
+            "Id of a registered user",
+        )
+    }
+}
 
 const MIN_PASSWORD_LENGTH: usize = 6;
 const MAX_PASSWORD_LENGTH: usize = 15;
@@ -123,11 +139,11 @@ pub struct BackendUserMe {
 }
 
 impl BackendUserMe {
-    pub fn new(username: String, password: String) -> BackendUserMe {
+    pub fn new(username: String, password: HashedPassword) -> BackendUserMe {
         BackendUserMe {
             id: UserId::new(),
             username,
-            password: HashedPassword::new(password),
+            password,
             email: None,
             game_info: UserGameInfo::new(),
             playing: None,
@@ -214,24 +230,60 @@ impl BackendFriendshipsMe {
 pub use pw::*;
 
 pub mod pw {
+    use argon2::{
+        password_hash::{PasswordHash, SaltString},
+        Argon2, PasswordHasher, PasswordVerifier,
+    };
+    use rand::{thread_rng, Rng};
     use serde::{de, Deserialize, Serialize, Serializer};
     use sha3::{Digest, Keccak256};
     use std::fmt;
 
+    /// Salted Argon2id hash in PHC string format. Accounts created before it was introduced
+    /// still have an unsalted Keccak256 digest, which is replaced on the next login.
     #[derive(Debug, Clone, PartialEq)]
-    pub struct HashedPassword(Vec<u8>);
+    pub enum HashedPassword {
+        Argon2(String),
+        Keccak(Vec<u8>),
+    }
     impl HashedPassword {
-        pub fn new(password: String) -> HashedPassword {
-            HashedPassword(Self::hash(&password))
+        /// Takes a while on purpose, so better not call it on an arbiter thread
+        pub fn new(password: &str) -> HashedPassword {
+            let salt = SaltString::encode_b64(&thread_rng().gen::<[u8; 16]>())
+                .expect("16 bytes are a valid salt");
+            let hash = Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .expect("Default argon2 parameters are valid");
+            HashedPassword::Argon2(hash.to_string())
         }
-        fn hash(string: &str) -> Vec<u8> {
+        fn keccak(string: &str) -> Vec<u8> {
             Keccak256::digest(string.as_bytes()).into_iter().collect()
         }
         pub fn matches(&self, password: &str) -> bool {
-            self.0 == Self::hash(password)
+            match self {
+                HashedPassword::Argon2(phc) => match PasswordHash::new(phc) {
+                    Ok(hash) => Argon2::default()
+                        .verify_password(password.as_bytes(), &hash)
+                        .is_ok(),
+                    Err(_) => false,
+                },
+                HashedPassword::Keccak(digest) => *digest == Self::keccak(password),
+            }
+        }
+        /// Should be replaced with a new hash once the password is known
+        pub fn is_legacy(&self) -> bool {
+            matches!(self, HashedPassword::Keccak(_))
         }
 
         fn from_str(string: &str) -> Result<HashedPassword, &str> {
+            if string.starts_with('$') {
+                return match PasswordHash::new(string) {
+                    Ok(hash) if hash.hash.is_some() => {
+                        Ok(HashedPassword::Argon2(string.to_owned()))
+                    }
+                    _ => Err("Invalid PHC string"),
+                };
+            }
             let mut vec = Vec::new();
             for i in (0..string.len()).step_by(2) {
                 if let Ok(b) = u8::from_str_radix(&string[i..i + 2], 16) {
@@ -240,16 +292,21 @@ pub mod pw {
                     return Err("Invalid hex byte");
                 }
             }
-            Ok(HashedPassword(vec))
+            Ok(HashedPassword::Keccak(vec))
         }
     }
 
     impl fmt::Display for HashedPassword {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            for h in self.0.iter() {
-                write!(f, "{:02x}", h)?;
+            match self {
+                HashedPassword::Argon2(phc) => write!(f, "{}", phc),
+                HashedPassword::Keccak(digest) => {
+                    for h in digest.iter() {
+                        write!(f, "{:02x}", h)?;
+                    }
+                    fmt::Result::Ok(())
+                }
             }
-            fmt::Result::Ok(())
         }
     }
 
@@ -270,31 +327,166 @@ pub mod pw {
             HashedPassword::from_str(&s).map_err(de::Error::custom)
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn stored(password: &HashedPassword) -> HashedPassword {
+            serde_json::from_str(&serde_json::to_string(password).unwrap()).unwrap()
+        }
+
+        #[test]
+        fn argon2_hashes_match() {
+            let password = HashedPassword::new("hunter2!");
+            assert!(password.to_string().starts_with("$argon2id$"));
+            assert!(!password.is_legacy());
+            let password = stored(&password);
+            assert!(password.matches("hunter2!"));
+            assert!(!password.matches("hunter3!"));
+        }
+
+        #[test]
+        fn hashes_are_salted() {
+            assert_ne!(
+                HashedPassword::new("hunter2!"),
+                HashedPassword::new("hunter2!")
+            );
+        }
+
+        #[test]
+        fn legacy_hashes_still_match() {
+            // As stored before argon2 was introduced
+            let legacy = HashedPassword::Keccak(HashedPassword::keccak("hunter2!")).to_string();
+            assert_eq!(legacy.len(), 64);
+            let password = HashedPassword::from_str(&legacy).unwrap();
+            assert!(password.is_legacy());
+            assert!(password.matches("hunter2!"));
+            assert!(!password.matches("hunter3!"));
+            assert_eq!(stored(&password), password);
+        }
+
+        #[test]
+        fn damaged_hashes_are_rejected() {
+            assert!(HashedPassword::from_str("$argon2id$nonsense").is_err());
+            assert!(HashedPassword::from_str("zz").is_err());
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserGameInfo {
     pub skill_rating: i32,
+    #[serde(default = "default_rating_deviation")]
+    pub rating_deviation: f64,
+    #[serde(default = "default_volatility")]
+    pub volatility: f64,
+    /// Number of rated games
+    #[serde(default)]
+    pub games_played: i32,
+    #[serde(default)]
+    pub games_won: i32,
     // pub rank: u32,
 }
 impl UserGameInfo {
-    fn new() -> UserGameInfo {
-        UserGameInfo { skill_rating: 1000 }
+    pub fn new() -> UserGameInfo {
+        UserGameInfo {
+            skill_rating: rating::DEFAULT_RATING,
+            rating_deviation: rating::DEFAULT_RATING_DEVIATION,
+            volatility: rating::DEFAULT_VOLATILITY,
+            games_played: 0,
+            games_won: 0,
+        }
     }
 }
+fn default_rating_deviation() -> f64 {
+    rating::DEFAULT_RATING_DEVIATION
+}
+fn default_volatility() -> f64 {
+    rating::DEFAULT_VOLATILITY
+}
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub user: PublicUserOther,
+    /// Share of rated games won, between 0 and 1
+    pub win_rate: f64,
+}
+impl LeaderboardEntry {
+    pub fn new(rank: usize, user: PublicUserOther) -> LeaderboardEntry {
+        let game_info = &user.game_info;
+        let win_rate = if game_info.games_played > 0 {
+            game_info.games_won as f64 / game_info.games_played as f64
+        } else {
+            0.0
+        };
+        LeaderboardEntry {
+            rank,
+            user,
+            win_rate,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PlayedGameInfo {
-    pub winner: UserId,
-    pub loser: UserId,
-    // pub one_won: bool,
+    #[serde(rename = "_id")]
+    pub id: GameOId,
+    /// Lobby host, `None` if not logged in
+    pub player_one: Option<UserId>,
+    pub player_two: Option<UserId>,
+    pub starting_player: Player,
+    pub board: BoardConfig,
+    pub moves: Vec<PlayedMove>,
+    /// `None` if the game was aborted before it was decided
+    pub result: Option<GameResult>,
+    pub end_reason: GameEndReason,
+    pub started_at: i64, // (millis since epoch)
+    pub ended_at: i64,
+    /// Set by the UserManager if the game was rated
+    #[serde(default)]
+    pub rating_change: Option<RatingChange>,
+    /// Difficulty of the server bot if player two was one. These games are never rated.
+    #[serde(default)]
+    pub bot_level: Option<i32>,
+    /// Set if the game was part of a best-of-N series
+    #[serde(default)]
+    pub series_id: Option<GameOId>,
+    /// Set if the game was part of a tournament
+    #[serde(default)]
+    pub tournament_id: Option<GameOId>,
     // kind: GameKind,
 }
-impl PlayedGameInfo {
-    pub fn new(winner: UserId, loser: UserId) -> PlayedGameInfo {
-        PlayedGameInfo { winner, loser }
-    }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayedSeriesInfo {
+    #[serde(rename = "_id")]
+    pub id: GameOId,
+    /// Lobby host, `None` if not logged in
+    pub player_one: Option<UserId>,
+    pub player_two: Option<UserId>,
+    pub best_of: i32,
+    pub wins_one: i32,
+    pub wins_two: i32,
+    pub draws: i32,
+    /// `None` if the series was drawn or abandoned before it was decided
+    pub winner: Option<Player>,
+    pub games: Vec<GameOId>,
+    pub ended_at: i64,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RatingChange {
+    pub player_one: i32,
+    pub player_two: i32,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayedMove {
+    pub column: i32,
+    pub timestamp: i64, // (millis since epoch)
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum GameKind {
     Ranked,
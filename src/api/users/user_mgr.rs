@@ -1,24 +1,32 @@
-use crate::api::{chat::ChatThreadId, users::user::*, ApiError};
+use crate::api::{
+    chat::ChatThreadId,
+    users::{rating, user::*},
+    ApiError,
+};
 use crate::database::DatabaseManager;
 use crate::game::client_adapter::ClientAdapterMsg;
 use crate::game::lobby_mgr::{self, LobbyManager};
 use crate::game::msg::*;
 
 use actix::prelude::*;
+use actix_web::web;
+use futures::channel::oneshot;
 use serde::Deserialize;
 use std::sync::Arc;
 
-const SR_PER_WIN: i32 = 25;
-
 pub struct UserManager {
     db: Arc<DatabaseManager>,
     lobby_mgr_state: BacklinkState,
+    storing_games: usize, // Played games and series which are not in the database yet
+    stored_waiters: Vec<oneshot::Sender<()>>, // Notified once `storing_games` drops to 0
 }
 impl UserManager {
     pub fn new(db: Arc<DatabaseManager>) -> UserManager {
         UserManager {
             db,
             lobby_mgr_state: BacklinkState::Waiting,
+            storing_games: 0,
+            stored_waiters: Vec::new(),
         }
     }
 }
@@ -37,6 +45,8 @@ impl Actor for UserManager {
 pub struct UserAuth {
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub device: Option<String>, // Label of the session, the user agent if not given
 }
 
 pub mod msg {
@@ -47,8 +57,11 @@ pub mod msg {
 
     use super::*;
     use crate::{
-        api::users::session_token::SessionToken,
-        game::{client_state::ClientState, msg::SrvMsgError},
+        api::users::session_token::{PublicSession, SessionId, SessionToken},
+        game::{
+            client_state::{ClientState, ClientStateMessage},
+            msg::SrvMsgError,
+        },
     };
 
     pub struct Register(pub UserAuth);
@@ -75,8 +88,15 @@ pub mod msg {
                     } else if username_is_in_use {
                         Err(ApiError::UsernameInUse)
                     } else {
-                        let mut user =
-                            BackendUserMe::new(auth.username.clone(), auth.password.clone());
+                        let password = auth.password.clone();
+                        let hashed_password =
+                            match web::block(move || Ok::<_, ()>(HashedPassword::new(&password)))
+                                .await
+                            {
+                                Ok(hashed_password) => hashed_password,
+                                Err(_) => return Err(ApiError::InternalServerError),
+                            };
+                        let mut user = BackendUserMe::new(auth.username.clone(), hashed_password);
                         while db.users.get_id(&user.id, &db.friendships).await.is_some() {
                             user.gen_new_id();
                         }
@@ -125,10 +145,112 @@ pub mod msg {
             let db = self.db.clone();
             Box::pin(
                 async move {
-                    db.users
-                        .remove_session_token(msg.0)
+                    match db.users.find_session(&msg.0).await {
+                        Some((user_id, session)) => {
+                            if revoke_sessions(&db, &user_id, vec![session.id]).await {
+                                Ok(())
+                            } else {
+                                Err(ApiError::InternalServerError)
+                            }
+                        }
+                        None => Ok(()), // Already logged out or expired
+                    }
+                }
+                .into_actor(self),
+            )
+        }
+    }
+
+    /// Removes the sessions, the client logged in with one of them is logged out
+    async fn revoke_sessions(
+        db: &DatabaseManager,
+        user_id: &UserId,
+        session_ids: Vec<SessionId>,
+    ) -> bool {
+        if session_ids.is_empty() {
+            return true;
+        }
+        if !db.users.remove_sessions(user_id, &session_ids).await {
+            return false;
+        }
+        if let Some(addr) = db.users.playing_addr(user_id) {
+            addr.do_send(ClientStateMessage::SessionsRevoked(session_ids));
+        }
+        true
+    }
+
+    /// The sessions of the user logged in with the token
+    pub struct GetSessions(pub SessionToken);
+    impl Message for GetSessions {
+        type Result = Option<Vec<PublicSession>>;
+    }
+    impl Handler<GetSessions> for UserManager {
+        type Result = ResponseActFuture<Self, Option<Vec<PublicSession>>>;
+
+        fn handle(&mut self, msg: GetSessions, _ctx: &mut Self::Context) -> Self::Result {
+            let db = self.db.clone();
+            Box::pin(
+                async move {
+                    let (user_id, current) = db.users.find_session(&msg.0).await?;
+                    let sessions = db.users.get_sessions(&user_id).await?;
+                    Some(
+                        sessions
+                            .iter()
+                            .map(|session| session.to_public(session.id == current.id))
+                            .collect(),
+                    )
+                }
+                .into_actor(self),
+            )
+        }
+    }
+
+    pub enum RevokeTarget {
+        Session(SessionId),
+        Others, // All sessions but the one of the request
+    }
+    pub struct RevokeSessions {
+        pub session_token: SessionToken,
+        pub target: RevokeTarget,
+    }
+    impl Message for RevokeSessions {
+        type Result = Result<(), ApiError>;
+    }
+    impl Handler<RevokeSessions> for UserManager {
+        type Result = ResponseActFuture<Self, Result<(), ApiError>>;
+
+        fn handle(&mut self, msg: RevokeSessions, _ctx: &mut Self::Context) -> Self::Result {
+            let db = self.db.clone();
+            Box::pin(
+                async move {
+                    let (user_id, current) = db
+                        .users
+                        .find_session(&msg.session_token)
+                        .await
+                        .ok_or(ApiError::IncorrectCredentials)?;
+                    let sessions = db
+                        .users
+                        .get_sessions(&user_id)
                         .await
-                        .map_err(|_| ApiError::InternalServerError)
+                        .ok_or(ApiError::InternalServerError)?;
+                    let session_ids: Vec<SessionId> = match msg.target {
+                        RevokeTarget::Session(session_id) => {
+                            if !sessions.iter().any(|session| session.id == session_id) {
+                                return Err(ApiError::SessionNotFound);
+                            }
+                            vec![session_id]
+                        }
+                        RevokeTarget::Others => sessions
+                            .into_iter()
+                            .map(|session| session.id)
+                            .filter(|session_id| *session_id != current.id)
+                            .collect(),
+                    };
+                    if revoke_sessions(&db, &user_id, session_ids).await {
+                        Ok(())
+                    } else {
+                        Err(ApiError::InternalServerError)
+                    }
                 }
                 .into_actor(self),
             )
@@ -140,22 +262,25 @@ pub mod msg {
         pub addr: Addr<ClientState>,
     }
     impl Message for StartPlaying {
-        type Result = Result<PublicUserMe, ()>;
+        type Result = Result<(PublicUserMe, SessionId), ()>;
     }
     struct StartPlayingIntermediate {
-        result: Result<PublicUserMe, ()>,
+        result: Result<(PublicUserMe, SessionId), ()>,
         client_adapter_addr_to_close: Option<Addr<ClientState>>,
     }
     impl Handler<StartPlaying> for UserManager {
-        type Result = ResponseActFuture<Self, Result<PublicUserMe, ()>>;
+        type Result = ResponseActFuture<Self, Result<(PublicUserMe, SessionId), ()>>;
         fn handle(&mut self, msg: StartPlaying, _ctx: &mut Self::Context) -> Self::Result {
             let db = self.db.clone();
             Box::pin(
                 async move {
-                    if let Some(user) = db
-                        .users
-                        .get_session_token(msg.session_token, &db.friendships)
-                        .await
+                    let maybe_session = db.users.find_session(&msg.session_token).await;
+                    let maybe_user: OptionFuture<_> = maybe_session
+                        .as_ref()
+                        .map(|(user_id, _)| db.users.get_id(user_id, &db.friendships))
+                        .into();
+                    if let (Some(user), Some((_, session))) =
+                        (maybe_user.await.flatten(), maybe_session)
                     {
                         let client_adapter_addr_to_close = if let Some(addr) = user.playing.clone()
                         {
@@ -173,7 +298,7 @@ pub mod msg {
                         user.playing = Some(msg.addr);
                         db.users.update(user.clone()).await;
                         StartPlayingIntermediate {
-                            result: Ok(user.to_public_user_me(&db).await),
+                            result: Ok((user.to_public_user_me(&db).await, session.id)),
                             client_adapter_addr_to_close,
                         }
                     } else {
@@ -196,6 +321,123 @@ pub mod msg {
         }
     }
 
+    /// Removes the account of the user logged in with the token after checking the password again.
+    /// Games and chat messages are kept for the other players, but no longer name the user.
+    pub struct DeleteAccount {
+        pub session_token: SessionToken,
+        pub password: String,
+    }
+    impl Message for DeleteAccount {
+        type Result = Result<(), ApiError>;
+    }
+    struct DeleteAccountIntermediate {
+        result: Result<(), ApiError>,
+        client_adapter_addr_to_close: Option<Addr<ClientState>>,
+    }
+    impl Handler<DeleteAccount> for UserManager {
+        type Result = ResponseActFuture<Self, Result<(), ApiError>>;
+        fn handle(&mut self, msg: DeleteAccount, ctx: &mut Self::Context) -> Self::Result {
+            let db = self.db.clone();
+            let user_mgr = ctx.address();
+            Box::pin(
+                async move {
+                    let result = async {
+                        let (user_id, _) = db
+                            .users
+                            .find_session(&msg.session_token)
+                            .await
+                            .ok_or(ApiError::IncorrectCredentials)?;
+                        let user = db
+                            .users
+                            .get_id(&user_id, &db.friendships)
+                            .await
+                            .ok_or(ApiError::IncorrectCredentials)?;
+                        let auth = UserAuth {
+                            username: user.username.clone(),
+                            password: msg.password,
+                            device: None,
+                        };
+                        if db.users.get_auth(auth, &db.friendships).await.is_none() {
+                            return Err(ApiError::IncorrectCredentials);
+                        }
+                        let sessions = db.users.get_sessions(&user.id).await.unwrap_or_default();
+                        let maybe_addr = db.users.playing_addr(&user.id);
+                        if !db.users.delete(&user.id).await {
+                            return Err(ApiError::InternalServerError);
+                        }
+                        // Games stored from now on leave the user out, older ones are
+                        // anonymised below
+                        let _ = user_mgr.send(WaitForStoredGames).await;
+                        if !db.friendships.remove_all_for(&user.id).await
+                            || !db.chat_msgs.anonymise(&user.id).await
+                            || !db.games.anonymise(&user.id).await
+                            || !db.series.anonymise(&user.id).await
+                        {
+                            println!("Failed to remove all data of deleted user {}", user.id);
+                        }
+                        if let Some(addr) = &maybe_addr {
+                            let session_ids = sessions.into_iter().map(|session| session.id);
+                            addr.do_send(ClientStateMessage::SessionsRevoked(
+                                session_ids.collect(),
+                            ));
+                        }
+                        Ok(maybe_addr)
+                    }
+                    .await;
+                    match result {
+                        Ok(maybe_addr) => DeleteAccountIntermediate {
+                            result: Ok(()),
+                            client_adapter_addr_to_close: maybe_addr,
+                        },
+                        Err(err) => DeleteAccountIntermediate {
+                            result: Err(err),
+                            client_adapter_addr_to_close: None,
+                        },
+                    }
+                }
+                .into_actor(self)
+                .map(|res, _act, ctx| {
+                    if let Some(addr) = res.client_adapter_addr_to_close {
+                        ctx.run_later(Duration::from_millis(100), move |_, _| {
+                            addr.do_send(ClientAdapterMsg::Close);
+                        });
+                    }
+                    res.result
+                }),
+            )
+        }
+    }
+
+    /// Logs a session restored after a restart back in, other clients of the user are kept
+    pub struct ResumePlaying {
+        pub user_id: UserId,
+        pub addr: Addr<ClientState>,
+    }
+    impl Message for ResumePlaying {
+        type Result = Result<PublicUserMe, ()>;
+    }
+    impl Handler<ResumePlaying> for UserManager {
+        type Result = ResponseActFuture<Self, Result<PublicUserMe, ()>>;
+        fn handle(&mut self, msg: ResumePlaying, _ctx: &mut Self::Context) -> Self::Result {
+            let db = self.db.clone();
+            Box::pin(
+                async move {
+                    let mut user = db
+                        .users
+                        .get_id(&msg.user_id, &db.friendships)
+                        .await
+                        .ok_or(())?;
+                    if user.playing.is_none() {
+                        user.playing = Some(msg.addr);
+                        db.users.update(user.clone()).await;
+                    }
+                    Ok(user.to_public_user_me(&db).await)
+                }
+                .into_actor(self),
+            )
+        }
+    }
+
     pub enum IntUserMgrMsg {
         Backlink(Addr<LobbyManager>),
         Game(GameMsg),
@@ -204,6 +446,7 @@ pub mod msg {
     }
     pub enum GameMsg {
         PlayedGame(PlayedGameInfo),
+        PlayedSeries(PlayedSeriesInfo),
     }
 
     impl Message for IntUserMgrMsg {
@@ -213,6 +456,10 @@ pub mod msg {
         type Result = ResponseActFuture<Self, ()>;
         fn handle(&mut self, msg: IntUserMgrMsg, _ctx: &mut Self::Context) -> Self::Result {
             let db = self.db.clone();
+            let is_game = matches!(msg, IntUserMgrMsg::Game(_));
+            if is_game {
+                self.storing_games += 1;
+            }
             Box::pin(
                 async move {
                     use GameMsg::*;
@@ -223,31 +470,61 @@ pub mod msg {
                             lobby_mgr_state = Some(BacklinkState::Linked(lobby_mgr))
                         }
                         Game(game_msg) => match game_msg {
-                            PlayedGame(game_info) => {
-                                let mut found = false;
-                                if let Some(mut winner) =
-                                    db.users.get_id(&game_info.winner, &db.friendships).await
-                                {
-                                    winner.game_info.skill_rating += SR_PER_WIN;
-                                    db.users.update(winner).await;
-                                    found = true;
-                                }
-                                if let Some(mut loser) =
-                                    db.users.get_id(&game_info.loser, &db.friendships).await
-                                {
-                                    if found {
-                                        loser.game_info.skill_rating -= SR_PER_WIN;
-                                        db.users.update(loser).await;
-                                        db.games.insert(game_info).await;
-                                    }
-                                } else if found {
-                                    if let Some(mut winner) =
-                                        db.users.get_id(&game_info.winner, &db.friendships).await
-                                    {
-                                        winner.game_info.skill_rating -= SR_PER_WIN;
-                                        db.users.update(winner).await;
+                            PlayedGame(mut game_info) => {
+                                game_info.player_one =
+                                    existing_user(&db, game_info.player_one).await;
+                                game_info.player_two =
+                                    existing_user(&db, game_info.player_two).await;
+                                // Aborted games and games against bots don't affect the rating
+                                // but are still recorded
+                                if let (Some(result), Some(player_one), Some(player_two)) = (
+                                    game_info.result,
+                                    game_info.player_one,
+                                    game_info
+                                        .player_two
+                                        .filter(|_| game_info.bot_level.is_none()),
+                                ) {
+                                    let one = db.users.get_id(&player_one, &db.friendships).await;
+                                    let two = db.users.get_id(&player_two, &db.friendships).await;
+                                    if let (Some(mut one), Some(mut two)) = (one, two) {
+                                        let (change_one, change_two) = rating::rate_game(
+                                            &mut one.game_info,
+                                            &mut two.game_info,
+                                            result,
+                                        );
+                                        if db
+                                            .users
+                                            .update_game_infos(&[
+                                                (one.id, one.game_info.clone()),
+                                                (two.id, two.game_info.clone()),
+                                            ])
+                                            .await
+                                        {
+                                            game_info.rating_change = Some(RatingChange {
+                                                player_one: change_one,
+                                                player_two: change_two,
+                                            });
+                                            for (user, change) in
+                                                [(one, change_one), (two, change_two)].iter()
+                                            {
+                                                if let Some(addr) = &user.playing {
+                                                    addr.do_send(ServerMessage::RatingChange(
+                                                        *change,
+                                                        user.game_info.skill_rating,
+                                                    ));
+                                                }
+                                            }
+                                        }
                                     }
                                 }
+                                db.games.insert(game_info).await;
+                            }
+                            PlayedSeries(mut series_info) => {
+                                series_info.player_one =
+                                    existing_user(&db, series_info.player_one).await;
+                                series_info.player_two =
+                                    existing_user(&db, series_info.player_two).await;
+                                db.series.insert(series_info).await;
                             }
                         },
                         // StartPlaying(id) => {
@@ -270,15 +547,57 @@ pub mod msg {
                     lobby_mgr_state
                 }
                 .into_actor(self)
-                .map(|maybe_lobby_mgr_state, act, _| {
+                .map(move |maybe_lobby_mgr_state, act, _| {
                     if let Some(state) = maybe_lobby_mgr_state {
                         act.lobby_mgr_state = state;
                     }
+                    if is_game {
+                        act.storing_games -= 1;
+                        if act.storing_games == 0 {
+                            for waiter in act.stored_waiters.drain(..) {
+                                let _ = waiter.send(());
+                            }
+                        }
+                    }
                 }),
             )
         }
     }
 
+    /// `None` if the user deleted their account while the game was running
+    async fn existing_user(db: &DatabaseManager, maybe_id: Option<UserId>) -> Option<UserId> {
+        match maybe_id {
+            Some(id) if db.users.exists(&id).await => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Resolves once all games and series received so far are stored
+    pub struct WaitForStoredGames;
+    impl Message for WaitForStoredGames {
+        type Result = ();
+    }
+    impl Handler<WaitForStoredGames> for UserManager {
+        type Result = ResponseActFuture<Self, ()>;
+        fn handle(&mut self, _: WaitForStoredGames, _ctx: &mut Self::Context) -> Self::Result {
+            let maybe_stored = if self.storing_games > 0 {
+                let (sender, receiver) = oneshot::channel();
+                self.stored_waiters.push(sender);
+                Some(receiver)
+            } else {
+                None
+            };
+            Box::pin(
+                async move {
+                    if let Some(stored) = maybe_stored {
+                        let _ = stored.await;
+                    }
+                }
+                .into_actor(self),
+            )
+        }
+    }
+
     pub struct SearchUsers {
         pub query: String,
     }
@@ -332,6 +651,45 @@ pub mod msg {
         }
     }
 
+    /// Ids of the user's friends, empty if the user doesn't exist
+    pub struct GetFriendIds(pub UserId);
+    impl Message for GetFriendIds {
+        type Result = Vec<UserId>;
+    }
+
+    impl Handler<GetFriendIds> for UserManager {
+        type Result = ResponseActFuture<Self, Vec<UserId>>;
+        fn handle(&mut self, msg: GetFriendIds, _ctx: &mut Self::Context) -> Self::Result {
+            let db = self.db.clone();
+            Box::pin(
+                async move {
+                    match db.users.get_id(&msg.0, &db.friendships).await {
+                        Some(user) => user
+                            .friendships
+                            .friends()
+                            .map(|friendship| friendship.other_id)
+                            .collect(),
+                        None => Vec::new(),
+                    }
+                }
+                .into_actor(self),
+            )
+        }
+    }
+
+    /// The `ClientState` of a user who is currently connected
+    pub struct GetPlayingAddr(pub UserId);
+    impl Message for GetPlayingAddr {
+        type Result = Option<Addr<ClientState>>;
+    }
+
+    impl Handler<GetPlayingAddr> for UserManager {
+        type Result = Option<Addr<ClientState>>;
+        fn handle(&mut self, msg: GetPlayingAddr, _ctx: &mut Self::Context) -> Self::Result {
+            self.db.users.playing_addr(&msg.0)
+        }
+    }
+
     pub struct UserAction {
         pub action: Action,
         pub session_token: SessionToken,
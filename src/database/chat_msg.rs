@@ -68,6 +68,18 @@ impl ChatMsgCollection {
             .unwrap_or(Vec::new())
     }
 
+    /// The messages stay in the thread so that the other user can still read them
+    pub async fn anonymise(&self, user_id: &UserId) -> bool {
+        self.collection
+            .update_many(
+                doc! { "from": user_id.to_string() },
+                doc! { "$set": { "from": Bson::Null } },
+                None,
+            )
+            .await
+            .is_ok()
+    }
+
     pub async fn add(
         &self,
         thread_id: String,
@@ -166,6 +166,17 @@ impl FriendshipCollection {
             .is_ok()
     }
 
+    /// Friends and friend requests in both directions
+    pub async fn remove_all_for(&self, user_id: &UserId) -> bool {
+        self.collection
+            .delete_many(
+                doc! {"$or": [{"from_id": user_id.to_string()}, {"to_id": user_id.to_string()}]},
+                None,
+            )
+            .await
+            .is_ok()
+    }
+
     pub async fn remove(&self, from_id: UserId, to_id: UserId) -> bool {
         self.collection
             .delete_one(
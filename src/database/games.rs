@@ -1,6 +1,12 @@
-use mongodb::{Collection, Database};
+use futures::future::OptionFuture;
+use mongodb::{bson::*, options::FindOptions, Collection, Database};
+use tokio::stream::StreamExt;
 
-use crate::api::users::user::PlayedGameInfo;
+use crate::api::users::user::{PlayedGameInfo, UserId};
+use crate::game::game_info::{BoardConfig, GameResult, Player};
+use crate::logging::{GameEndReason, GameOId};
+
+const GAMES_PAGE_SIZE: i64 = 25;
 
 pub struct GameCollection {
     collection: Collection<PlayedGameInfo>,
@@ -13,7 +19,137 @@ impl GameCollection {
         }
     }
 
+    /// Games used to be stored as `{winner, loser, draw}` only. They are converted to games
+    /// on the default board without moves, the time comes from the object id.
+    pub async fn migrate_legacy_games(db: &Database) -> bool {
+        let collection = db.collection("games");
+        let legacy = doc! { "winner": { "$exists": true }, "player_one": { "$exists": false } };
+        let results = (
+            to_bson(&Some(GameResult::Draw)),
+            to_bson(&Some(GameResult::Winner(Player::One))),
+        );
+        let (defaults, draw, win) = match (legacy_game_defaults(), results) {
+            (Some(defaults), (Ok(draw), Ok(win))) => (defaults, draw, win),
+            _ => return false,
+        };
+        let created_at = doc! { "$toLong": { "$toDate": "$_id" } };
+        let update = vec![
+            doc! { "$set": defaults },
+            doc! { "$set": {
+                "player_one": "$winner",
+                "player_two": "$loser",
+                "result": { "$cond": [
+                    { "$eq": ["$draw", true] },
+                    { "$literal": draw },
+                    { "$literal": win },
+                ] },
+                "started_at": created_at.clone(),
+                "ended_at": created_at,
+            } },
+            doc! { "$unset": ["winner", "loser", "draw"] },
+        ];
+        collection.update_many(legacy, update, None).await.is_ok()
+    }
+
     pub async fn insert(&self, game: PlayedGameInfo) -> bool {
         self.collection.insert_one(game, None).await.is_ok()
     }
+
+    /// The user is stored like a player who wasn't logged in, the games remain for the opponent
+    pub async fn anonymise(&self, user_id: &UserId) -> bool {
+        for player in ["player_one", "player_two"].iter() {
+            let res = self
+                .collection
+                .update_many(
+                    doc! { *player: user_id.to_string() },
+                    doc! { "$set": { *player: Bson::Null } },
+                    None,
+                )
+                .await;
+            if res.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub async fn get(&self, id: &GameOId) -> Option<PlayedGameInfo> {
+        self.collection
+            .find_one(doc! {"_id": id.object_id()}, None)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Newest games first. Object ids grow over time, so the id of the last game
+    /// of a page can be used as `before_id` to get the next one.
+    pub async fn get_games_of_user(
+        &self,
+        user_id: UserId,
+        maybe_before_id: Option<GameOId>,
+    ) -> Vec<PlayedGameInfo> {
+        let participant = doc! {"$or": [
+            {"player_one": user_id.to_string()},
+            {"player_two": user_id.to_string()},
+        ]};
+        let doc = if let Some(before_id) = maybe_before_id {
+            doc! {"$and": [participant, {"_id": {"$lt": before_id.object_id()}}]}
+        } else {
+            participant
+        };
+        let mut options = FindOptions::default();
+        options.limit = Some(GAMES_PAGE_SIZE);
+        let minus_one: i32 = -1;
+        options.sort = Some(doc! { "_id": minus_one });
+        let games: OptionFuture<_> = self
+            .collection
+            .find(doc, Some(options))
+            .await
+            .map(|cursor| cursor.collect::<Result<Vec<_>, _>>())
+            .ok()
+            .into();
+        games.await.and_then(|r| r.ok()).unwrap_or_default()
+    }
+}
+
+/// What legacy games didn't record, player one is the winner of the game
+fn legacy_game_defaults() -> Option<Document> {
+    let literal = |value: Bson| doc! { "$literal": value };
+    Some(doc! {
+        "starting_player": literal(to_bson(&Player::One).ok()?),
+        "board": literal(to_bson(&BoardConfig::default()).ok()?),
+        "moves": literal(Bson::Array(Vec::new())),
+        "end_reason": literal(to_bson(&GameEndReason::Regular).ok()?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrated_legacy_games_can_be_read() {
+        // What the update pipeline computes for a legacy game
+        let mut game: Document = legacy_game_defaults()
+            .unwrap()
+            .into_iter()
+            .map(|(key, value)| match value {
+                Bson::Document(mut literal) => (key, literal.remove("$literal").unwrap()),
+                value => (key, value),
+            })
+            .collect();
+        let winner = UserId::new();
+        let created_at = crate::game::game_info::now_millis();
+        game.insert("_id", oid::ObjectId::new());
+        game.insert("player_one", winner.to_string());
+        game.insert("player_two", UserId::new().to_string());
+        game.insert("result", to_bson(&Some(GameResult::Draw)).unwrap());
+        game.insert("started_at", created_at);
+        game.insert("ended_at", created_at);
+        let game: PlayedGameInfo = from_document(game).unwrap();
+        assert_eq!(game.player_one, Some(winner));
+        assert_eq!(game.board, BoardConfig::default());
+        assert!(game.moves.is_empty());
+        assert_eq!(game.result, Some(GameResult::Draw));
+    }
 }
@@ -1,13 +1,15 @@
 pub mod chat_msg;
 pub mod friendships;
 pub mod games;
+pub mod series;
+pub mod snapshots;
 pub mod users;
 
 use mongodb::{options::ClientOptions, Client};
 
 use self::{
     chat_msg::ChatMsgCollection, friendships::FriendshipCollection, games::GameCollection,
-    users::UserCollection,
+    series::SeriesCollection, snapshots::SnapshotCollection, users::UserCollection,
 };
 
 const MONGO_URL_DEFAULT: &str = "mongodb://localhost:27017";
@@ -15,8 +17,10 @@ const MONGO_URL_DEFAULT: &str = "mongodb://localhost:27017";
 pub struct DatabaseManager {
     pub users: UserCollection,
     pub games: GameCollection,
+    pub series: SeriesCollection,
     pub friendships: FriendshipCollection,
     pub chat_msgs: ChatMsgCollection,
+    pub snapshots: SnapshotCollection,
 }
 
 impl DatabaseManager {
@@ -26,12 +30,23 @@ impl DatabaseManager {
         let opt = ClientOptions::parse(&url).await.unwrap();
         let client = Client::with_options(opt).expect("Failed to start mongodb client");
         let db = client.database("fourinarow");
+        if !UserCollection::create_indexes(&db).await {
+            println!("Failed to create indexes on users collection");
+        }
+        if !UserCollection::migrate_session_tokens(&db).await {
+            println!("Failed to migrate session tokens");
+        }
+        if !GameCollection::migrate_legacy_games(&db).await {
+            println!("Failed to migrate legacy games");
+        }
 
         DatabaseManager {
             users: UserCollection::new(&db),
             games: GameCollection::new(&db),
+            series: SeriesCollection::new(&db),
             friendships: FriendshipCollection::new(&db),
             chat_msgs: ChatMsgCollection::new(&db),
+            snapshots: SnapshotCollection::new(&db),
         }
     }
 
@@ -0,0 +1,37 @@
+use mongodb::{bson::*, Collection, Database};
+
+use crate::api::users::user::{PlayedSeriesInfo, UserId};
+
+pub struct SeriesCollection {
+    collection: Collection<PlayedSeriesInfo>,
+}
+
+impl SeriesCollection {
+    pub fn new(db: &Database) -> Self {
+        SeriesCollection {
+            collection: db.collection_with_type("series"),
+        }
+    }
+
+    pub async fn insert(&self, series: PlayedSeriesInfo) -> bool {
+        self.collection.insert_one(series, None).await.is_ok()
+    }
+
+    /// As `GameCollection::anonymise`
+    pub async fn anonymise(&self, user_id: &UserId) -> bool {
+        for player in ["player_one", "player_two"].iter() {
+            let res = self
+                .collection
+                .update_many(
+                    doc! { *player: user_id.to_string() },
+                    doc! { "$set": { *player: Bson::Null } },
+                    None,
+                )
+                .await;
+            if res.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,37 @@
+use mongodb::{bson::doc, options::FindOneOptions, Collection, Database};
+
+use crate::game::snapshot::ServerSnapshot;
+
+pub struct SnapshotCollection {
+    collection: Collection<ServerSnapshot>,
+}
+
+impl SnapshotCollection {
+    pub fn new(db: &Database) -> Self {
+        SnapshotCollection {
+            collection: db.collection_with_type("snapshots"),
+        }
+    }
+
+    pub async fn insert(&self, snapshot: ServerSnapshot) -> bool {
+        self.collection.insert_one(snapshot, None).await.is_ok()
+    }
+
+    /// The newest snapshot, all snapshots are removed so that none is restored twice
+    pub async fn take(&self) -> Option<ServerSnapshot> {
+        let mut options = FindOneOptions::default();
+        let minus_one: i32 = -1;
+        options.sort = Some(doc! { "taken_at": minus_one });
+        let snapshot = self.collection.find_one(None, Some(options)).await;
+        if self.collection.delete_many(doc! {}, None).await.is_err() {
+            println!("Failed to remove snapshots");
+        }
+        match snapshot {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                println!("Failed to load snapshot: {:?}", err);
+                None
+            }
+        }
+    }
+}
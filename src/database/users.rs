@@ -1,8 +1,10 @@
 use actix::Addr;
+use actix_web::web;
 use dashmap::DashMap;
 use futures::future::OptionFuture;
 use mongodb::{
     bson::{self, doc},
+    options::FindOptions,
     Collection, Database,
 };
 use serde::{Deserialize, Serialize};
@@ -11,15 +13,17 @@ use tokio::stream::StreamExt;
 use super::friendships::FriendshipCollection;
 use crate::{
     api::users::{
-        session_token::SessionToken,
+        rating::PROVISIONAL_GAMES,
+        session_token::{Session, SessionId, SessionToken},
         user::{BackendUserMe, HashedPassword, PublicUserOther, UserGameInfo, UserId},
         user_mgr::UserAuth,
     },
-    game::client_state::ClientState,
+    game::{client_state::ClientState, game_info::now_millis},
 };
 
 pub struct UserCollection {
     collection: Collection<DbUser>,
+    sessions: Collection<DbUserSessions>, // Same collection, `DbUser` leaves out the sessions
     playing_users_cache: DashMap<UserId, Addr<ClientState>>,
 }
 
@@ -27,21 +31,52 @@ impl UserCollection {
     pub fn new(db: &Database) -> Self {
         UserCollection {
             collection: db.collection_with_type("users"),
+            sessions: db.collection_with_type("users"),
             playing_users_cache: DashMap::new(),
         }
     }
 
-    async fn get_auth(
+    /// Legacy password hashes are replaced once the password is known to be correct
+    pub async fn get_auth(
         &self,
         auth: UserAuth,
         friendships: &FriendshipCollection,
     ) -> Option<BackendUserMe> {
-        if let Some(user) = self.get_username(&auth.username, friendships).await {
-            if user.password.matches(&auth.password) {
-                return Some(user);
+        let mut user = self.get_username(&auth.username, friendships).await?;
+        let hashed_password = user.password.clone();
+        // Argon2 is slow on purpose, keep it off the arbiter thread
+        let (matches, maybe_rehashed) = web::block(move || {
+            let matches = hashed_password.matches(&auth.password);
+            let maybe_rehashed = Some(&auth.password)
+                .filter(|_| matches && hashed_password.is_legacy())
+                .map(|password| HashedPassword::new(password));
+            Ok::<_, ()>((matches, maybe_rehashed))
+        })
+        .await
+        .unwrap_or((false, None));
+        if !matches {
+            return None;
+        }
+        if let Some(rehashed) = maybe_rehashed {
+            if self.set_password(&user.id, &rehashed).await {
+                user.password = rehashed;
+            } else {
+                println!("Failed to rehash password of {}", user.id);
             }
         }
-        None
+        Some(user)
+    }
+
+    async fn set_password(&self, id: &UserId, password: &HashedPassword) -> bool {
+        self.collection
+            .update_one(
+                doc! { "_id": id.to_string() },
+                doc! { "$set": { "password": password.to_string() } },
+                None,
+            )
+            .await
+            .map(|res| res.matched_count == 1)
+            .unwrap_or(false)
     }
 
     pub async fn get_session_token(
@@ -49,15 +84,48 @@ impl UserCollection {
         session_token: SessionToken,
         friendships: &FriendshipCollection,
     ) -> Option<BackendUserMe> {
-        let user: OptionFuture<_> = self
-            .collection
-            .find_one(doc! {"session_tokens": session_token.to_string() }, None)
+        let (user_id, _) = self.find_session(&session_token).await?;
+        self.get_id(&user_id, friendships).await
+    }
+
+    /// The unexpired session with this token, using it extends it
+    pub async fn find_session(&self, session_token: &SessionToken) -> Option<(UserId, Session)> {
+        let now = now_millis();
+        let token_hash = session_token.hash();
+        let user = self
+            .sessions
+            .find_one(
+                doc! { "sessions": { "$elemMatch": {
+                    "token_hash": &token_hash,
+                    "expires_at": { "$gt": now },
+                } } },
+                None,
+            )
             .await
             .ok()
-            .flatten()
-            .map(|user| user.to_backend_user(&self, friendships))
-            .into();
-        user.await
+            .flatten()?;
+        let mut session = user
+            .sessions
+            .into_iter()
+            .find(|session| session.token_hash == token_hash)?;
+        if session.needs_touch(now) {
+            session.touch(now);
+            let touched = self
+                .sessions
+                .update_one(
+                    doc! { "_id": user.id.to_string(), "sessions.token_hash": &token_hash },
+                    doc! { "$set": {
+                        "sessions.$.last_used_at": session.last_used_at,
+                        "sessions.$.expires_at": session.expires_at,
+                    } },
+                    None,
+                )
+                .await;
+            if touched.is_err() {
+                println!("Failed to update session of {}", user.id);
+            }
+        }
+        Some((user.id, session))
     }
 
     pub async fn create_session_token(
@@ -65,13 +133,27 @@ impl UserCollection {
         auth: UserAuth,
         friendships: &FriendshipCollection,
     ) -> Option<SessionToken> {
+        let device = auth.device.clone();
         if let Some(user) = self.get_auth(auth, friendships).await {
             let session_token = SessionToken::new();
+            let session = bson::to_bson(&Session::new(&session_token, device)).ok()?;
+            // Expired sessions are cleaned up whenever a new one is added
+            let pulled = self
+                .sessions
+                .update_one(
+                    doc! { "_id": user.id.to_string() },
+                    doc! { "$pull": { "sessions": { "expires_at": { "$lte": now_millis() } } } },
+                    None,
+                )
+                .await;
+            if pulled.is_err() {
+                println!("Failed to remove expired sessions of {}", user.id);
+            }
             return self
-                .collection
+                .sessions
                 .update_one(
-                    doc! {"username": user.username},
-                    doc! { "$push": { "session_tokens": session_token.to_string() } },
+                    doc! { "_id": user.id.to_string() },
+                    doc! { "$push": { "sessions": session } },
                     None,
                 )
                 .await
@@ -81,17 +163,74 @@ impl UserCollection {
         None
     }
 
-    pub async fn remove_session_token(&self, session_token: SessionToken) -> Result<(), ()> {
-        let session_token_str = session_token.to_string();
-        self.collection
+    /// Unexpired sessions of a user, oldest first
+    pub async fn get_sessions(&self, id: &UserId) -> Option<Vec<Session>> {
+        let now = now_millis();
+        let user = self
+            .sessions
+            .find_one(doc! { "_id": id.to_string() }, None)
+            .await
+            .ok()
+            .flatten()?;
+        Some(
+            user.sessions
+                .into_iter()
+                .filter(|session| session.expires_at > now)
+                .collect(),
+        )
+    }
+
+    pub async fn remove_sessions(&self, id: &UserId, session_ids: &[SessionId]) -> bool {
+        let session_ids: Vec<bson::Bson> = session_ids
+            .iter()
+            .filter_map(|session_id| bson::to_bson(session_id).ok())
+            .collect();
+        self.sessions
             .update_one(
-                doc! { "session_tokens": &session_token_str },
-                doc! { "$pull": { "session_tokens": session_token_str } },
+                doc! { "_id": id.to_string() },
+                doc! { "$pull": { "sessions": { "id": { "$in": session_ids } } } },
                 None,
             )
             .await
-            .map(|_| ())
-            .map_err(|_| ())
+            .map(|res| res.matched_count == 1)
+            .unwrap_or(false)
+    }
+
+    /// Tokens used to be stored in plaintext in `session_tokens`, only their hashes are kept now
+    pub async fn migrate_session_tokens(db: &Database) -> bool {
+        let collection: Collection<DbUserSessions> = db.collection_with_type("users");
+        let users: OptionFuture<_> = collection
+            .find(doc! { "session_tokens": { "$exists": true } }, None)
+            .await
+            .map(|cursor| cursor.collect::<Result<Vec<_>, _>>())
+            .ok()
+            .into();
+        let users = match users.await.and_then(|r| r.ok()) {
+            Some(users) => users,
+            None => return false,
+        };
+        for user in users {
+            let sessions: Vec<bson::Bson> = user
+                .session_tokens
+                .iter()
+                .map(Session::migrate)
+                .filter_map(|session| bson::to_bson(&session).ok())
+                .collect();
+            let res = collection
+                .update_one(
+                    doc! { "_id": user.id.to_string() },
+                    doc! {
+                        "$push": { "sessions": { "$each": sessions } },
+                        "$unset": { "session_tokens": "" },
+                    },
+                    None,
+                )
+                .await;
+            if res.is_err() {
+                return false;
+            }
+        }
+        true
     }
 
     pub async fn get_username(
@@ -126,6 +265,16 @@ impl UserCollection {
         user.await
     }
 
+    /// False once the account was deleted. Errors count as existing, so that games
+    /// aren't stored without their players when the database hiccups.
+    pub async fn exists(&self, id: &UserId) -> bool {
+        self.collection
+            .count_documents(doc! {"_id": id.to_string()}, None)
+            .await
+            .map(|count| count > 0)
+            .unwrap_or(true)
+    }
+
     pub async fn get_id_public(&self, id: UserId) -> Option<PublicUserOther> {
         self.collection
             .find_one(doc! {"_id": id.to_string()}, None)
@@ -166,6 +315,70 @@ impl UserCollection {
         users.await.map(|r| r.ok()).flatten().unwrap_or(Vec::new())
     }
 
+    /// Established players ordered by skill rating, best first. Ties are ordered by id
+    /// so that pages neither repeat nor skip players.
+    pub async fn get_leaderboard(&self, skip: i64, limit: i64) -> Vec<PublicUserOther> {
+        let mut options = FindOptions::default();
+        options.skip = Some(skip);
+        options.limit = Some(limit);
+        let minus_one: i32 = -1;
+        options.sort = Some(doc! { "game_info.skill_rating": minus_one, "_id": 1 });
+        let users: OptionFuture<_> = self
+            .collection
+            .find(
+                doc! { "game_info.games_played": { "$gte": PROVISIONAL_GAMES } },
+                Some(options),
+            )
+            .await
+            .map(|cursor| {
+                cursor
+                    .map(|result| result.map(|user| self.to_public(user)))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .ok()
+            .into();
+        users.await.and_then(|r| r.ok()).unwrap_or_default()
+    }
+
+    fn to_public(&self, user: DbUser) -> PublicUserOther {
+        PublicUserOther {
+            playing: self.playing_users_cache.contains_key(&user.id),
+            id: user.id,
+            username: user.username,
+            game_info: user.game_info,
+        }
+    }
+
+    pub fn playing_addr(&self, id: &UserId) -> Option<Addr<ClientState>> {
+        self.playing_users_cache.get(id).map(|addr| addr.clone())
+    }
+
+    /// Backs the leaderboard query and the session lookup
+    pub async fn create_indexes(db: &Database) -> bool {
+        // Replaced by the index with the id as tiebreaker, fails if it's gone already
+        let _ = db
+            .run_command(
+                doc! { "dropIndexes": "users", "index": "skill_rating" },
+                None,
+            )
+            .await;
+        db.run_command(
+            doc! {
+                "createIndexes": "users",
+                "indexes": [{
+                    "key": { "game_info.skill_rating": -1, "_id": 1 },
+                    "name": "skill_rating_id",
+                }, {
+                    "key": { "sessions.token_hash": 1 },
+                    "name": "session_token_hash",
+                }],
+            },
+            None,
+        )
+        .await
+        .is_ok()
+    }
+
     pub async fn insert(&self, user: BackendUserMe) -> bool {
         self.collection
             .insert_one(DbUser::from_backend_user(user), None)
@@ -173,6 +386,16 @@ impl UserCollection {
             .is_ok()
     }
 
+    /// Removes the user together with its sessions
+    pub async fn delete(&self, id: &UserId) -> bool {
+        self.playing_users_cache.remove(id);
+        self.collection
+            .delete_one(doc! { "_id": id.to_string() }, None)
+            .await
+            .map(|res| res.deleted_count == 1)
+            .unwrap_or(false)
+    }
+
     pub async fn update(&self, user: BackendUserMe) -> bool {
         if let Some(playing_addr) = &user.playing {
             self.playing_users_cache
@@ -190,6 +413,35 @@ impl UserCollection {
             .await
             .is_ok()
     }
+
+    /// Sets the game info of several users in a single update command, so that the two
+    /// players of a game are rated together. The driver we use has no transactions and
+    /// a standalone mongodb does not support them either.
+    pub async fn update_game_infos(&self, game_infos: &[(UserId, UserGameInfo)]) -> bool {
+        let ids: Vec<String> = game_infos.iter().map(|(id, _)| id.to_string()).collect();
+        let branches: Vec<bson::Document> = game_infos
+            .iter()
+            .filter_map(|(id, game_info)| {
+                bson::to_document(game_info).ok().map(|game_info| {
+                    doc! {
+                        "case": { "$eq": ["$_id", id.to_string()] },
+                        "then": { "$literal": game_info },
+                    }
+                })
+            })
+            .collect();
+        if branches.len() != game_infos.len() {
+            return false;
+        }
+        let update = vec![doc! {
+            "$set": { "game_info": { "$switch": { "branches": branches, "default": "$game_info" } } }
+        }];
+        self.collection
+            .update_many(doc! { "_id": { "$in": ids } }, update, None)
+            .await
+            .map(|res| res.matched_count as usize == game_infos.len())
+            .unwrap_or(false)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -203,10 +455,17 @@ struct DbUser {
     pub email: Option<String>,
 
     pub game_info: UserGameInfo,
+}
 
-    #[serde(skip)]
-    #[allow(dead_code)]
-    pub session_tokens: Vec<SessionToken>,
+/// The sessions of a user, kept apart so that `UserCollection::update` doesn't overwrite them
+#[derive(Debug, Serialize, Deserialize)]
+struct DbUserSessions {
+    #[serde(rename = "_id")]
+    pub id: UserId,
+    #[serde(default)]
+    pub sessions: Vec<Session>,
+    #[serde(default)]
+    pub session_tokens: Vec<SessionToken>, // Before `migrate_session_tokens`
 }
 
 impl DbUser {
@@ -217,8 +476,6 @@ impl DbUser {
             password: user.password,
             email: user.email,
             game_info: user.game_info,
-
-            session_tokens: vec![],
         }
     }
     async fn to_backend_user(
@@ -0,0 +1,508 @@
+use super::game_info::{BoardConfig, GameResult, Player};
+
+use std::fmt;
+
+/// Bitboard representation of a field, one `u64` per player.
+///
+/// Cell (column, row) is stored at bit `column * rows + row`, with row 0 being the bottom
+/// of the column. There are no separator bits between columns, so every shift used for
+/// win detection is masked to cells whose neighbour in that direction is on the board.
+/// This allows boards with up to 64 cells.
+#[derive(Clone)]
+pub struct Board {
+    config: BoardConfig,
+    first: Player,
+    chips: [u64; 2],
+    heights: Vec<usize>,
+    moves: Vec<usize>,
+    full_mask: u64,
+    directions: [(usize, u64); 4], // (shift, cells that have a neighbour at +shift)
+}
+
+impl Board {
+    pub fn new(config: BoardConfig, first: Player) -> Board {
+        let columns = config.columns;
+        let rows = config.rows;
+        assert!(columns * rows <= 64, "Board does not fit into a bitboard");
+
+        let mut full_mask = 0;
+        let mut up = 0;
+        let mut right = 0;
+        let mut up_right = 0;
+        let mut down_right = 0;
+        for column in 0..columns {
+            for row in 0..rows {
+                let bit = 1 << (column * rows + row);
+                full_mask |= bit;
+                let has_up = row + 1 < rows;
+                let has_right = column + 1 < columns;
+                let has_down = row > 0;
+                if has_up {
+                    up |= bit;
+                }
+                if has_right {
+                    right |= bit;
+                }
+                if has_right && has_up {
+                    up_right |= bit;
+                }
+                if has_right && has_down {
+                    down_right |= bit;
+                }
+            }
+        }
+
+        Board {
+            config,
+            first,
+            chips: [0, 0],
+            heights: vec![0; columns],
+            moves: Vec::with_capacity(columns * rows),
+            full_mask,
+            directions: [
+                (1, up),
+                (rows, right),
+                (rows + 1, up_right),
+                (rows - 1, down_right),
+            ],
+        }
+    }
+
+    pub fn config(&self) -> BoardConfig {
+        self.config
+    }
+
+    pub fn first(&self) -> Player {
+        self.first
+    }
+
+    /// The player who places the next chip
+    pub fn turn(&self) -> Player {
+        if self.moves.len() & 1 == 0 {
+            self.first
+        } else {
+            self.first.other()
+        }
+    }
+
+    /// Columns in the order they were played
+    pub fn moves(&self) -> &[usize] {
+        &self.moves
+    }
+
+    pub fn move_count(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn can_play(&self, column: usize) -> bool {
+        column < self.config.columns && self.heights[column] < self.config.rows
+    }
+
+    pub fn legal_moves(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.config.columns).filter(move |column| self.can_play(*column))
+    }
+
+    /// Drops a chip of the current player into the column
+    pub fn play(&mut self, column: usize) -> Result<(), ()> {
+        if !self.can_play(column) {
+            return Err(());
+        }
+        let bit = self.bit(column, self.heights[column]);
+        self.chips[Self::index(self.turn())] |= bit;
+        self.heights[column] += 1;
+        self.moves.push(column);
+        Ok(())
+    }
+
+    /// Takes back the last move and returns its column
+    pub fn undo(&mut self) -> Option<usize> {
+        let column = self.moves.pop()?;
+        self.heights[column] -= 1;
+        let bit = self.bit(column, self.heights[column]);
+        // turn() already refers to the player who made the undone move
+        self.chips[Self::index(self.turn())] &= !bit;
+        Some(column)
+    }
+
+    /// Returns the chip at (column, row), where row 0 is the bottom
+    pub fn cell(&self, column: usize, row: usize) -> Option<Player> {
+        if column >= self.config.columns || row >= self.config.rows {
+            return None;
+        }
+        let bit = self.bit(column, row);
+        if self.chips[0] & bit != 0 {
+            Some(Player::One)
+        } else if self.chips[1] & bit != 0 {
+            Some(Player::Two)
+        } else {
+            None
+        }
+    }
+
+    pub fn has_won(&self, player: Player) -> bool {
+        let chips = self.chips[Self::index(player)];
+        self.directions.iter().any(|&(shift, mask)| {
+            // After i iterations, `run` contains every cell starting a run of i + 1 chips
+            let mut run = chips;
+            for _ in 1..self.config.win_length {
+                run = chips & ((run >> shift) & mask);
+                if run == 0 {
+                    return false;
+                }
+            }
+            true
+        })
+    }
+
+    pub fn winner(&self) -> Option<Player> {
+        if self.has_won(Player::One) {
+            Some(Player::One)
+        } else if self.has_won(Player::Two) {
+            Some(Player::Two)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        (self.chips[0] | self.chips[1]) == self.full_mask
+    }
+
+    pub fn result(&self) -> Option<GameResult> {
+        if let Some(winner) = self.winner() {
+            Some(GameResult::Winner(winner))
+        } else if self.is_full() {
+            Some(GameResult::Draw)
+        } else {
+            None
+        }
+    }
+
+    fn bit(&self, column: usize, row: usize) -> u64 {
+        1 << (column * self.config.rows + row)
+    }
+
+    fn index(player: Player) -> usize {
+        player.select(0, 1)
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in (0..self.config.rows).rev() {
+            for column in 0..self.config.columns {
+                let c = match self.cell(column, row) {
+                    None => '□',
+                    Some(Player::One) => 'X',
+                    Some(Player::Two) => 'O',
+                };
+                write!(f, "{} ", c)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Straightforward grid implementation the bitboard is checked against
+    struct ReferenceBoard {
+        config: BoardConfig,
+        field: Vec<Vec<Option<Player>>>, // field[column][row], row 0 is the bottom
+        turn: Player,
+    }
+
+    impl ReferenceBoard {
+        fn new(config: BoardConfig, first: Player) -> Self {
+            ReferenceBoard {
+                config,
+                field: vec![Vec::new(); config.columns],
+                turn: first,
+            }
+        }
+
+        fn play(&mut self, column: usize) -> bool {
+            if column >= self.config.columns || self.field[column].len() >= self.config.rows {
+                return false;
+            }
+            self.field[column].push(Some(self.turn));
+            self.turn = self.turn.other();
+            true
+        }
+
+        fn cell(&self, column: usize, row: usize) -> Option<Player> {
+            self.field[column].get(row).copied().flatten()
+        }
+
+        fn has_won(&self, player: Player) -> bool {
+            let columns = self.config.columns as isize;
+            let rows = self.config.rows as isize;
+            let length = self.config.win_length as isize;
+            for x in 0..columns {
+                for y in 0..rows {
+                    for (dx, dy) in [(1, 0), (0, 1), (1, 1), (1, -1)].iter() {
+                        let is_run = (0..length).all(|i| {
+                            let cx = x + dx * i;
+                            let cy = y + dy * i;
+                            cx >= 0
+                                && cx < columns
+                                && cy >= 0
+                                && cy < rows
+                                && self.cell(cx as usize, cy as usize) == Some(player)
+                        });
+                        if is_run {
+                            return true;
+                        }
+                    }
+                }
+            }
+            false
+        }
+
+        fn is_full(&self) -> bool {
+            self.field
+                .iter()
+                .all(|column| column.len() == self.config.rows)
+        }
+    }
+
+    fn all_configs() -> Vec<BoardConfig> {
+        let mut configs = Vec::new();
+        for columns in 1..=10 {
+            for rows in 1..=10 {
+                for win_length in 1..=10 {
+                    if let Some(config) = BoardConfig::new(columns, rows, win_length) {
+                        configs.push(config);
+                    }
+                }
+            }
+        }
+        configs
+    }
+
+    fn assert_same(board: &Board, reference: &ReferenceBoard) {
+        let config = board.config();
+        for column in 0..config.columns {
+            for row in 0..config.rows {
+                assert_eq!(
+                    board.cell(column, row),
+                    reference.cell(column, row),
+                    "cell ({}, {}) differs on\n{}",
+                    column,
+                    row,
+                    board
+                );
+            }
+        }
+        assert_eq!(board.turn(), reference.turn);
+        for player in [Player::One, Player::Two].iter() {
+            assert_eq!(
+                board.has_won(*player),
+                reference.has_won(*player),
+                "win for {:?} differs ({}) on\n{}",
+                player,
+                config,
+                board
+            );
+        }
+        assert_eq!(board.is_full(), reference.is_full());
+        let legal: Vec<_> = board.legal_moves().collect();
+        let reference_legal: Vec<_> = (0..config.columns)
+            .filter(|c| reference.field[*c].len() < config.rows)
+            .collect();
+        assert_eq!(legal, reference_legal);
+    }
+
+    fn board(columns: usize, rows: usize, win_length: usize) -> Board {
+        Board::new(
+            BoardConfig::new(columns, rows, win_length).unwrap(),
+            Player::One,
+        )
+    }
+
+    fn play_all(board: &mut Board, columns: &[usize]) {
+        for column in columns {
+            board.play(*column).unwrap();
+        }
+    }
+
+    #[test]
+    fn every_valid_config_fits_into_a_bitboard() {
+        for config in all_configs() {
+            assert!(config.columns * config.rows <= 64, "{}", config);
+        }
+    }
+
+    #[test]
+    fn empty_board() {
+        let board = Board::new(BoardConfig::default(), Player::Two);
+        assert_eq!(board.turn(), Player::Two);
+        assert_eq!(board.winner(), None);
+        assert_eq!(board.result(), None);
+        assert!(!board.is_full());
+        assert_eq!(board.legal_moves().count(), 7);
+    }
+
+    #[test]
+    fn detects_simple_wins() {
+        // Vertical
+        let mut b = board(7, 7, 4);
+        play_all(&mut b, &[0, 1, 0, 1, 0, 1]);
+        assert_eq!(b.winner(), None);
+        b.play(0).unwrap();
+        assert_eq!(b.result(), Some(GameResult::Winner(Player::One)));
+
+        // Horizontal
+        let mut b = board(7, 6, 4);
+        play_all(&mut b, &[0, 0, 1, 1, 2, 2, 3]);
+        assert_eq!(b.winner(), Some(Player::One));
+
+        // Diagonal up-right
+        let mut b = board(7, 6, 4);
+        play_all(&mut b, &[0, 1, 1, 2, 2, 3, 2, 3, 3, 6, 3]);
+        assert_eq!(b.winner(), Some(Player::One));
+
+        // Diagonal down-right
+        let mut b = board(7, 6, 4);
+        play_all(&mut b, &[6, 5, 5, 4, 4, 3, 4, 3, 3, 0, 3]);
+        assert_eq!(b.winner(), Some(Player::One));
+    }
+
+    #[test]
+    fn respects_win_length() {
+        let mut b = board(9, 7, 5);
+        play_all(&mut b, &[0, 0, 1, 1, 2, 2, 3, 3]);
+        assert_eq!(b.winner(), None);
+        b.play(4).unwrap();
+        assert_eq!(b.winner(), Some(Player::One));
+    }
+
+    #[test]
+    fn runs_do_not_wrap_around_columns() {
+        // Player One fills the top of column 0 and the bottom of column 1, which are
+        // adjacent bits but not adjacent cells.
+        let mut b = board(4, 4, 3);
+        play_all(&mut b, &[0, 0, 1, 0, 3, 2, 0, 3, 1]);
+        assert_eq!(b.cell(0, 3), Some(Player::One));
+        assert_eq!(b.cell(1, 0), Some(Player::One));
+        assert_eq!(b.cell(1, 1), Some(Player::One));
+        assert_eq!(b.winner(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_moves() {
+        let mut b = board(4, 4, 3);
+        assert!(b.play(4).is_err());
+        play_all(&mut b, &[0, 0, 0, 0]);
+        assert!(!b.can_play(0));
+        assert!(b.play(0).is_err());
+        assert_eq!(b.move_count(), 4);
+        assert_eq!(b.legal_moves().collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn detects_draw() {
+        // Alternating columns, with the right half mirrored so the diagonals are broken up
+        let mut b = board(4, 4, 4);
+        play_all(&mut b, &[0, 1, 1, 0, 0, 1, 1, 0, 3, 2, 2, 3, 3, 2, 2, 3]);
+        assert!(b.is_full());
+        assert_eq!(b.winner(), None);
+        assert_eq!(b.result(), Some(GameResult::Draw));
+        assert_eq!(b.legal_moves().count(), 0);
+    }
+
+    #[test]
+    fn undo_restores_previous_state() {
+        let mut b = board(7, 6, 4);
+        play_all(&mut b, &[3, 3, 4]);
+        let before = b.clone();
+        b.play(2).unwrap();
+        assert_eq!(b.undo(), Some(2));
+        assert_eq!(b.chips, before.chips);
+        assert_eq!(b.heights, before.heights);
+        assert_eq!(b.turn(), before.turn());
+        assert_eq!(b.undo(), Some(4));
+        assert_eq!(b.undo(), Some(3));
+        assert_eq!(b.undo(), Some(3));
+        assert_eq!(b.undo(), None);
+        assert_eq!(b.chips, [0, 0]);
+    }
+
+    #[test]
+    fn matches_reference_in_random_games() {
+        let mut rng = StdRng::seed_from_u64(4);
+        for config in all_configs() {
+            for game in 0..10 {
+                let first = if game % 2 == 0 {
+                    Player::One
+                } else {
+                    Player::Two
+                };
+                let mut board = Board::new(config, first);
+                let mut reference = ReferenceBoard::new(config, first);
+                let mut states = vec![board.clone()];
+
+                // Keep playing after a win as well, so that boards with several runs are covered
+                loop {
+                    assert_same(&board, &reference);
+                    let column = rng.gen_range(0, config.columns + 1);
+                    let legal = reference.play(column);
+                    assert_eq!(board.play(column).is_ok(), legal);
+                    if legal {
+                        states.push(board.clone());
+                    }
+                    if board.is_full() {
+                        assert_same(&board, &reference);
+                        break;
+                    }
+                }
+
+                // Undo everything and compare against the recorded states
+                states.pop();
+                while let Some(state) = states.pop() {
+                    board.undo().unwrap();
+                    assert_eq!(board.chips, state.chips);
+                    assert_eq!(board.heights, state.heights);
+                    assert_eq!(board.winner(), state.winner());
+                }
+                assert_eq!(board.undo(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn result_is_first_win_in_random_games() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for config in all_configs() {
+            for _ in 0..10 {
+                let mut board = Board::new(config, Player::One);
+                let mut reference = ReferenceBoard::new(config, Player::One);
+                let mut last_player = None;
+                while board.result().is_none() {
+                    let moves: Vec<_> = board.legal_moves().collect();
+                    let column = moves[rng.gen_range(0, moves.len())];
+                    last_player = Some(board.turn());
+                    board.play(column).unwrap();
+                    reference.play(column);
+                }
+                match board.result().unwrap() {
+                    // The game stops at the first win, so only the last mover can have won
+                    GameResult::Winner(winner) => {
+                        assert_eq!(Some(winner), last_player);
+                        assert!(reference.has_won(winner));
+                        assert!(!reference.has_won(winner.other()));
+                    }
+                    GameResult::Draw => {
+                        assert!(reference.is_full());
+                        assert!(!reference.has_won(Player::One));
+                        assert!(!reference.has_won(Player::Two));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Server side opponent for players who don't want to wait for a human.
+//! The bot takes the place of a `ClientState` in a lobby. Searching for a move runs on the
+//! blocking thread pool so that a deep search does not stall other actors.
+
+use super::board::Board;
+use super::game_info::Player;
+use super::lobby::{ClientLobbyMessage, ClientLobbyMessageNamed, Lobby};
+use super::msg::ServerMessage;
+
+use actix::*;
+use actix_web::web;
+use rand::{seq::SliceRandom, thread_rng};
+
+/// Search depth per difficulty level, level 0 plays random moves
+const SEARCH_DEPTHS: [usize; 6] = [0, 1, 2, 4, 6, 8];
+const WIN_SCORE: i32 = 1_000_000;
+
+/// Opponent for players who waited in the public queue for too long
+pub const QUEUE_FALLBACK_LEVEL: BotLevel = BotLevel(3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BotLevel(usize);
+
+impl BotLevel {
+    pub fn parse(text: &str) -> Option<BotLevel> {
+        BotLevel::new(text.parse().ok()?)
+    }
+
+    pub fn new(level: usize) -> Option<BotLevel> {
+        if level < SEARCH_DEPTHS.len() {
+            Some(BotLevel(level))
+        } else {
+            None
+        }
+    }
+
+    /// As stored in the games collection
+    pub fn number(self) -> i32 {
+        self.0 as i32
+    }
+
+    fn depth(self) -> usize {
+        SEARCH_DEPTHS[self.0]
+    }
+}
+
+pub struct Bot {
+    level: BotLevel,
+    player: Player,
+    lobby: Addr<Lobby>,
+    board: Option<Board>,
+    game_count: usize, // Discards moves that were computed for a previous game
+}
+
+impl Bot {
+    pub fn new(level: BotLevel, player: Player, lobby: Addr<Lobby>) -> Bot {
+        Bot {
+            level,
+            player,
+            lobby,
+            board: None,
+            game_count: 0,
+        }
+    }
+
+    fn play_if_my_turn(&mut self, ctx: &mut Context<Self>) {
+        let board = match &self.board {
+            Some(board) if board.turn() == self.player && board.result().is_none() => board.clone(),
+            _ => return,
+        };
+        let level = self.level;
+        let game_count = self.game_count;
+        ctx.spawn(
+            web::block(move || choose_move(&board, level).ok_or(()))
+                .into_actor(self)
+                .map(move |res, act, _ctx| {
+                    if act.game_count != game_count {
+                        return;
+                    }
+                    if let (Ok(column), Some(board)) = (res, &mut act.board) {
+                        if board.play(column).is_ok() {
+                            act.lobby.do_send(ClientLobbyMessageNamed {
+                                sender: act.player,
+                                msg: ClientLobbyMessage::PlaceChip(column),
+                            });
+                        }
+                    }
+                }),
+        );
+    }
+}
+
+impl Actor for Bot {
+    type Context = Context<Self>;
+}
+
+impl Handler<ServerMessage> for Bot {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, msg: ServerMessage, ctx: &mut Self::Context) -> Self::Result {
+        match msg {
+            ServerMessage::GameStart(my_turn, _, config) => {
+                let first = if my_turn {
+                    self.player
+                } else {
+                    self.player.other()
+                };
+                self.board = Some(Board::new(config, first));
+                self.game_count += 1;
+                self.play_if_my_turn(ctx);
+            }
+            ServerMessage::BoardState {
+                board: config,
+                my_turn,
+                moves,
+                ..
+            } => {
+                // Sent once a game restored after a restart continues
+                let first = if my_turn == (moves.len() % 2 == 0) {
+                    self.player
+                } else {
+                    self.player.other()
+                };
+                let mut board = Board::new(config, first);
+                if moves.iter().any(|column| board.play(*column).is_err()) {
+                    return Err(());
+                }
+                self.board = Some(board);
+                self.game_count += 1;
+                self.play_if_my_turn(ctx);
+            }
+            ServerMessage::PlaceChip(column) => {
+                if let Some(board) = &mut self.board {
+                    if board.play(column).is_err() {
+                        return Err(());
+                    }
+                }
+                self.play_if_my_turn(ctx);
+            }
+            ServerMessage::GameOver(_) | ServerMessage::GameDraw => {
+                // Always up for another round, the rematch starts once the player asks for it
+                self.lobby.do_send(ClientLobbyMessageNamed {
+                    sender: self.player,
+                    msg: ClientLobbyMessage::PlayAgainRequest,
+                });
+            }
+            ServerMessage::DrawOffered => {
+                self.lobby.do_send(ClientLobbyMessageNamed {
+                    sender: self.player,
+                    msg: ClientLobbyMessage::DeclineDraw,
+                });
+            }
+            ServerMessage::OpponentLeaving | ServerMessage::LobbyClosing => {
+                ctx.stop();
+            }
+            ServerMessage::Error(maybe_err) => {
+                println!("Bot: received error {:?}", maybe_err);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Picks a column for the player whose turn it is, `None` if the board is full
+pub fn choose_move(board: &Board, level: BotLevel) -> Option<usize> {
+    let mut board = board.clone();
+    let depth = level.depth();
+    let moves = ordered_moves(&board);
+    if depth == 0 {
+        return moves.choose(&mut thread_rng()).copied();
+    }
+
+    let me = board.turn();
+    let mut best_score = -WIN_SCORE * 2;
+    let mut best_moves = Vec::new();
+    for column in moves {
+        board.play(column).ok()?;
+        let score = if board.has_won(me) {
+            WIN_SCORE + depth as i32
+        } else if board.is_full() {
+            0
+        } else {
+            // A window just below the best score still yields exact values for equally good moves
+            -negamax(&mut board, depth - 1, -WIN_SCORE * 2, -(best_score - 1))
+        };
+        board.undo();
+        if score > best_score {
+            best_score = score;
+            best_moves.clear();
+        }
+        if score == best_score {
+            best_moves.push(column);
+        }
+    }
+    best_moves.choose(&mut thread_rng()).copied()
+}
+
+/// Score of the position for the player to move. Faster wins score higher.
+fn negamax(board: &mut Board, depth: usize, mut alpha: i32, beta: i32) -> i32 {
+    let me = board.turn();
+    if depth == 0 {
+        return evaluate(board, me);
+    }
+    let mut best = -WIN_SCORE * 2;
+    for column in ordered_moves(board) {
+        if board.play(column).is_err() {
+            continue;
+        }
+        let score = if board.has_won(me) {
+            WIN_SCORE + depth as i32
+        } else if board.is_full() {
+            0
+        } else {
+            -negamax(board, depth - 1, -beta, -alpha)
+        };
+        board.undo();
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+/// Legal moves, central columns first as they tend to be stronger and cause earlier cutoffs
+fn ordered_moves(board: &Board) -> Vec<usize> {
+    let columns = board.config().columns as isize;
+    let mut moves: Vec<usize> = board.legal_moves().collect();
+    moves.sort_by_key(|column| (2 * *column as isize - (columns - 1)).abs());
+    moves
+}
+
+/// Heuristic for positions without a winner: every line of `win_length` cells that is
+/// still open for only one player counts for that player, more so the fuller it is.
+fn evaluate(board: &Board, player: Player) -> i32 {
+    let config = board.config();
+    let (columns, rows, length) = (
+        config.columns as isize,
+        config.rows as isize,
+        config.win_length as isize,
+    );
+    let mut score = 0;
+    for &(dc, dr) in &[(1, 0), (0, 1), (1, 1), (1, -1)] {
+        for column in 0..columns {
+            for row in 0..rows {
+                let (end_column, end_row) = (column + dc * (length - 1), row + dr * (length - 1));
+                if end_column >= columns || end_row < 0 || end_row >= rows {
+                    continue;
+                }
+                let (mut mine, mut theirs) = (0, 0);
+                for i in 0..length {
+                    match board.cell((column + dc * i) as usize, (row + dr * i) as usize) {
+                        Some(owner) if owner == player => mine += 1,
+                        Some(_) => theirs += 1,
+                        None => {}
+                    }
+                }
+                if theirs == 0 {
+                    score += mine * mine;
+                } else if mine == 0 {
+                    score -= theirs * theirs;
+                }
+            }
+        }
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::game_info::BoardConfig;
+
+    fn board_after(moves: &[usize]) -> Board {
+        let mut board = Board::new(BoardConfig::default(), Player::One);
+        for column in moves {
+            board.play(*column).unwrap();
+        }
+        board
+    }
+
+    #[test]
+    fn parses_levels() {
+        assert_eq!(BotLevel::parse("0"), Some(BotLevel(0)));
+        assert_eq!(BotLevel::parse("5"), Some(BotLevel(5)));
+        assert_eq!(BotLevel::parse("6"), None);
+        assert_eq!(BotLevel::parse("x"), None);
+    }
+
+    #[test]
+    fn random_bot_plays_legal_moves() {
+        let mut board = board_after(&[]);
+        while board.result().is_none() {
+            let column = choose_move(&board, BotLevel(0)).unwrap();
+            board.play(column).unwrap();
+        }
+    }
+
+    #[test]
+    fn takes_immediate_win() {
+        // One has three in column 0, Two three in column 1
+        let board = board_after(&[0, 1, 0, 1, 0, 1]);
+        for level in 1..SEARCH_DEPTHS.len() {
+            assert_eq!(choose_move(&board, BotLevel(level)), Some(0));
+        }
+    }
+
+    #[test]
+    fn blocks_opponent_win() {
+        // One threatens to complete the bottom row in column 3
+        let board = board_after(&[0, 6, 1, 6, 2]);
+        for level in 2..SEARCH_DEPTHS.len() {
+            assert_eq!(choose_move(&board, BotLevel(level)), Some(3));
+        }
+    }
+
+    #[test]
+    fn deeper_search_beats_random_player() {
+        let mut board = board_after(&[]);
+        while board.result().is_none() {
+            let level = if board.turn() == Player::One { 4 } else { 0 };
+            let column = choose_move(&board, BotLevel(level)).unwrap();
+            board.play(column).unwrap();
+        }
+        assert_eq!(board.winner(), Some(Player::One));
+    }
+}
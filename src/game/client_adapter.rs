@@ -1,14 +1,21 @@
 use super::client_connection::{ClientConnection, ClientConnectionMsg};
 use super::client_state::*;
 use super::msg::*;
+use super::msg_json::JsonServerMessage;
+use super::snapshot::{PendingServerMessage, ReliabilitySnapshot};
 
 use actix::*;
 
+use std::collections::{BTreeMap, VecDeque};
 use std::time::{Duration, Instant};
 
-const QUEUE_CHECK_INTERVAL_MS: u64 = 250;
-const QUEUE_RESEND_TIMEOUT_MS: u128 = 700;
-const RPKT_RETRY_LIMIT: usize = 16;
+const QUEUE_CHECK_INTERVAL_MS: u64 = 100;
+const INITIAL_RESEND_TIMEOUT_MS: u64 = 700;
+const MAX_RESEND_TIMEOUT_MS: u64 = 8000; // Reached after 4 retries
+const RPKT_RETRY_LIMIT: usize = 10;
+const SEND_WINDOW: usize = 32; // Maximum number of unacknowledged server messages
+const SEND_BACKLOG_LIMIT: usize = 1024; // Messages waiting for the window, the client is dropped beyond that
+const PLAYER_MSG_Q_LIMIT: usize = 32; // Out of order player messages further ahead are dropped
 
 /* Version 4:
    - ServerMsg.ReadyForBattlePing
@@ -16,40 +23,202 @@ const RPKT_RETRY_LIMIT: usize = 16;
 */
 pub const MIN_VERSION: usize = 4;
 
+/* Version 5:
+   - Packets and messages are JSON objects, see msg_json
+*/
+pub const JSON_VERSION: usize = 5;
+
+/// How reliable packets are written on the wire, chosen by the protocol version in the client's hello
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Text,
+    Json,
+}
+
+impl Encoding {
+    pub fn for_version(protocol_version: usize) -> Encoding {
+        if protocol_version >= JSON_VERSION {
+            Encoding::Json
+        } else {
+            Encoding::Text
+        }
+    }
+}
+
+/// Orders the player's messages and resends the server's until they are acknowledged.
+/// Server messages get their id when they enter the window, so ids stay in order of sending.
 #[derive(Default)]
 struct ReliabilityLayer {
-    player_msg_index: usize, // Last successfully received message
-    player_msg_q: Vec<QueuedMessage<PlayerMessage>>,
+    player_msg_index: usize, // Last message received in order
+    player_msg_q: BTreeMap<usize, PlayerMessage>, // Received out of order
     server_msg_index: usize, // Last sent message
-    server_msg_q: Vec<QueuedMessage<ServerMessage>>,
+    server_msg_q: BTreeMap<usize, QueuedMessage>, // Sent, but not acknowledged
+    server_msg_backlog: VecDeque<ServerMessage>, // Waiting for room in the window
 }
 
 #[derive(Debug)]
-struct QueuedMessage<T> {
-    sent: Instant,
-    id: usize,
-    msg: T,
+struct QueuedMessage {
+    msg: ServerMessage,
+    resend_at: Instant,
     retry_count: usize,
 }
 
-impl QueuedMessage<PlayerMessage> {
-    fn new(id: usize, msg: PlayerMessage) -> Self {
-        QueuedMessage {
-            sent: Instant::now(),
-            id,
-            msg,
-            retry_count: 0,
+#[derive(Debug, PartialEq)]
+enum Received {
+    /// This message and the queued ones that directly follow it
+    InOrder(Vec<(usize, PlayerMessage)>),
+    Queued,
+    Duplicate,
+    /// Too far ahead, the client has to resend it
+    Dropped,
+}
+
+fn resend_timeout(retry_count: usize) -> Duration {
+    let timeout_ms = INITIAL_RESEND_TIMEOUT_MS.saturating_mul(1 << retry_count.min(16));
+    Duration::from_millis(timeout_ms.min(MAX_RESEND_TIMEOUT_MS))
+}
+
+impl ReliabilityLayer {
+    fn received(&mut self, id: usize, msg: PlayerMessage) -> Received {
+        let expected_id = self.player_msg_index + 1;
+        if id < expected_id || self.player_msg_q.contains_key(&id) {
+            // Client re-sent already known message -> maybe ack got lost
+            Received::Duplicate
+        } else if id == expected_id {
+            // We got the expected message, process queued messages that might have been waiting
+            let mut in_order = vec![(id, msg)];
+            self.player_msg_index = id;
+            while let Some(msg) = self.player_msg_q.remove(&(self.player_msg_index + 1)) {
+                self.player_msg_index += 1;
+                in_order.push((self.player_msg_index, msg));
+            }
+            Received::InOrder(in_order)
+        } else if id - expected_id <= PLAYER_MSG_Q_LIMIT {
+            // A message between this and the last one was lost. Queue this one and wait for client to resend it
+            self.player_msg_q.insert(id, msg);
+            Received::Queued
+        } else {
+            Received::Dropped
+        }
+    }
+
+    /// Acknowledges everything received in order and lists what arrived out of order
+    fn cumulative_ack(&self) -> ReliablePacketOut {
+        ReliablePacketOut::CumulativeAck(
+            self.player_msg_index,
+            self.player_msg_q.keys().copied().collect(),
+        )
+    }
+
+    /// Queues a message for the client, false if the client fell too far behind
+    fn push(&mut self, msg: ServerMessage) -> bool {
+        self.server_msg_backlog.push_back(msg);
+        self.server_msg_backlog.len() <= SEND_BACKLOG_LIMIT
+    }
+
+    fn acked(&mut self, id: usize) {
+        self.server_msg_q.remove(&id);
+    }
+
+    /// The ids come from the client, acks of messages that were never sent are ignored
+    fn acked_up_to(&mut self, up_to: usize, selective: &[usize]) {
+        let up_to = up_to.min(self.server_msg_index);
+        self.server_msg_q = self.server_msg_q.split_off(&(up_to + 1));
+        for id in selective {
+            self.server_msg_q.remove(id);
+        }
+    }
+
+    /// Moves messages from the backlog into the window and returns all packets that are due.
+    /// Err if a message was resent too often
+    fn due_packets(&mut self, now: Instant) -> Result<Vec<ReliablePacketOut>, ()> {
+        while self.server_msg_q.len() < SEND_WINDOW {
+            if let Some(msg) = self.server_msg_backlog.pop_front() {
+                self.server_msg_index += 1;
+                self.server_msg_q.insert(
+                    self.server_msg_index,
+                    QueuedMessage {
+                        msg,
+                        resend_at: now,
+                        retry_count: 0,
+                    },
+                );
+            } else {
+                break;
+            }
+        }
+        let mut packets = Vec::new();
+        for (id, queued_msg) in self.server_msg_q.iter_mut() {
+            if queued_msg.resend_at > now {
+                continue;
+            }
+            if queued_msg.retry_count > RPKT_RETRY_LIMIT {
+                return Err(());
+            }
+            queued_msg.resend_at = now + resend_timeout(queued_msg.retry_count);
+            queued_msg.retry_count += 1;
+            packets.push(ReliablePacketOut::Msg {
+                id: *id,
+                msg: queued_msg.msg.clone(),
+            });
+        }
+        Ok(packets)
+    }
+
+    /// After a reconnect everything unacknowledged is resent right away
+    fn resend_all(&mut self, now: Instant) {
+        for queued_msg in self.server_msg_q.values_mut() {
+            queued_msg.resend_at = now;
         }
     }
-}
 
-impl QueuedMessage<ServerMessage> {
-    fn to_packet(self) -> ReliablePacketOut {
-        ReliablePacketOut::Msg {
-            id: self.id,
-            msg: self.msg,
-            retry_count: self.retry_count,
+    /// Player messages that arrived out of order are left out, they were never acknowledged
+    fn snapshot(&self) -> ReliabilitySnapshot {
+        let encode = |msg: &ServerMessage| {
+            serde_json::to_string(&JsonServerMessage::from(msg.clone())).unwrap_or_default()
+        };
+        ReliabilitySnapshot {
+            player_msg_index: self.player_msg_index as i64,
+            server_msg_index: self.server_msg_index as i64,
+            unacked: self
+                .server_msg_q
+                .iter()
+                .map(|(id, queued_msg)| PendingServerMessage {
+                    id: *id as i64,
+                    msg: encode(&queued_msg.msg),
+                })
+                .collect(),
+            backlog: self.server_msg_backlog.iter().map(encode).collect(),
+        }
+    }
+
+    /// None if the snapshot is damaged
+    fn restore(snapshot: ReliabilitySnapshot, now: Instant) -> Option<ReliabilityLayer> {
+        let index = |n: i64| if n >= 0 { Some(n as usize) } else { None };
+        let decode = |msg: &str| {
+            serde_json::from_str::<JsonServerMessage>(msg)
+                .ok()
+                .map(ServerMessage::from)
+        };
+        let mut layer = ReliabilityLayer {
+            player_msg_index: index(snapshot.player_msg_index)?,
+            server_msg_index: index(snapshot.server_msg_index)?,
+            ..Default::default()
+        };
+        for pending in snapshot.unacked {
+            layer.server_msg_q.insert(
+                index(pending.id)?,
+                QueuedMessage {
+                    msg: decode(&pending.msg)?,
+                    resend_at: now,
+                    retry_count: 0,
+                },
+            );
+        }
+        for msg in snapshot.backlog {
+            layer.server_msg_backlog.push_back(decode(&msg)?);
         }
+        Some(layer)
     }
 }
 
@@ -63,17 +232,20 @@ pub struct ClientAdapter {
     client_connection: ClientConnectionConnectionState,
     client_state: Addr<ClientState>,
     reliability_layer: ReliabilityLayer,
+    encoding: Encoding, // Unused for legacy clients
 }
 
 impl ClientAdapter {
     pub fn new(
         client_connection: Addr<ClientConnection>,
         client_state_addr: Addr<ClientState>,
+        encoding: Encoding,
     ) -> ClientAdapter {
         ClientAdapter {
             client_connection: ClientConnectionConnectionState::Connected(client_connection),
             client_state: client_state_addr,
             reliability_layer: ReliabilityLayer::default(),
+            encoding,
         }
     }
 
@@ -85,99 +257,101 @@ impl ClientAdapter {
             client_connection: ClientConnectionConnectionState::ConnectedLegacy(client_connection),
             client_state: client_state_addr,
             reliability_layer: ReliabilityLayer::default(),
+            encoding: Encoding::Text,
         }
     }
 
+    /// An adapter whose client has yet to reconnect after a restart
+    pub fn restore(
+        client_state_addr: Addr<ClientState>,
+        snapshot: ReliabilitySnapshot,
+    ) -> Option<ClientAdapter> {
+        Some(ClientAdapter {
+            client_connection: ClientConnectionConnectionState::Disconnected,
+            client_state: client_state_addr,
+            reliability_layer: ReliabilityLayer::restore(snapshot, Instant::now())?,
+            encoding: Encoding::Text, // Set by the client's hello when it reconnects
+        })
+    }
+
     fn resend_queued_interval(&self, ctx: &mut Context<Self>) {
         ctx.run_interval(
             Duration::from_millis(QUEUE_CHECK_INTERVAL_MS),
-            |act, ctx| {
-                let messages: Vec<QueuedMessage<ServerMessage>> =
-                    act.reliability_layer.server_msg_q.drain(..).collect();
-
-                for queued_msg in messages {
-                    if queued_msg.sent.elapsed().as_millis() >= QUEUE_RESEND_TIMEOUT_MS {
-                        ctx.notify(queued_msg.to_packet());
-                    } else {
-                        act.reliability_layer.server_msg_q.push(queued_msg);
-                    }
-                }
-            },
+            |act, ctx| act.send_due_packets(ctx),
         );
     }
 
-    fn received_reliable_pkt(&mut self, msg: ReliablePacketIn, ctx: &mut Context<Self>) {
-        match msg {
-            ReliablePacketIn::Ack(id) => {
-                let maybe_rmsg = self
-                    .reliability_layer
-                    .server_msg_q
-                    .iter()
-                    .position(|rmsg| rmsg.id == id);
-                if let Some(rmsg_index) = maybe_rmsg {
-                    self.reliability_layer.server_msg_q.remove(rmsg_index);
+    fn send_due_packets(&mut self, ctx: &mut Context<Self>) {
+        // Retries only count while the client is there to receive them
+        if let ClientConnectionConnectionState::Connected(_) = self.client_connection {
+            match self.reliability_layer.due_packets(Instant::now()) {
+                Ok(packets) => packets
+                    .into_iter()
+                    .for_each(|packet| self.send_packet(packet)),
+                Err(()) => {
+                    println!("ClientAdapter: Retry limit reached, dropping client");
+                    ctx.stop();
                 }
+            }
+        }
+    }
 
-                // } else {
-                //     ctx.notify(ServerMessage::Error(Some(SrvMsgError::InvalidMessage)));
-                //     ctx.stop();
-                // }
+    fn send_packet(&self, packet: ReliablePacketOut) {
+        match &self.client_connection {
+            ClientConnectionConnectionState::Connected(client_connection) => {
+                let msg_str = match self.encoding {
+                    Encoding::Text => packet.serialize(),
+                    Encoding::Json => packet.serialize_json(),
+                };
+                client_connection.do_send(ClientMsgString(msg_str));
             }
-            ReliablePacketIn::Msg(id, player_msg) => {
-                let expected_id = self.reliability_layer.player_msg_index + 1;
-                if id == expected_id {
-                    // We got the expected message
-                    self.reliability_layer.player_msg_index = expected_id;
-                    self.forward_message(player_msg, ctx);
-                    self.ack_message(id, ctx);
-
-                    // Process queued messages that might have been waiting
-                    self.process_queue(ctx);
-                } else if id > expected_id {
-                    // A message between this and the last one was lost. Queue this one and wait for client to resend it
-                    self.queue_message(id, player_msg);
-                    // Ack (duplicate) last successful message
-                    self.ack_message(self.reliability_layer.player_msg_index, ctx);
-                } else {
-                    // Client re-sent already known message -> maybe ack got lost -> ack but don't process
-                    self.ack_message(id, ctx);
+            ClientConnectionConnectionState::ConnectedLegacy(client_connection) => {
+                if let ReliablePacketOut::Msg { msg, .. } = packet {
+                    client_connection.do_send(ClientMsgString(msg.serialize()));
                 }
             }
+            ClientConnectionConnectionState::Disconnected => {}
         }
     }
 
-    /// Processes all player messages in the queue that can be ordered
-    /// If player_msg_index is 2 and the queue contains [4, 5, 3, 7] all except 7 will be processed
-    fn process_queue(&mut self, ctx: &mut Context<Self>) {
-        loop {
-            let mut added = false;
-            let messages: Vec<_> = self.reliability_layer.player_msg_q.drain(..).collect();
-            for queued_message in messages {
-                let expected_id = self.reliability_layer.player_msg_index + 1;
-                if queued_message.id == expected_id {
-                    self.reliability_layer.player_msg_index = expected_id;
-                    // We got the expected message. Process queued messages that might have been waiting
-                    self.forward_message(queued_message.msg, ctx);
-                    self.ack_message(queued_message.id, ctx);
-                    added = true;
-                } else {
-                    self.reliability_layer.player_msg_q.push(queued_message);
-                }
+    fn received_reliable_pkt(&mut self, msg: ReliablePacketIn, ctx: &mut Context<Self>) {
+        match msg {
+            ReliablePacketIn::Ack(id) => {
+                self.reliability_layer.acked(id);
+                self.send_due_packets(ctx);
             }
-            if !added {
-                break;
+            ReliablePacketIn::CumulativeAck(up_to, selective) => {
+                self.reliability_layer.acked_up_to(up_to, &selective);
+                self.send_due_packets(ctx);
+            }
+            ReliablePacketIn::Msg(id, player_msg) => {
+                let received = self.reliability_layer.received(id, player_msg);
+                if self.encoding == Encoding::Json {
+                    self.send_packet(self.reliability_layer.cumulative_ack());
+                }
+                match received {
+                    Received::InOrder(messages) => {
+                        for (id, player_msg) in messages {
+                            self.forward_message(player_msg, ctx);
+                            self.ack_message(id);
+                        }
+                    }
+                    // Ack (duplicate) last successful message
+                    Received::Queued => self.ack_message(self.reliability_layer.player_msg_index),
+                    Received::Duplicate => self.ack_message(id),
+                    Received::Dropped => {
+                        println!("ClientAdapter: Dropped message {} (too far ahead)", id)
+                    }
+                }
             }
         }
     }
 
-    fn ack_message(&mut self, id: usize, ctx: &mut Context<Self>) {
-        ctx.notify(ReliablePacketOut::Ack(id));
-    }
-
-    fn queue_message(&mut self, id: usize, msg: PlayerMessage) {
-        self.reliability_layer
-            .player_msg_q
-            .push(QueuedMessage::new(id, msg.clone()));
+    /// Individual acks for the text protocol, JSON clients get cumulative acks
+    fn ack_message(&self, id: usize) {
+        if self.encoding == Encoding::Text {
+            self.send_packet(ReliablePacketOut::Ack(id));
+        }
     }
 
     fn forward_message(&mut self, msg: PlayerMessage, ctx: &mut Context<Self>) {
@@ -215,7 +389,11 @@ impl Handler<ClientMsgString> for ClientAdapter {
         use ClientConnectionConnectionState::*;
         match self.client_connection {
             Connected(_) | Disconnected => {
-                match ReliablePacketIn::parse(&msg.0) {
+                let parsed = match self.encoding {
+                    Encoding::Text => ReliablePacketIn::parse(&msg.0),
+                    Encoding::Json => ReliablePacketIn::parse_json(&msg.0),
+                };
+                match parsed {
                     Ok(msg) => self.received_reliable_pkt(msg, ctx),
                     Err(reliability_err) => {
                         // TODO!
@@ -236,69 +414,22 @@ impl Handler<ClientMsgString> for ClientAdapter {
 impl Handler<ServerMessage> for ClientAdapter {
     type Result = Result<(), ()>;
     fn handle(&mut self, msg: ServerMessage, ctx: &mut Self::Context) -> Self::Result {
-        self.reliability_layer.server_msg_index += 1;
-        ctx.notify(ReliablePacketOut::Msg {
-            id: self.reliability_layer.server_msg_index,
-            msg,
-            retry_count: 0,
-        });
+        if let ClientConnectionConnectionState::ConnectedLegacy(_) = self.client_connection {
+            self.send_packet(ReliablePacketOut::Msg { id: 0, msg });
+        } else if self.reliability_layer.push(msg) {
+            self.send_due_packets(ctx);
+        } else {
+            println!("ClientAdapter: Client fell too far behind, dropping it");
+            ctx.stop();
+        }
         Ok(())
     }
 }
 
 impl Handler<ReliablePacketOut> for ClientAdapter {
     type Result = ();
-    fn handle(&mut self, msg: ReliablePacketOut, ctx: &mut Self::Context) {
-        match &self.client_connection {
-            ClientConnectionConnectionState::Connected(client_connection) => {
-                if let ReliablePacketOut::Msg {
-                    id,
-                    msg,
-                    retry_count,
-                } = msg.clone()
-                {
-                    if retry_count > RPKT_RETRY_LIMIT {
-                        ctx.stop();
-                        return;
-                    }
-                    self.reliability_layer.server_msg_q.push(QueuedMessage {
-                        id,
-                        msg: msg.clone(),
-                        sent: Instant::now(),
-                        retry_count: retry_count + 1,
-                    });
-                }
-
-                let msg_str = msg.serialize();
-                client_connection.do_send(ClientMsgString(msg_str));
-            }
-            ClientConnectionConnectionState::ConnectedLegacy(client_connection) => {
-                if let ReliablePacketOut::Msg {
-                    id: _,
-                    msg,
-                    retry_count: _,
-                } = msg.clone()
-                {
-                    let msg_str = msg.serialize();
-                    client_connection.do_send(ClientMsgString(msg_str));
-                }
-            }
-            ClientConnectionConnectionState::Disconnected => {
-                if let ReliablePacketOut::Msg {
-                    id,
-                    msg,
-                    retry_count,
-                } = msg.clone()
-                {
-                    self.reliability_layer.server_msg_q.push(QueuedMessage {
-                        id,
-                        msg: msg.clone(),
-                        sent: Instant::now(),
-                        retry_count,
-                    });
-                }
-            }
-        }
+    fn handle(&mut self, msg: ReliablePacketOut, _ctx: &mut Self::Context) {
+        self.send_packet(msg);
     }
 }
 
@@ -310,8 +441,26 @@ impl Handler<ClientStateMessage> for ClientAdapter {
     }
 }
 
+/// `None` for legacy clients, they can't reconnect to their session
+pub struct SnapshotReliability;
+
+impl Message for SnapshotReliability {
+    type Result = Option<ReliabilitySnapshot>;
+}
+
+impl Handler<SnapshotReliability> for ClientAdapter {
+    type Result = Option<ReliabilitySnapshot>;
+    fn handle(&mut self, _: SnapshotReliability, _: &mut Self::Context) -> Self::Result {
+        if let ClientConnectionConnectionState::ConnectedLegacy(_) = self.client_connection {
+            None
+        } else {
+            Some(self.reliability_layer.snapshot())
+        }
+    }
+}
+
 pub enum ClientAdapterMsg {
-    Connect(Addr<ClientConnection>),
+    Connect(Addr<ClientConnection>, Encoding), // The client may have been updated in the meantime
     Disconnect,
     Close,
 }
@@ -323,9 +472,12 @@ impl Handler<ClientAdapterMsg> for ClientAdapter {
     fn handle(&mut self, msg: ClientAdapterMsg, ctx: &mut Self::Context) -> Self::Result {
         // print!("ClientAdapter recv ClientAdapterMsg: ");
         match msg {
-            ClientAdapterMsg::Connect(client_connection_addr) => {
+            ClientAdapterMsg::Connect(client_connection_addr, encoding) => {
                 self.client_connection =
                     ClientConnectionConnectionState::Connected(client_connection_addr);
+                self.encoding = encoding;
+                self.reliability_layer.resend_all(Instant::now());
+                self.send_due_packets(ctx);
             }
             ClientAdapterMsg::Disconnect => {
                 self.client_connection = ClientConnectionConnectionState::Disconnected;
@@ -359,3 +511,181 @@ impl Actor for ClientAdapter {
         Running::Stop
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent_ids(packets: &[ReliablePacketOut]) -> Vec<usize> {
+        packets
+            .iter()
+            .map(|packet| match packet {
+                ReliablePacketOut::Msg { id, .. } => *id,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    fn layer_with_messages(count: usize) -> ReliabilityLayer {
+        let mut layer = ReliabilityLayer::default();
+        for column in 0..count {
+            assert!(layer.push(ServerMessage::PlaceChip(column)));
+        }
+        layer
+    }
+
+    #[test]
+    fn window_is_bounded() {
+        let now = Instant::now();
+        let mut layer = layer_with_messages(SEND_WINDOW + 3);
+        let packets = layer.due_packets(now).unwrap();
+        assert_eq!(sent_ids(&packets), (1..=SEND_WINDOW).collect::<Vec<_>>());
+        assert!(layer.due_packets(now).unwrap().is_empty());
+
+        layer.acked(2);
+        assert_eq!(
+            sent_ids(&layer.due_packets(now).unwrap()),
+            vec![SEND_WINDOW + 1]
+        );
+        layer.acked_up_to(10, &[20, 21]);
+        assert_eq!(
+            sent_ids(&layer.due_packets(now).unwrap()),
+            vec![SEND_WINDOW + 2, SEND_WINDOW + 3]
+        );
+        assert_eq!(layer.server_msg_q.len(), SEND_WINDOW - 9);
+    }
+
+    #[test]
+    fn acks_of_unsent_messages_are_ignored() {
+        let now = Instant::now();
+        let mut layer = layer_with_messages(SEND_WINDOW + 1);
+        assert_eq!(layer.due_packets(now).unwrap().len(), SEND_WINDOW);
+        layer.acked_up_to(usize::MAX, &[usize::MAX]);
+        assert!(layer.server_msg_q.is_empty());
+        // The message still in the backlog wasn't acknowledged
+        assert_eq!(
+            sent_ids(&layer.due_packets(now).unwrap()),
+            vec![SEND_WINDOW + 1]
+        );
+    }
+
+    #[test]
+    fn backlog_is_bounded() {
+        let mut layer = layer_with_messages(SEND_BACKLOG_LIMIT);
+        assert!(!layer.push(ServerMessage::LobbyClosing));
+    }
+
+    #[test]
+    fn resends_with_backoff() {
+        let start = Instant::now();
+        let mut layer = layer_with_messages(1);
+        assert_eq!(sent_ids(&layer.due_packets(start).unwrap()), vec![1]);
+
+        let mut elapsed = Duration::from_millis(0);
+        for retry_count in 0..RPKT_RETRY_LIMIT {
+            let timeout = resend_timeout(retry_count);
+            assert!(timeout <= Duration::from_millis(MAX_RESEND_TIMEOUT_MS));
+            elapsed += timeout;
+            let almost = start + elapsed - Duration::from_millis(1);
+            assert!(layer.due_packets(almost).unwrap().is_empty());
+            assert_eq!(
+                sent_ids(&layer.due_packets(start + elapsed).unwrap()),
+                vec![1]
+            );
+        }
+        assert_eq!(resend_timeout(1), Duration::from_millis(1400));
+        elapsed += resend_timeout(RPKT_RETRY_LIMIT);
+        assert!(layer.due_packets(start + elapsed).is_err());
+    }
+
+    #[test]
+    fn resends_everything_after_reconnect() {
+        let now = Instant::now();
+        let mut layer = layer_with_messages(3);
+        assert_eq!(layer.due_packets(now).unwrap().len(), 3);
+        layer.acked(2);
+        let later = now + Duration::from_millis(10);
+        layer.resend_all(later);
+        assert_eq!(sent_ids(&layer.due_packets(later).unwrap()), vec![1, 3]);
+    }
+
+    #[test]
+    fn orders_player_messages() {
+        let mut layer = ReliabilityLayer::default();
+        assert_eq!(layer.received(2, PlayerMessage::Resign), Received::Queued);
+        assert_eq!(layer.received(4, PlayerMessage::Logout), Received::Queued);
+        assert_eq!(
+            layer.received(2, PlayerMessage::Resign),
+            Received::Duplicate
+        );
+        match layer.cumulative_ack() {
+            ReliablePacketOut::CumulativeAck(0, selective) => assert_eq!(selective, vec![2, 4]),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            layer.received(1, PlayerMessage::PlaceChip(3)),
+            Received::InOrder(vec![
+                (1, PlayerMessage::PlaceChip(3)),
+                (2, PlayerMessage::Resign)
+            ])
+        );
+        assert_eq!(
+            layer.received(1, PlayerMessage::PlaceChip(3)),
+            Received::Duplicate
+        );
+        assert_eq!(
+            layer.received(3, PlayerMessage::ChatRead),
+            Received::InOrder(vec![
+                (3, PlayerMessage::ChatRead),
+                (4, PlayerMessage::Logout)
+            ])
+        );
+        assert!(layer.player_msg_q.is_empty());
+    }
+
+    #[test]
+    fn player_queue_is_bounded() {
+        let mut layer = ReliabilityLayer::default();
+        for id in 2..=PLAYER_MSG_Q_LIMIT + 1 {
+            assert_eq!(
+                layer.received(id, PlayerMessage::ChatRead),
+                Received::Queued
+            );
+        }
+        assert_eq!(
+            layer.received(PLAYER_MSG_Q_LIMIT + 2, PlayerMessage::ChatRead),
+            Received::Dropped
+        );
+        assert_eq!(layer.player_msg_q.len(), PLAYER_MSG_Q_LIMIT);
+    }
+
+    #[test]
+    fn snapshot_keeps_ids() {
+        let now = Instant::now();
+        let mut layer = layer_with_messages(SEND_WINDOW + 2);
+        layer.due_packets(now).unwrap();
+        layer.acked_up_to(3, &[5]);
+        layer.received(1, PlayerMessage::ChatRead);
+
+        let mut restored = ReliabilityLayer::restore(layer.snapshot(), now).unwrap();
+        assert_eq!(restored.player_msg_index, 1);
+        assert_eq!(
+            restored.received(2, PlayerMessage::ChatRead),
+            Received::InOrder(vec![(2, PlayerMessage::ChatRead)])
+        );
+        let packets = restored.due_packets(now).unwrap();
+        let mut expected = vec![4];
+        expected.extend(6..=SEND_WINDOW + 2);
+        assert_eq!(sent_ids(&packets), expected);
+        for packet in packets {
+            // The backlog got the ids following the window
+            if let ReliablePacketOut::Msg {
+                id,
+                msg: ServerMessage::PlaceChip(column),
+            } = packet
+            {
+                assert_eq!(id, column + 1);
+            }
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use super::{
-    client_adapter::{ClientAdapter, ClientMsgString, MIN_VERSION},
+    client_adapter::{ClientAdapter, ClientMsgString, Encoding, MIN_VERSION},
     connection_mgr::{ConnectionManager, NewAdapterAdresses, WSSessionToken},
     msg::{HelloOut, PlayerMessage},
 };
@@ -92,6 +92,7 @@ impl ClientConnection {
                         return;
                     }
 
+                    let encoding = Encoding::for_version(hello.protocol_version);
                     if let Some(session_token) = hello.maybe_session_token {
                         self.connection_mgr
                             .do_send(ConnectionManagerMsg::RequestAdapterExisting(
@@ -101,6 +102,7 @@ impl ClientConnection {
                                     user_mgr: self.user_mgr.clone(),
                                 },
                                 session_token.to_string(),
+                                encoding,
                             ));
                     } else {
                         self.connection_mgr
@@ -110,6 +112,7 @@ impl ClientConnection {
                                     lobby_mgr: self.lobby_mgr.clone(),
                                     user_mgr: self.user_mgr.clone(),
                                 },
+                                encoding,
                             ))
                     }
                     self.connection_state = ClientAdapterConnectionState::Pending;
@@ -207,7 +210,7 @@ pub enum ClientConnectionMsg {
 }
 
 pub enum ConnectionType {
-    Reliable { is_new: bool }, // is_new
+    Reliable { is_new: bool, encoding: Encoding },
     Legacy,
 }
 
@@ -225,12 +228,17 @@ impl Handler<ClientConnectionMsg> for ClientConnection {
                 client_adapter,
                 connection_type,
             } => match connection_type {
-                ConnectionType::Reliable { is_new } => {
+                ConnectionType::Reliable { is_new, encoding } => {
                     self.connection_state = ClientAdapterConnectionState::Connected(
                         session_token.clone(),
                         client_adapter,
                     );
-                    self.text(ctx, HelloOut::Ok(session_token, is_new).serialize());
+                    let hello = HelloOut::Ok(session_token, is_new);
+                    let hello_str = match encoding {
+                        Encoding::Text => hello.serialize(),
+                        Encoding::Json => hello.serialize_json(),
+                    };
+                    self.text(ctx, hello_str);
                 }
                 ConnectionType::Legacy => {
                     self.connection_state = ClientAdapterConnectionState::ConnectedLegacy(
@@ -5,10 +5,11 @@ use super::{
     connection_mgr::ConnectionManager,
 };
 use super::{connection_mgr::ConnectionManagerMsg, game_info::*};
-use super::{connection_mgr::WSSessionToken, lobby::*};
+use super::{connection_mgr::WSSessionToken, lobby::*, snapshot::LobbySnapshot};
 use crate::{
     api::users::{
-        user::PublicUserMe,
+        session_token::SessionId,
+        user::{PublicUserMe, UserId},
         user_mgr::{self, UserManager},
     },
     logging::*,
@@ -26,13 +27,16 @@ pub struct ClientState {
     backlinked_state: BacklinkState,
     lobby_state: ClientLobbyState,
     maybe_user_info: Option<PublicUserMe>,
+    maybe_session_id: Option<SessionId>, // The login session of `maybe_user_info`
 }
 
 #[derive(Clone)]
 pub enum ClientLobbyState {
     Idle,
+    InQueue,
     InLobbyWaitingForHost { player: Player, lobby: Addr<Lobby> },
     InLobby { player: Player, lobby: Addr<Lobby> },
+    Spectating { lobby: Addr<Lobby> },
 }
 
 impl ClientState {
@@ -52,18 +56,34 @@ impl ClientState {
             backlinked_state: BacklinkState::Waiting,
             lobby_state: ClientLobbyState::Idle,
             maybe_user_info: None,
+            maybe_session_id: None,
         }
     }
 
+    fn log_out(&mut self, ctx: &mut Context<Self>) {
+        if let Some(user_info) = self.maybe_user_info.clone() {
+            self.user_mgr
+                .do_send(user_mgr::msg::IntUserMgrMsg::StopPlaying(
+                    user_info.id,
+                    ctx.address(),
+                ));
+        }
+        self.maybe_user_info = None;
+        self.maybe_session_id = None;
+    }
+
     fn received_lobby_request_response(
         &mut self,
-        res: Result<Result<LobbyRequestResponse, Option<SrvMsgError>>, MailboxError>,
+        res: Result<Result<Option<LobbyRequestResponse>, Option<SrvMsgError>>, MailboxError>,
 
         client_adapter_addr: Addr<ClientAdapter>,
     ) {
         match res {
             Ok(lobby_request_response_result) => match lobby_request_response_result {
-                Ok(lobby_request_response) => match lobby_request_response {
+                Ok(None) => {
+                    self.lobby_state = ClientLobbyState::InQueue;
+                }
+                Ok(Some(lobby_request_response)) => match lobby_request_response {
                     LobbyRequestResponse {
                         player,
                         lobby_addr,
@@ -102,9 +122,16 @@ enum BacklinkState {
 pub enum ClientStateMessage {
     BackLink(Addr<ClientAdapter>),
     Reset,
-    Close, // Triggered by client timeout or disconnect
+    Close,          // Triggered by client timeout or disconnect
+    ConnectionLost, // The client may still come back with its session token
+    Reconnected,
     BattleReqJoinLobby(Addr<Lobby>),
+    QueueMatched(Player, Addr<Lobby>), // Player one hosts the lobby, player two waits for the host
+    TournamentMatched(Player, Addr<Lobby>, String, GameId), // as above, tournament id
     CurrentServerState(usize, bool, bool), // connected players, someone wants to play, [internal: was requeued]
+    RestoreLogin(UserId, Option<SessionId>), // The session was logged in before a restart
+    RestoredLobby(Player, Addr<Lobby>),
+    SessionsRevoked(Vec<SessionId>), // Logged out if the own login session is among them
 }
 
 impl Handler<ClientStateMessage> for ClientState {
@@ -118,6 +145,31 @@ impl Handler<ClientStateMessage> for ClientState {
                 }
             }
             Close => {
+                match &self.lobby_state {
+                    ClientLobbyState::InLobby {
+                        player,
+                        lobby: lobby_addr,
+                    } => {
+                        lobby_addr.do_send(ClientLobbyMessageNamed {
+                            sender: *player,
+                            msg: ClientLobbyMessage::PlayerLeaving {
+                                reason: PlayerLeaveReason::Disconnect,
+                            },
+                        });
+                    }
+                    ClientLobbyState::InQueue => {
+                        self.lobby_mgr
+                            .do_send(LobbyManagerMsg::LeaveQueue(ctx.address()));
+                    }
+                    ClientLobbyState::Spectating { lobby } => {
+                        lobby.do_send(LobbyMessage::SpectatorLeft(ctx.address()));
+                    }
+                    _ => {}
+                }
+                self.lobby_state = ClientLobbyState::Idle;
+                ctx.stop();
+            }
+            ConnectionLost | Reconnected => {
                 if let ClientLobbyState::InLobby {
                     player,
                     lobby: lobby_addr,
@@ -125,17 +177,80 @@ impl Handler<ClientStateMessage> for ClientState {
                 {
                     lobby_addr.do_send(ClientLobbyMessageNamed {
                         sender: *player,
-                        msg: ClientLobbyMessage::PlayerLeaving {
-                            reason: PlayerLeaveReason::Disconnect,
+                        msg: match msg {
+                            ConnectionLost => ClientLobbyMessage::ConnectionLost,
+                            _ => ClientLobbyMessage::Reconnected,
                         },
                     });
-                    self.lobby_state = ClientLobbyState::Idle;
                 }
-                ctx.stop();
             }
             Reset => {
                 self.lobby_state = ClientLobbyState::Idle;
             }
+            QueueMatched(player, lobby) => {
+                if let ClientLobbyState::InQueue = self.lobby_state {
+                    self.lobby_state = match player {
+                        Player::One => ClientLobbyState::InLobby { player, lobby },
+                        Player::Two => ClientLobbyState::InLobbyWaitingForHost { player, lobby },
+                    };
+                } else {
+                    // Left the queue while being matched
+                    lobby.do_send(ClientLobbyMessageNamed {
+                        sender: player,
+                        msg: ClientLobbyMessage::PlayerLeaving {
+                            reason: PlayerLeaveReason::Leave,
+                        },
+                    });
+                }
+            }
+            TournamentMatched(player, lobby, tournament_id, game_id) => {
+                if let ClientLobbyState::Idle = self.lobby_state {
+                    ctx.notify(ServerMessage::TournamentMatch(tournament_id, game_id));
+                    self.lobby_state = match player {
+                        Player::One => ClientLobbyState::InLobby { player, lobby },
+                        Player::Two => ClientLobbyState::InLobbyWaitingForHost { player, lobby },
+                    };
+                } else {
+                    // Busy with another game, the tournament tries again later
+                    lobby.do_send(ClientLobbyMessageNamed {
+                        sender: player,
+                        msg: ClientLobbyMessage::PlayerLeaving {
+                            reason: PlayerLeaveReason::Leave,
+                        },
+                    });
+                }
+            }
+            RestoreLogin(user_id, maybe_session_id) => {
+                self.user_mgr
+                    .send(user_mgr::msg::ResumePlaying {
+                        user_id,
+                        addr: ctx.address(),
+                    })
+                    .into_actor(self)
+                    .then(|res, act, _| {
+                        if let Ok(Ok(user)) = res {
+                            act.maybe_user_info = Some(user);
+                            act.maybe_session_id = maybe_session_id;
+                        }
+                        fut::ready(())
+                    })
+                    .wait(ctx);
+            }
+            RestoredLobby(player, lobby) => {
+                self.lobby_state = ClientLobbyState::InLobby { player, lobby };
+            }
+            SessionsRevoked(session_ids) => {
+                let revoked = match &self.maybe_session_id {
+                    Some(session_id) => session_ids.contains(session_id),
+                    None => false,
+                };
+                if revoked {
+                    self.log_out(ctx);
+                    if let BacklinkState::Linked(ref client_adapter_addr) = self.backlinked_state {
+                        client_adapter_addr.do_send(ServerMessage::SessionRevoked);
+                    }
+                }
+            }
             BattleReqJoinLobby(addr) => {
                 if let BacklinkState::Linked(_) = self.backlinked_state {
                     self.lobby_state = ClientLobbyState::InLobby {
@@ -207,6 +322,30 @@ impl Handler<PlayerMessage> for ClientState {
                         err
                     }
                 }
+                Resign | OfferDraw | AcceptDraw | DeclineDraw | BoardStateRequest => {
+                    if let ClientLobbyState::InLobby {
+                        player,
+                        lobby: lobby_addr,
+                    } = &self.lobby_state
+                    {
+                        let msg = match msg {
+                            Resign => ClientLobbyMessage::Resign,
+                            OfferDraw => ClientLobbyMessage::OfferDraw,
+                            AcceptDraw => ClientLobbyMessage::AcceptDraw,
+                            DeclineDraw => ClientLobbyMessage::DeclineDraw,
+                            _ => ClientLobbyMessage::BoardStateRequest,
+                        };
+                        lobby_addr.do_send(ClientLobbyMessageNamed {
+                            sender: *player,
+                            msg,
+                        });
+                        ok
+                    } else {
+                        client_adapter_addr
+                            .do_send(ServerMessage::Error(Some(SrvMsgError::NotInLobby)));
+                        err
+                    }
+                }
                 Leaving => {
                     match &self.lobby_state {
                         ClientLobbyState::InLobby {
@@ -228,6 +367,13 @@ impl Handler<PlayerMessage> for ClientState {
                                 // TODO: Lobby is dead. Send okay or error here?
                             }
                         }
+                        ClientLobbyState::InQueue => {
+                            self.lobby_mgr
+                                .do_send(LobbyManagerMsg::LeaveQueue(ctx.address()));
+                        }
+                        ClientLobbyState::Spectating { lobby } => {
+                            lobby.do_send(LobbyMessage::SpectatorLeft(ctx.address()));
+                        }
                         ClientLobbyState::Idle => {
                             client_adapter_addr
                                 .do_send(ServerMessage::Error(Some(SrvMsgError::NotInLobby)));
@@ -240,13 +386,17 @@ impl Handler<PlayerMessage> for ClientState {
                     self.lobby_state = ClientLobbyState::Idle;
                     ok
                 }
-                LobbyRequest(kind) => {
+                LobbyRequest(kind, settings) => {
                     if let ClientLobbyState::Idle = &self.lobby_state {
                         self.lobby_mgr
                             .send(lobby_mgr::LobbyRequest::NewLobby(
                                 ctx.address(),
-                                self.maybe_user_info.clone().map(|u| u.id),
+                                self.maybe_user_info.as_ref().map(|u| u.id),
+                                self.maybe_user_info
+                                    .as_ref()
+                                    .map(|u| u.game_info.skill_rating),
                                 kind,
+                                settings,
                             ))
                             .into_actor(self)
                             .then(move |res, act, _ctx| {
@@ -284,6 +434,48 @@ impl Handler<PlayerMessage> for ClientState {
                         err
                     }
                 }
+                Spectate(id) => {
+                    if let ClientLobbyState::Idle = &self.lobby_state {
+                        let user_id = match &self.maybe_user_info {
+                            Some(user_info) => user_info.id,
+                            None => {
+                                client_adapter_addr
+                                    .do_send(ServerMessage::Error(Some(SrvMsgError::NotLoggedIn)));
+                                return err;
+                            }
+                        };
+                        self.lobby_mgr
+                            .send(lobby_mgr::SpectateRequest {
+                                game_id: id,
+                                addr: ctx.address(),
+                                user_id,
+                            })
+                            .into_actor(self)
+                            .then(move |res, act, _ctx| {
+                                match res {
+                                    Ok(Ok(lobby)) => {
+                                        act.lobby_state = ClientLobbyState::Spectating { lobby };
+                                    }
+                                    Ok(Err(srv_msg_err)) => {
+                                        client_adapter_addr
+                                            .do_send(ServerMessage::Error(Some(srv_msg_err)));
+                                    }
+                                    Err(_) => {
+                                        client_adapter_addr.do_send(ServerMessage::Error(Some(
+                                            SrvMsgError::Internal,
+                                        )));
+                                    }
+                                }
+                                fut::ready(())
+                            })
+                            .wait(ctx);
+                        ok
+                    } else {
+                        client_adapter_addr
+                            .do_send(ServerMessage::Error(Some(SrvMsgError::AlreadyInLobby)));
+                        err
+                    }
+                }
                 Login(session_token) => {
                     if let ClientLobbyState::InLobby {
                         player: _,
@@ -310,9 +502,10 @@ impl Handler<PlayerMessage> for ClientState {
                         .then(move |res, act, _| {
                             if let Ok(maybe_id) = res {
                                 match maybe_id {
-                                    Ok(user) => {
+                                    Ok((user, session_id)) => {
                                         // println!("Start playing! user: {:?}", user);
                                         act.maybe_user_info = Some(user);
+                                        act.maybe_session_id = Some(session_id);
                                         client_adapter_addr.do_send(ServerMessage::LoginResponse {
                                             success: true,
                                         });
@@ -340,14 +533,7 @@ impl Handler<PlayerMessage> for ClientState {
                 }
 
                 Logout => {
-                    if let Some(user_info) = self.maybe_user_info.clone() {
-                        self.user_mgr
-                            .do_send(user_mgr::msg::IntUserMgrMsg::StopPlaying(
-                                user_info.id,
-                                ctx.address(),
-                            ));
-                    }
-                    self.maybe_user_info = None;
+                    self.log_out(ctx);
                     ok
                 }
 
@@ -427,6 +613,12 @@ impl Handler<ServerMessage> for ClientState {
     type Result = Result<(), ()>;
 
     fn handle(&mut self, msg: ServerMessage, _: &mut Self::Context) -> Self::Result {
+        if let (ServerMessage::RatingChange(_, rating), Some(user_info)) =
+            (&msg, &mut self.maybe_user_info)
+        {
+            // Keep the rating used for matchmaking up to date
+            user_info.game_info.skill_rating = *rating;
+        }
         if let BacklinkState::Linked(ref adapter) = self.backlinked_state {
             adapter.do_send(msg);
             Ok(())
@@ -449,6 +641,52 @@ impl Message for ClientStateMessage {
     type Result = Result<(), ()>;
 }
 
+/// The user and the lobby of the client, the lobby only if a game is played in it
+pub struct SnapshotClient;
+
+impl Message for SnapshotClient {
+    type Result = (
+        Option<UserId>,
+        Option<SessionId>,
+        Option<(Player, LobbySnapshot)>,
+    );
+}
+
+impl Handler<SnapshotClient> for ClientState {
+    type Result = ResponseActFuture<
+        Self,
+        (
+            Option<UserId>,
+            Option<SessionId>,
+            Option<(Player, LobbySnapshot)>,
+        ),
+    >;
+
+    fn handle(&mut self, _: SnapshotClient, _ctx: &mut Self::Context) -> Self::Result {
+        let maybe_user_id = self.maybe_user_info.as_ref().map(|user| user.id);
+        let maybe_session_id = self.maybe_session_id.clone();
+        let maybe_lobby = match &self.lobby_state {
+            ClientLobbyState::InLobby { player, lobby } => Some((*player, lobby.clone())),
+            _ => None,
+        };
+        Box::pin(
+            async move {
+                let maybe_lobby_snapshot = match maybe_lobby {
+                    Some((player, lobby)) => lobby
+                        .send(SnapshotLobby)
+                        .await
+                        .ok()
+                        .flatten()
+                        .map(|snapshot| (player, snapshot)),
+                    None => None,
+                };
+                (maybe_user_id, maybe_session_id, maybe_lobby_snapshot)
+            }
+            .into_actor(self),
+        )
+    }
+}
+
 impl Handler<LobbyRequestResponseReady> for ClientState {
     type Result = ();
 
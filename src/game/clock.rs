@@ -0,0 +1,230 @@
+//! Chess-style game clocks. Times are in milliseconds since the unix epoch, see `now_millis`.
+
+use super::game_info::Player;
+
+use serde::{Deserialize, Serialize};
+
+const MAX_INITIAL_S: i64 = 60 * 60;
+const MAX_INCREMENT_S: i64 = 60;
+const MAX_MOVE_S: i64 = 10 * 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TimeControl {
+    #[default]
+    Unlimited,
+    /// Initial time plus an increment for every move
+    Fischer {
+        initial_s: i64,
+        increment_s: i64,
+    },
+    /// A fixed amount of time for every move, unused time is lost
+    PerMove {
+        move_s: i64,
+    },
+}
+
+impl TimeControl {
+    /// "300+5" for five minutes with five seconds per move
+    pub fn parse_fischer(text: &str) -> Option<TimeControl> {
+        let parts: Vec<_> = text.splitn(2, '+').collect();
+        let initial_s: i64 = parts[0].parse().ok()?;
+        let increment_s: i64 = match parts.get(1) {
+            Some(increment) => increment.parse().ok()?,
+            None => 0,
+        };
+        TimeControl::fischer(initial_s, increment_s)
+    }
+
+    pub fn parse_per_move(text: &str) -> Option<TimeControl> {
+        TimeControl::per_move(text.parse().ok()?)
+    }
+
+    pub fn fischer(initial_s: i64, increment_s: i64) -> Option<TimeControl> {
+        if (1..=MAX_INITIAL_S).contains(&initial_s) && (0..=MAX_INCREMENT_S).contains(&increment_s)
+        {
+            Some(TimeControl::Fischer {
+                initial_s,
+                increment_s,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn per_move(move_s: i64) -> Option<TimeControl> {
+        if (1..=MAX_MOVE_S).contains(&move_s) {
+            Some(TimeControl::PerMove { move_s })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Clock {
+    time_control: TimeControl,
+    remaining_ms: [i64; 2],
+    running: Option<(Player, i64)>, // player whose time is running and since when
+}
+
+impl Clock {
+    pub fn new(time_control: TimeControl) -> Clock {
+        let initial_ms = match time_control {
+            TimeControl::Unlimited => 0,
+            TimeControl::Fischer { initial_s, .. } => initial_s * 1000,
+            TimeControl::PerMove { move_s } => move_s * 1000,
+        };
+        Clock {
+            time_control,
+            remaining_ms: [initial_ms; 2],
+            running: None,
+        }
+    }
+
+    /// A stopped clock with the given remaining times of player one and two
+    pub fn restore(time_control: TimeControl, remaining_ms: [i64; 2]) -> Clock {
+        Clock {
+            time_control,
+            remaining_ms,
+            running: None,
+        }
+    }
+
+    pub fn time_control(&self) -> TimeControl {
+        self.time_control
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.time_control == TimeControl::Unlimited
+    }
+
+    pub fn start(&mut self, player: Player, now: i64) {
+        self.running = Some((player, now));
+    }
+
+    /// Ends the turn of the running player and starts the opponent's
+    pub fn press(&mut self, now: i64) {
+        if let Some((player, since)) = self.running {
+            let index = player.select(0, 1);
+            match self.time_control {
+                TimeControl::Unlimited => {}
+                TimeControl::Fischer { increment_s, .. } => {
+                    self.remaining_ms[index] -= now - since;
+                    self.remaining_ms[index] += increment_s * 1000;
+                }
+                TimeControl::PerMove { move_s } => self.remaining_ms[index] = move_s * 1000,
+            }
+            self.running = Some((player.other(), now));
+        }
+    }
+
+    /// Stops the running player's time, `start` continues where it was stopped
+    pub fn stop(&mut self, now: i64) {
+        if let Some((player, since)) = self.running.take() {
+            if !self.is_unlimited() {
+                self.remaining_ms[player.select(0, 1)] -= now - since;
+            }
+        }
+    }
+
+    pub fn remaining_ms(&self, player: Player, now: i64) -> i64 {
+        let mut remaining = self.remaining_ms[player.select(0, 1)];
+        if let Some((running, since)) = self.running {
+            if running == player {
+                remaining -= now - since;
+            }
+        }
+        remaining.max(0)
+    }
+
+    /// The player whose time has run out, if any
+    pub fn flagged(&self, now: i64) -> Option<Player> {
+        if self.is_unlimited() {
+            return None;
+        }
+        self.running
+            .map(|(player, _)| player)
+            .filter(|player| self.remaining_ms(*player, now) == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_time_controls() {
+        assert_eq!(
+            TimeControl::parse_fischer("300+5"),
+            Some(TimeControl::Fischer {
+                initial_s: 300,
+                increment_s: 5
+            })
+        );
+        assert_eq!(
+            TimeControl::parse_fischer("60"),
+            Some(TimeControl::Fischer {
+                initial_s: 60,
+                increment_s: 0
+            })
+        );
+        assert_eq!(TimeControl::parse_fischer("0+5"), None);
+        assert_eq!(TimeControl::parse_fischer("60+x"), None);
+        assert_eq!(
+            TimeControl::parse_per_move("30"),
+            Some(TimeControl::PerMove { move_s: 30 })
+        );
+        assert_eq!(TimeControl::parse_per_move("100000"), None);
+    }
+
+    #[test]
+    fn fischer_adds_increment() {
+        let mut clock = Clock::new(TimeControl::Fischer {
+            initial_s: 10,
+            increment_s: 2,
+        });
+        clock.start(Player::One, 0);
+        assert_eq!(clock.remaining_ms(Player::One, 3_000), 7_000);
+        clock.press(3_000);
+        assert_eq!(clock.remaining_ms(Player::One, 5_000), 9_000);
+        assert_eq!(clock.remaining_ms(Player::Two, 5_000), 8_000);
+    }
+
+    #[test]
+    fn per_move_resets_every_turn() {
+        let mut clock = Clock::new(TimeControl::PerMove { move_s: 5 });
+        clock.start(Player::Two, 0);
+        clock.press(4_000);
+        assert_eq!(clock.remaining_ms(Player::Two, 4_000), 5_000);
+        assert_eq!(clock.flagged(8_999), None);
+        assert_eq!(clock.flagged(9_000), Some(Player::One));
+    }
+
+    #[test]
+    fn unlimited_never_flags() {
+        let mut clock = Clock::new(TimeControl::Unlimited);
+        clock.start(Player::One, 0);
+        assert_eq!(clock.flagged(i64::MAX / 2), None);
+    }
+
+    #[test]
+    fn stopped_clock_does_not_run() {
+        let mut clock = Clock::new(TimeControl::Fischer {
+            initial_s: 10,
+            increment_s: 0,
+        });
+        clock.start(Player::One, 0);
+        clock.stop(4_000);
+        assert_eq!(clock.remaining_ms(Player::One, 60_000), 6_000);
+        assert_eq!(clock.flagged(60_000), None);
+    }
+
+    #[test]
+    fn paused_per_move_clock_keeps_elapsed_time() {
+        let mut clock = Clock::new(TimeControl::PerMove { move_s: 10 });
+        clock.start(Player::One, 0);
+        clock.stop(4_000);
+        clock.start(Player::One, 30_000);
+        assert_eq!(clock.remaining_ms(Player::One, 32_000), 4_000);
+    }
+}
@@ -1,21 +1,29 @@
 use actix::*;
+use futures::future::join_all;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use std::{collections::HashMap, time::Instant};
 
 use super::{
-    client_adapter::{ClientAdapter, ClientAdapterMsg, ClientMsgString},
+    client_adapter::{
+        ClientAdapter, ClientAdapterMsg, ClientMsgString, Encoding, SnapshotReliability,
+    },
     client_connection::{ClientConnectionMsg, ConnectionType},
-    client_state::{ClientState, ClientStateMessage},
-    lobby_mgr::LobbyManager,
+    client_state::{ClientState, ClientStateMessage, SnapshotClient},
+    lobby_mgr::{LobbyManager, RestoreLobby},
+    snapshot::{ServerSnapshot, SessionSnapshot},
     ClientConnection,
 };
-use crate::{api::users::user_mgr::UserManager, game::msg::ServerMessage, logging::Logger};
+use crate::{
+    api::users::user_mgr::UserManager,
+    game::{game_info::Player, msg::ServerMessage},
+    logging::Logger,
+};
 
 pub type WSSessionToken = String;
 
 const SEND_SERVER_INFO_INTERVAL_SECONDS: u64 = 2;
 
-const CONNECTION_KEEPALIVE_SECONDS: u64 = 30;
+pub const CONNECTION_KEEPALIVE_SECONDS: u64 = 30;
 
 enum BacklinkState {
     Linked(Addr<LobbyManager>),
@@ -27,6 +35,7 @@ pub struct ConnectionManager {
     connections: HashMap<WSSessionToken, Connection>,
     player_in_queue: bool,
     send_server_info_batched: bool,
+    restarting_at: Option<Instant>, // Set while the server is drained before a restart
     logger: Addr<Logger>,
 }
 
@@ -37,6 +46,7 @@ impl ConnectionManager {
             connections: HashMap::new(),
             player_in_queue: false,
             send_server_info_batched: false,
+            restarting_at: None,
             logger,
         }
     }
@@ -93,11 +103,24 @@ impl ConnectionManager {
         }
     }
 
+    /// Tells a newly connected client that the server is about to restart
+    fn send_restarting(&self, adapter_addr: &Addr<ClientAdapter>) {
+        if let Some(restarting_at) = self.restarting_at {
+            adapter_addr.do_send(ServerMessage::ServerRestarting(seconds_until(
+                restarting_at,
+            )));
+        }
+    }
+
     fn generate_session_token() -> WSSessionToken {
         thread_rng().sample_iter(&Alphanumeric).take(32).collect()
     }
 }
 
+fn seconds_until(instant: Instant) -> u64 {
+    instant.saturating_duration_since(Instant::now()).as_secs()
+}
+
 #[derive(Clone)]
 enum ConnectionState {
     Connected(Addr<ClientConnection>),
@@ -121,9 +144,10 @@ pub enum ConnectionManagerMsg {
     ChatMessage(WSSessionToken, String), // global chat message (sender_addr, msg)
     ChatRead(WSSessionToken),
     Backlink(Addr<LobbyManager>), // sent by lobbyManager when it starts to form bidirectional link
-    RequestAdapterNew(NewAdapterAdresses), // sent when client first connects
-    RequestAdapterExisting(NewAdapterAdresses, String), // sent when client reconnects
+    RequestAdapterNew(NewAdapterAdresses, Encoding), // sent when client first connects
+    RequestAdapterExisting(NewAdapterAdresses, String, Encoding), // sent when client reconnects
     RequestAdapterLegacy(NewAdapterAdresses, Option<String>), // sent when legacy client first connects with playerMsgStr in "queue"
+    ServerRestarting(Instant), // sent when the server is drained, with the time it goes down
 }
 
 pub struct NewAdapterAdresses {
@@ -165,6 +189,9 @@ impl Handler<ConnectionManagerMsg> for ConnectionManager {
                                     .adapter_addr
                                     .do_send(ClientAdapterMsg::Disconnect);
                                 connection.state = ConnectionState::Disconnected(Instant::now());
+                                connection
+                                    .state_addr
+                                    .do_send(ClientStateMessage::ConnectionLost);
                             }
                         }
                     }
@@ -196,7 +223,16 @@ impl Handler<ConnectionManagerMsg> for ConnectionManager {
             Backlink(lobby_mgr_addr) => {
                 self.lobby_mgr_state = BacklinkState::Linked(lobby_mgr_addr)
             }
-            RequestAdapterNew(new_adapter_addresses) => {
+            ServerRestarting(restarting_at) => {
+                self.restarting_at = Some(restarting_at);
+                let seconds = seconds_until(restarting_at);
+                for connection in self.connections.values() {
+                    connection
+                        .state_addr
+                        .do_send(ServerMessage::ServerRestarting(seconds));
+                }
+            }
+            RequestAdapterNew(new_adapter_addresses, encoding) => {
                 let session_token = Self::generate_session_token();
                 let client_state_addr = ClientState::new(
                     session_token.clone(),
@@ -209,6 +245,7 @@ impl Handler<ConnectionManagerMsg> for ConnectionManager {
                 let client_adapter = ClientAdapter::new(
                     new_adapter_addresses.client_conn.clone(),
                     client_state_addr.clone(),
+                    encoding,
                 )
                 .start();
                 // Add this new connection to list
@@ -226,28 +263,39 @@ impl Handler<ConnectionManagerMsg> for ConnectionManager {
                     .client_conn
                     .do_send(ClientConnectionMsg::Connect {
                         session_token,
-                        client_adapter,
-                        connection_type: ConnectionType::Reliable { is_new: true },
+                        client_adapter: client_adapter.clone(),
+                        connection_type: ConnectionType::Reliable {
+                            is_new: true,
+                            encoding,
+                        },
                     });
+                self.send_restarting(&client_adapter);
 
                 // <- Commented out for performance reasons ->
                 // self.send_server_info_to_all(ctx);
                 self.send_server_info_batched = true;
             }
-            RequestAdapterExisting(new_adapter_addresses, session_token) => {
+            RequestAdapterExisting(new_adapter_addresses, session_token, encoding) => {
                 if let Some(connection) = self.connections.get_mut(&session_token) {
                     connection.state =
                         ConnectionState::Connected(new_adapter_addresses.client_conn.clone());
                     connection.adapter_addr.do_send(ClientAdapterMsg::Connect(
                         new_adapter_addresses.client_conn.clone(),
+                        encoding,
                     ));
                     new_adapter_addresses
                         .client_conn
                         .do_send(ClientConnectionMsg::Connect {
                             session_token: session_token.clone(),
                             client_adapter: connection.adapter_addr.clone(),
-                            connection_type: ConnectionType::Reliable { is_new: false },
+                            connection_type: ConnectionType::Reliable {
+                                is_new: false,
+                                encoding,
+                            },
                         });
+                    connection
+                        .state_addr
+                        .do_send(ClientStateMessage::Reconnected);
                 // println!(
                 //     "ConnMgr: ReqAdapterExisting({:?}) found",
                 //     session_token
@@ -257,7 +305,7 @@ impl Handler<ConnectionManagerMsg> for ConnectionManager {
                 //         .collect::<String>()
                 // );
                 } else {
-                    ctx.notify(RequestAdapterNew(new_adapter_addresses));
+                    ctx.notify(RequestAdapterNew(new_adapter_addresses, encoding));
                 }
             }
             RequestAdapterLegacy(new_adapter_addresses, maybe_str_msg) => {
@@ -297,6 +345,7 @@ impl Handler<ConnectionManagerMsg> for ConnectionManager {
                 if let Some(str_msg) = maybe_str_msg {
                     client_adapter.do_send(ClientMsgString(str_msg));
                 }
+                self.send_restarting(&client_adapter);
                 self.send_server_info_batched = true;
             }
         }
@@ -304,6 +353,134 @@ impl Handler<ConnectionManagerMsg> for ConnectionManager {
     }
 }
 
+/// Collects all sessions that can be continued after a restart
+pub struct TakeSnapshot;
+
+impl Message for TakeSnapshot {
+    type Result = ServerSnapshot;
+}
+
+impl Handler<TakeSnapshot> for ConnectionManager {
+    type Result = ResponseActFuture<Self, ServerSnapshot>;
+
+    fn handle(&mut self, _: TakeSnapshot, _ctx: &mut Self::Context) -> Self::Result {
+        let requests = self
+            .connections
+            .iter()
+            .map(|(session_token, connection)| {
+                let session_token = session_token.clone();
+                let adapter_addr = connection.adapter_addr.clone();
+                let state_addr = connection.state_addr.clone();
+                async move {
+                    let reliability = adapter_addr.send(SnapshotReliability).await.ok()??;
+                    let (user_id, session_id, maybe_lobby) =
+                        state_addr.send(SnapshotClient).await.ok()?;
+                    let session = SessionSnapshot {
+                        token: session_token,
+                        user_id,
+                        session_id,
+                        game_id: maybe_lobby.as_ref().map(|(_, lobby)| lobby.game_id),
+                        player: maybe_lobby.as_ref().map(|(player, _)| *player),
+                        reliability,
+                    };
+                    Some((session, maybe_lobby.map(|(_, lobby)| lobby)))
+                }
+            })
+            .collect::<Vec<_>>();
+        Box::pin(
+            async move {
+                let mut snapshot = ServerSnapshot::new();
+                for (session, maybe_lobby) in join_all(requests).await.into_iter().flatten() {
+                    // Both players of a lobby return it
+                    if let Some(lobby) = maybe_lobby {
+                        if !snapshot.lobbies.iter().any(|l| l.game_id == lobby.game_id) {
+                            snapshot.lobbies.push(lobby);
+                        }
+                    }
+                    snapshot.sessions.push(session);
+                }
+                snapshot
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+/// Recreates the sessions of a snapshot, their clients have `CONNECTION_KEEPALIVE_SECONDS` to reconnect
+pub struct RestoreSnapshot {
+    pub snapshot: ServerSnapshot,
+    pub lobby_mgr: Addr<LobbyManager>,
+    pub user_mgr: Addr<UserManager>,
+}
+
+impl Message for RestoreSnapshot {
+    type Result = ();
+}
+
+impl Handler<RestoreSnapshot> for ConnectionManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: RestoreSnapshot, ctx: &mut Self::Context) -> Self::Result {
+        let mut lobby_players = HashMap::new();
+        let session_count = msg.snapshot.sessions.len();
+        let mut restored_count = 0;
+        for session in msg.snapshot.sessions {
+            if self.connections.contains_key(&session.token) {
+                continue;
+            }
+            let client_state_addr = ClientState::new(
+                session.token.clone(),
+                msg.lobby_mgr.clone(),
+                msg.user_mgr.clone(),
+                self.logger.clone(),
+                ctx.address(),
+            )
+            .start();
+            let client_adapter =
+                match ClientAdapter::restore(client_state_addr.clone(), session.reliability) {
+                    Some(client_adapter) => client_adapter.start(),
+                    None => {
+                        client_state_addr.do_send(ClientStateMessage::Close);
+                        continue;
+                    }
+                };
+            if let Some(user_id) = session.user_id {
+                client_state_addr.do_send(ClientStateMessage::RestoreLogin(
+                    user_id,
+                    session.session_id,
+                ));
+            }
+            if let (Some(game_id), Some(player)) = (session.game_id, session.player) {
+                lobby_players.insert((game_id, player), client_state_addr.clone());
+            }
+            self.connections.insert(
+                session.token,
+                Connection {
+                    state_addr: client_state_addr,
+                    adapter_addr: client_adapter,
+                    state: ConnectionState::Disconnected(Instant::now()),
+                },
+            );
+            restored_count += 1;
+        }
+        for lobby in msg.snapshot.lobbies {
+            let game_id = lobby.game_id;
+            if let Some(host) = lobby_players.remove(&(game_id, Player::One)) {
+                msg.lobby_mgr.do_send(RestoreLobby {
+                    snapshot: lobby,
+                    host,
+                    joined: lobby_players.remove(&(game_id, Player::Two)),
+                });
+            }
+        }
+        println!(
+            "ConnMgr: Restored {} of {} sessions",
+            restored_count, session_count
+        );
+        self.send_server_info_batched = true;
+    }
+}
+
 impl Actor for ConnectionManager {
     type Context = Context<Self>;
 
@@ -1,12 +1,29 @@
+use super::board::Board;
+use super::bot::BotLevel;
+use super::clock::{Clock, TimeControl};
 use super::msg::SrvMsgError;
-use crate::api::users::user::UserId;
+use super::snapshot::GameSnapshot;
+use crate::api::users::user::{PlayedGameInfo, PlayedMove, UserId};
+use crate::logging::{GameEndReason, GameOId};
 
 use rand::{thread_rng, Rng};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{de, Deserialize, Serialize, Serializer};
 use std::fmt;
+use std::time::SystemTime;
 
-pub const FIELD_SIZE: usize = 7;
 pub const GAME_ID_LEN: usize = 4;
 
+pub const DEFAULT_COLUMNS: usize = 7;
+pub const DEFAULT_ROWS: usize = 7;
+pub const DEFAULT_WIN_LENGTH: usize = 4;
+
+const MIN_BOARD_SIDE: usize = 4;
+const MAX_COLUMNS: usize = 9;
+const MAX_ROWS: usize = 7;
+const MIN_WIN_LENGTH: usize = 3;
+const MAX_WIN_LENGTH: usize = 6;
+
 const VALID_GAME_ID_CHARS: &str = "ABCDEFGHJKLMNOPQRSTUXYZ";
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
@@ -52,7 +69,140 @@ impl fmt::Display for GameId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+impl Serialize for GameId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+impl<'de> Deserialize<'de> for GameId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s: String = de::Deserialize::deserialize(deserializer)?;
+        GameId::parse(&s.to_uppercase()).ok_or_else(|| de::Error::custom("Invalid game id"))
+    }
+}
+impl JsonSchema for GameId {
+    fn schema_name() -> String {
+        "GameId".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_schema(
+            &format!("^[{}]{{{}}}$", VALID_GAME_ID_CHARS, GAME_ID_LEN),
+            "Id of a lobby, e.g. ABCD",
+        )
+    }
+}
+
+/// Shape of the board and number of chips in a row needed to win.
+/// The default 7x7 / 4 board is the one all app versions know about.
+/// All valid configurations have at most 64 cells so they fit into a bitboard.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct BoardConfig {
+    pub columns: usize,
+    pub rows: usize,
+    pub win_length: usize,
+}
+impl BoardConfig {
+    pub fn new(columns: usize, rows: usize, win_length: usize) -> Option<BoardConfig> {
+        if (MIN_BOARD_SIDE..=MAX_COLUMNS).contains(&columns)
+            && (MIN_BOARD_SIDE..=MAX_ROWS).contains(&rows)
+            && (MIN_WIN_LENGTH..=MAX_WIN_LENGTH).contains(&win_length)
+            && win_length <= columns.max(rows)
+        {
+            Some(BoardConfig {
+                columns,
+                rows,
+                win_length,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == BoardConfig::default()
+    }
+
+    /// Parses "<columns>X<rows>X<win_length>", e.g. "7X6X4"
+    pub fn parse(text: &str) -> Option<BoardConfig> {
+        let parts: Vec<_> = text.split('X').collect();
+        if parts.len() != 3 {
+            return None;
+        }
+        let columns = parts[0].parse().ok()?;
+        let rows = parts[1].parse().ok()?;
+        let win_length = parts[2].parse().ok()?;
+        BoardConfig::new(columns, rows, win_length)
+    }
+}
+impl Default for BoardConfig {
+    fn default() -> Self {
+        BoardConfig {
+            columns: DEFAULT_COLUMNS,
+            rows: DEFAULT_ROWS,
+            win_length: DEFAULT_WIN_LENGTH,
+        }
+    }
+}
+impl fmt::Display for BoardConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}X{}X{}", self.columns, self.rows, self.win_length)
+    }
+}
+impl Serialize for BoardConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+impl<'de> Deserialize<'de> for BoardConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s: String = de::Deserialize::deserialize(deserializer)?;
+        BoardConfig::parse(&s).ok_or_else(|| de::Error::custom("Invalid board config"))
+    }
+}
+impl JsonSchema for BoardConfig {
+    fn schema_name() -> String {
+        "BoardConfig".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_schema(
+            "^[0-9]+X[0-9]+X[0-9]+$",
+            "<columns>X<rows>X<win_length>, e.g. 7X6X4",
+        )
+    }
+}
+
+/// Schema of the types that are serialized as a string with a fixed format
+pub fn string_schema(pattern: &str, description: &str) -> Schema {
+    use schemars::schema::{InstanceType, Metadata, SchemaObject, StringValidation};
+    Schema::Object(SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        string: Some(Box::new(StringValidation {
+            pattern: Some(pattern.to_owned()),
+            ..Default::default()
+        })),
+        metadata: Some(Box::new(Metadata {
+            description: Some(description.to_owned()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 pub enum Player {
     One,
     Two,
@@ -99,162 +249,273 @@ impl GameState {
 pub enum GameType {
     Anonymous(GameInfo),
     Registered(GameInfo, UserId, UserId),
+    Bot(GameInfo, Option<UserId>, BotLevel), // The bot always joins the player's lobby
+}
+impl GameType {
+    pub fn info(&self) -> &GameInfo {
+        match self {
+            GameType::Registered(game_info, _, _)
+            | GameType::Anonymous(game_info)
+            | GameType::Bot(game_info, _, _) => game_info,
+        }
+    }
+
+    pub fn info_mut(&mut self) -> &mut GameInfo {
+        match self {
+            GameType::Registered(game_info, _, _)
+            | GameType::Anonymous(game_info)
+            | GameType::Bot(game_info, _, _) => game_info,
+        }
+    }
+
+    /// (host, joined)
+    pub fn player_ids(&self) -> (Option<UserId>, Option<UserId>) {
+        match self {
+            GameType::Registered(_, host_id, joined_id) => (Some(*host_id), Some(*joined_id)),
+            GameType::Anonymous(_) => (None, None),
+            GameType::Bot(_, maybe_host_id, _) => (*maybe_host_id, None),
+        }
+    }
+
+    pub fn is_ranked(&self) -> bool {
+        matches!(self, GameType::Registered(..))
+    }
+
+    pub fn to_played_game_info(&self, end_reason: GameEndReason) -> PlayedGameInfo {
+        let (host_id, joined_id) = self.player_ids();
+        let mut played_game_info = self
+            .info()
+            .to_played_game_info(host_id, joined_id, end_reason);
+        if let GameType::Bot(_, _, level) = self {
+            played_game_info.bot_level = Some(level.number());
+        }
+        played_game_info
+    }
+}
+
+/// Milliseconds since the unix epoch, used to timestamp games and moves
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
 }
 
 pub struct GameInfo {
-    // pub id: crate::logging::GameId,
-    field: [[Option<Player>; FIELD_SIZE]; FIELD_SIZE],
-    pub turn: Player,
-    pub winner: Option<WinnerInfo>,
+    pub id: GameOId,
+    board: Board,
+    started_at: i64,
+    move_times: Vec<i64>,
+    clock: Clock,
+    pub draw_offer: Option<Player>, // Stands until the opponent answers or moves
+    pub result: Option<GameResultInfo>,
 }
 impl GameInfo {
-    pub fn new() -> Self {
+    pub fn new(config: BoardConfig, time_control: TimeControl) -> Self {
+        let first = [Player::One, Player::Two][thread_rng().gen_range(0, 2)];
+        Self::with_first(config, time_control, first)
+    }
+    pub fn with_first(config: BoardConfig, time_control: TimeControl, first: Player) -> Self {
+        let started_at = now_millis();
+        let mut clock = Clock::new(time_control);
+        clock.start(first, started_at);
         GameInfo {
-            // id: crate::logging::GameId::new(),
-            field: [[None; FIELD_SIZE]; FIELD_SIZE],
-            turn: [Player::One, Player::Two][thread_rng().gen_range(0, 2)],
-            winner: None,
+            id: GameOId::new(),
+            board: Board::new(config, first),
+            started_at,
+            move_times: Vec::new(),
+            clock,
+            draw_offer: None,
+            result: None,
         }
     }
-    pub fn reset(&mut self) {
-        *self = Self::new();
+    /// Starts a new game, `None` picks a random first player
+    pub fn reset(&mut self, maybe_first: Option<Player>) {
+        let (config, time_control) = (self.board.config(), self.clock.time_control());
+        *self = match maybe_first {
+            Some(first) => Self::with_first(config, time_control, first),
+            None => Self::new(config, time_control),
+        };
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn turn(&self) -> Player {
+        self.board.turn()
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
     }
 
-    pub fn check_win(&mut self) -> Option<Player> {
-        let maybe_winner = self.check_win_internal();
-        if let Some(winner) = maybe_winner {
-            self.winner = Some(WinnerInfo {
-                winner,
-                requesting_rematch: None,
-            });
+    /// The player who ran out of time while the game is still running
+    pub fn flagged(&self, now: i64) -> Option<Player> {
+        if self.result.is_some() {
+            return None;
         }
-        maybe_winner
-    }
-
-    fn check_win_internal(&self) -> Option<Player> {
-        const RANGE: isize = FIELD_SIZE as isize - 4;
-        for r in (-RANGE)..=RANGE {
-            let mut combo: (Option<Player>, usize) = (None, 0);
-            for i in 0..FIELD_SIZE {
-                let i_isize = i as isize;
-                if i_isize + r < 0 || i_isize + r >= FIELD_SIZE as isize {
-                    combo = (None, 0);
-                    continue;
-                }
-                let column = (i_isize + r) as usize;
-                let cell = self.field[column][i];
-                if cell.is_none() {
-                    combo = (None, 0);
-                    continue;
-                } else if cell != combo.0 {
-                    combo = (cell, 0);
-                }
-
-                combo.1 += 1;
-                if combo.1 >= 4 {
-                    return cell;
-                }
-            }
+        self.clock.flagged(now)
+    }
 
-            let mut combo: (Option<Player>, usize) = (None, 0);
-            for i in (0..FIELD_SIZE - 1).rev() {
-                let i_isize = i as isize;
-                if i_isize + r < 0 || i_isize + r >= FIELD_SIZE as isize {
-                    combo = (None, 0);
-                    continue;
-                }
-                let real_y = FIELD_SIZE - 1 - i;
-                let column = (i_isize + r) as usize;
-                let cell = self.field[column][real_y];
-                if cell.is_none() {
-                    combo = (None, 0);
-                    continue;
-                } else if cell != combo.0 {
-                    combo = (cell, 0);
-                }
-
-                combo.1 += 1;
-                if combo.1 >= 4 {
-                    return cell;
-                }
-            }
+    /// Stops the clock while a player is reconnecting
+    pub fn pause(&mut self, now: i64) {
+        self.clock.stop(now);
+    }
+
+    pub fn resume(&mut self, now: i64) {
+        if self.result.is_none() {
+            self.clock.start(self.turn(), now);
         }
+    }
 
-        let mut x_combo: Vec<(Option<Player>, usize)> = vec![(None, 0); FIELD_SIZE];
-        let mut combo: (Option<Player>, usize) = (None, 0);
-        for column in &self.field {
-            for (y, cell) in column.iter().enumerate() {
-                let cell = *cell;
-                if cell.is_none() {
-                    combo = (None, 0);
-                    x_combo[y] = (None, 0);
-                    continue;
-                } else if cell != combo.0 {
-                    combo = (cell, 0);
-                }
-                combo.1 += 1;
-
-                if combo.1 >= 4 {
-                    return cell;
-                }
-
-                if cell != x_combo[y].0 {
-                    x_combo[y] = (cell, 0);
-                }
-                x_combo[y].1 += 1;
-
-                if x_combo[y].1 >= 4 {
-                    return cell;
-                }
-            }
+    /// Ends the game, no more chips can be placed afterwards
+    pub fn finish(&mut self, result: GameResult) {
+        self.clock.stop(now_millis());
+        self.result = Some(GameResultInfo {
+            result,
+            requesting_rematch: None,
+        });
+    }
+
+    /// Checks whether the game is over, either because someone connected enough chips
+    /// or because the field is completely filled up.
+    pub fn check_result(&mut self) -> Option<GameResult> {
+        let maybe_result = self.board.result();
+        if let Some(result) = maybe_result {
+            self.finish(result);
         }
-        None
+        maybe_result
     }
 
     pub fn place_chip(
         &mut self,
         column: usize,
         player: Player,
-    ) -> Result<Option<Player>, Option<SrvMsgError>> {
-        if column >= self.field.len() {
+    ) -> Result<Option<GameResult>, Option<SrvMsgError>> {
+        if self.result.is_some() {
+            return Err(Some(SrvMsgError::GameAlreadyOver));
+        }
+        if column >= self.board.config().columns {
             return Err(Some(SrvMsgError::InvalidColumn));
         }
-        if player == self.turn {
-            for i in (0..FIELD_SIZE).rev() {
-                if self.field[column][i] == None {
-                    self.field[column][i] = Some(self.turn);
-                    self.turn = self.turn.other();
-                    // self.print_field();
-                    return Ok(self.check_win());
-                }
+        if player == self.board.turn() {
+            if self.board.play(column).is_ok() {
+                let now = now_millis();
+                self.move_times.push(now);
+                self.clock.press(now);
+                self.draw_offer = None;
+                // println!("{}", self.board);
+                Ok(self.check_result())
+            } else {
+                Err(Some(SrvMsgError::InvalidColumn))
             }
-            Err(Some(SrvMsgError::InvalidColumn))
         } else {
             Err(Some(SrvMsgError::NotYourTurn))
         }
         // Err(None)
     }
 
-    #[allow(dead_code)]
-    fn print_field(&self) {
-        for column in self.field.iter() {
-            for cell in column {
-                match cell {
-                    None => print!("□"),
-                    Some(Player::One) => print!("X"),
-                    Some(Player::Two) => print!("O"),
-                }
-                print!(" ");
+    /// The clock times are taken at `now`, a restored game continues from there
+    pub fn snapshot(&self, now: i64) -> GameSnapshot {
+        GameSnapshot {
+            id: self.id.clone(),
+            first: self.board.first(),
+            moves: self
+                .board
+                .moves()
+                .iter()
+                .map(|column| *column as i32)
+                .collect(),
+            move_times: self.move_times.clone(),
+            started_at: self.started_at,
+            remaining_ms: [
+                self.clock.remaining_ms(Player::One, now),
+                self.clock.remaining_ms(Player::Two, now),
+            ],
+            draw_offer: self.draw_offer,
+            result: self.result.as_ref().map(|result_info| result_info.result),
+            requesting_rematch: self
+                .result
+                .as_ref()
+                .and_then(|result_info| result_info.requesting_rematch),
+        }
+    }
+
+    /// Replays the moves of a snapshot, the clock stays stopped until `resume` is called.
+    /// None if the moves don't fit the board.
+    pub fn restore(
+        snapshot: GameSnapshot,
+        config: BoardConfig,
+        time_control: TimeControl,
+    ) -> Option<GameInfo> {
+        let mut board = Board::new(config, snapshot.first);
+        for column in snapshot.moves {
+            if column < 0 || board.play(column as usize).is_err() {
+                return None;
             }
-            println!();
         }
+        if snapshot.move_times.len() != board.move_count() {
+            return None;
+        }
+        let requesting_rematch = snapshot.requesting_rematch;
+        Some(GameInfo {
+            id: snapshot.id,
+            board,
+            started_at: snapshot.started_at,
+            move_times: snapshot.move_times,
+            clock: Clock::restore(time_control, snapshot.remaining_ms),
+            draw_offer: snapshot.draw_offer,
+            result: snapshot.result.map(|result| GameResultInfo {
+                result,
+                requesting_rematch,
+            }),
+        })
     }
-    // fn is_column_full(&self, column: usize) -> bool {
-    //     !self.field[column].contains(&None)
-    // }
+
+    /// Builds the record that is stored in the games collection.
+    /// Player one is always the lobby host.
+    pub fn to_played_game_info(
+        &self,
+        player_one: Option<UserId>,
+        player_two: Option<UserId>,
+        end_reason: GameEndReason,
+    ) -> PlayedGameInfo {
+        PlayedGameInfo {
+            id: self.id.clone(),
+            player_one,
+            player_two,
+            starting_player: self.board.first(),
+            board: self.board.config(),
+            moves: self
+                .board
+                .moves()
+                .iter()
+                .zip(self.move_times.iter())
+                .map(|(column, timestamp)| PlayedMove {
+                    column: *column as i32,
+                    timestamp: *timestamp,
+                })
+                .collect(),
+            result: self.result.as_ref().map(|result_info| result_info.result),
+            end_reason,
+            started_at: self.started_at,
+            ended_at: now_millis(),
+            rating_change: None,
+            bot_level: None,
+            series_id: None,
+            tournament_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum GameResult {
+    Winner(Player),
+    Draw,
 }
 
-pub struct WinnerInfo {
-    pub winner: Player,
+pub struct GameResultInfo {
+    pub result: GameResult,
     pub requesting_rematch: Option<Player>,
 }
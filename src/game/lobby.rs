@@ -1,12 +1,14 @@
+use super::bot::{Bot, BotLevel};
 use super::client_state::{ClientState, ClientStateMessage};
-use super::game_info::{GameId, GameInfo, GameType, Player};
-use super::lobby_mgr::{LobbyManager, LobbyManagerMsg, LobbyRequestResponseReady};
+use super::connection_mgr::CONNECTION_KEEPALIVE_SECONDS;
+use super::game_info::{now_millis, GameId, GameInfo, GameResult, GameType, Player};
+use super::lobby_mgr::{LobbyManager, LobbyManagerMsg, LobbyRequestResponseReady, LobbySettings};
 use super::msg::*;
+use super::series::Series;
+use super::snapshot::LobbySnapshot;
+use super::tournament::TournamentId;
 use crate::{
-    api::users::{
-        user::{PlayedGameInfo, UserId},
-        user_mgr,
-    },
+    api::users::{user::UserId, user_mgr},
     logging::*,
 };
 
@@ -17,7 +19,10 @@ use std::time::{Duration, Instant};
 const LOBBY_TIMEOUT_S: u64 = 30 * 60; // 30 minutes
 const GAME_START_DELAY_S: u64 = 2;
 const GAME_READY_RESPONSE_TIMEOUT_MS: u64 = 5000; // TODO: 1 second
+const RECONNECT_GRACE_S: u64 = CONNECTION_KEEPALIVE_SECONDS; // The session is dropped afterwards anyway
+const TOURNAMENT_LOBBY_CLOSE_S: u64 = 5; // Time to see the result before the lobby closes
 
+#[allow(clippy::large_enum_variant)]
 enum LobbyState {
     OnePlayer {
         host_info: LobbyPlayerInfo,
@@ -28,19 +33,47 @@ enum LobbyState {
         timeout_handle: SpawnHandle,
     },
     TwoPlayers {
-        game_oid: GameOId,
         game_type: GameType,
-        host_addr: Addr<ClientState>,
-        joined_addr: Addr<ClientState>,
+        host_addr: Participant,
+        joined_addr: Participant,
     },
 }
 
 #[derive(Clone)]
 struct LobbyPlayerInfo {
-    addr: Addr<ClientState>,
+    addr: Participant,
     maybe_uid: Option<UserId>,
 }
 
+/// One side of a lobby, either a connected player or a server bot
+#[derive(Clone)]
+pub enum Participant {
+    Client(Addr<ClientState>),
+    Bot(Addr<Bot>, BotLevel),
+}
+
+impl Participant {
+    fn do_send(&self, msg: ServerMessage) {
+        match self {
+            Participant::Client(addr) => addr.do_send(msg),
+            Participant::Bot(addr, _) => addr.do_send(msg),
+        }
+    }
+
+    /// Puts a client back into the idle state, bots stop on their own
+    fn reset(&self) {
+        if let Participant::Client(addr) = self {
+            addr.do_send(ClientStateMessage::Reset);
+        }
+    }
+
+    fn ready(&self) {
+        if let Participant::Client(addr) = self {
+            addr.do_send(LobbyRequestResponseReady);
+        }
+    }
+}
+
 pub struct ClientLobbyMessageNamed {
     pub sender: Player,
     pub msg: ClientLobbyMessage,
@@ -50,8 +83,15 @@ pub enum ClientLobbyMessage {
     PlayerLeaving { reason: PlayerLeaveReason },
     PlayAgainRequest,
     PlaceChip(usize),
+    Resign, // Ends the game but keeps the lobby open for a rematch
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
     ChatMessage(String, Option<String>), // content, sender
     ChatRead,
+    BoardStateRequest,
+    ConnectionLost, // Pauses the game until the player is back or the grace period is over
+    Reconnected,
 }
 
 pub enum PlayerLeaveReason {
@@ -71,6 +111,14 @@ pub struct Lobby {
     lobby_mgr: Addr<LobbyManager>,
     logger: Addr<Logger>,
     lobby_state: LobbyState,
+    spectators: Vec<Addr<ClientState>>,
+    settings: LobbySettings,
+    clock_handle: Option<SpawnHandle>,
+    reconnecting: Option<(Player, SpawnHandle)>, // player who lost the connection, grace timer
+    absent: Vec<Player>, // Players who have yet to reconnect after a restart
+    series: Option<Series>,
+    tournament: Option<TournamentId>, // Tournament lobbies host a single game
+    draining: bool, // The server is about to restart, the running game is the last one
     last_hb: Instant,
 }
 
@@ -88,63 +136,92 @@ impl Handler<ClientLobbyMessageNamed> for Lobby {
             PlayerLeaving {
                 reason: leave_reason,
             } => {
-                self.lobby_mgr
-                    .do_send(LobbyManagerMsg::CloseLobbyMsg(self.game_id));
-                self.logger.do_send(LobbyLogEvent::LobbyClosed {
-                    id: self.lobby_id.clone(),
-                });
+                let end_reason = match leave_reason {
+                    PlayerLeaveReason::Leave => GameEndReason::PlayerLeft,
+                    PlayerLeaveReason::Disconnect => GameEndReason::PlayerDisconnected,
+                };
+                if let Some((player, grace_handle)) = self.reconnecting.take() {
+                    ctx.cancel_future(grace_handle);
+                    if player == msg_named.sender && self.game_running() {
+                        // Did not come back in time
+                        self.end_game(
+                            GameResult::Winner(player.other()),
+                            GameEndReason::PlayerDisconnected,
+                            ctx,
+                        );
+                    }
+                }
+                if self.absent.contains(&msg_named.sender)
+                    && !self.absent.contains(&msg_named.sender.other())
+                    && self.game_running()
+                {
+                    // Did not come back after the restart
+                    self.end_game(
+                        GameResult::Winner(msg_named.sender.other()),
+                        GameEndReason::PlayerDisconnected,
+                        ctx,
+                    );
+                }
+                if self.tournament.is_some() && self.game_running() {
+                    // Leaving a tournament game forfeits it
+                    self.end_game(
+                        GameResult::Winner(msg_named.sender.other()),
+                        end_reason,
+                        ctx,
+                    );
+                }
 
                 fn send_messages(
-                    logger: &Addr<Logger>,
-                    leave_reason: PlayerLeaveReason,
-                    game_oid: Option<GameOId>,
                     sender: Player,
-                    host_addr: &Addr<ClientState>,
-                    joined_addr: &Addr<ClientState>,
+                    host_addr: &Participant,
+                    joined_addr: &Participant,
                 ) {
-                    if let Some(game_oid) = game_oid {
-                        logger.do_send(GameLogEvent::EndGame {
-                            id: game_oid,
-                            reason: match leave_reason {
-                                PlayerLeaveReason::Leave => GameEndReason::PlayerLeft,
-                                PlayerLeaveReason::Disconnect => GameEndReason::PlayerDisconnected,
-                            },
-                        });
-                    }
                     let other_addr = sender.other().select(host_addr, joined_addr);
-                    other_addr.do_send(ClientStateMessage::Reset);
+                    other_addr.reset();
                     other_addr.do_send(ServerMessage::OpponentLeaving);
                 }
 
                 match &self.lobby_state {
                     LobbyState::TwoPlayers {
-                        game_oid,
-                        game_type: _,
+                        game_type,
                         host_addr,
                         joined_addr,
                     } => {
-                        send_messages(
-                            &self.logger,
-                            leave_reason,
-                            Some(game_oid.clone()),
-                            msg_named.sender,
-                            host_addr,
-                            joined_addr,
-                        );
+                        let game_info = game_type.info();
+                        // Finished games are already logged and stored
+                        if game_info.result.is_none() {
+                            self.logger.do_send(GameLogEvent::EndGame {
+                                id: game_info.id.clone(),
+                                reason: end_reason,
+                            });
+                            // Keep games that were abandoned halfway through
+                            if game_info.board().move_count() > 0 {
+                                let mut played_game_info =
+                                    game_type.to_played_game_info(end_reason);
+                                played_game_info.series_id =
+                                    self.series.as_ref().map(|series| series.id.clone());
+                                played_game_info.tournament_id = self.tournament.clone();
+                                self.lobby_mgr
+                                    .do_send(LobbyManagerMsg::PlayedGame(played_game_info));
+                            }
+                        }
+                        if let Some(series) = &self.series {
+                            // Decided series are already stored
+                            if series.has_games() && !series.is_over() {
+                                let (host_id, joined_id) = game_type.player_ids();
+                                self.lobby_mgr.do_send(LobbyManagerMsg::PlayedSeries(
+                                    series.to_played_series_info(host_id, joined_id),
+                                ));
+                            }
+                        }
+                        send_messages(msg_named.sender, host_addr, joined_addr);
                     }
                     LobbyState::TwoPlayersWaitingForPing {
                         host_info,
                         joined_info,
                         ..
                     } => {
-                        send_messages(
-                            &self.logger,
-                            leave_reason,
-                            None,
-                            msg_named.sender,
-                            &host_info.addr,
-                            &joined_info.addr,
-                        );
+                        send_messages(msg_named.sender, &host_info.addr, &joined_info.addr);
                     }
                     LobbyState::OnePlayer { .. } => {}
                 }
@@ -152,9 +229,22 @@ impl Handler<ClientLobbyMessageNamed> for Lobby {
                 Ok(())
             }
             PlayAgainRequest => {
+                if self.tournament.is_some() {
+                    self.send_to_player(
+                        msg_named.sender,
+                        ServerMessage::Error(Some(SrvMsgError::NoRematchInTournament)),
+                    );
+                    return Err(());
+                }
+                if self.draining {
+                    self.send_to_player(
+                        msg_named.sender,
+                        ServerMessage::Error(Some(SrvMsgError::ServerRestarting)),
+                    );
+                    return Err(());
+                }
                 match &mut self.lobby_state {
                     LobbyState::TwoPlayers {
-                        game_oid: _,
                         game_type,
                         host_addr,
                         joined_addr,
@@ -162,9 +252,10 @@ impl Handler<ClientLobbyMessageNamed> for Lobby {
                         let requesting_addr = msg_named.sender.select(host_addr, joined_addr);
                         match game_type {
                             GameType::Registered(game_info, _, _)
-                            | GameType::Anonymous(game_info) => {
-                                if let Some(winner_info) = &mut game_info.winner {
-                                    if let Some(already_requested) = winner_info.requesting_rematch
+                            | GameType::Anonymous(game_info)
+                            | GameType::Bot(game_info, _, _) => {
+                                if let Some(result_info) = &mut game_info.result {
+                                    if let Some(already_requested) = result_info.requesting_rematch
                                     {
                                         if already_requested != msg_named.sender {
                                             // both have now requested -> rematch
@@ -174,7 +265,7 @@ impl Handler<ClientLobbyMessageNamed> for Lobby {
                                             // requesting_addr.do_send(ServerMessage::Okay);
                                         }
                                     } else {
-                                        winner_info.requesting_rematch = Some(msg_named.sender);
+                                        result_info.requesting_rematch = Some(msg_named.sender);
                                     }
                                 } else {
                                     // game not over yet
@@ -207,75 +298,126 @@ impl Handler<ClientLobbyMessageNamed> for Lobby {
                     }
                 }
             }
-            PlaceChip(column) => match self.lobby_state {
-                LobbyState::TwoPlayers {
-                    ref game_oid,
-                    ref mut game_type,
-                    ref host_addr,
-                    ref joined_addr,
-                } => match game_type {
-                    GameType::Registered(game_info, _, _) | GameType::Anonymous(game_info) => {
+            PlaceChip(column) => {
+                if self.reconnecting.is_some() || !self.absent.is_empty() {
+                    self.send_to_player(
+                        msg_named.sender,
+                        ServerMessage::Error(Some(SrvMsgError::GamePaused)),
+                    );
+                    return Err(());
+                }
+                let maybe_result = match self.lobby_state {
+                    LobbyState::TwoPlayers {
+                        ref mut game_type,
+                        ref host_addr,
+                        ref joined_addr,
+                    } => {
+                        let game_info = game_type.info_mut();
+                        let placing_addr = msg_named.sender.select(host_addr, joined_addr);
+                        if let Some(flagged) = game_info.flagged(now_millis()) {
+                            // The move came in too late
+                            self.end_game(
+                                GameResult::Winner(flagged.other()),
+                                GameEndReason::Timeout,
+                                ctx,
+                            );
+                            return Err(());
+                        }
                         match game_info.place_chip(column, msg_named.sender) {
-                            Ok(maybe_winner) => {
-                                let placing_addr = msg_named.sender.select(host_addr, joined_addr);
-
+                            Ok(maybe_result) => {
                                 let other_addr =
                                     msg_named.sender.other().select(host_addr, joined_addr);
                                 other_addr.do_send(ServerMessage::PlaceChip(column));
-                                if let Some(winner) = maybe_winner {
-                                    placing_addr.do_send(ServerMessage::GameOver(
-                                        msg_named.sender == winner,
-                                    ));
-                                    other_addr.do_send(ServerMessage::GameOver(
-                                        msg_named.sender.other() == winner,
-                                    ));
-                                    self.logger.do_send(GameLogEvent::EndGame {
-                                        id: game_oid.clone(),
-                                        reason: GameEndReason::Regular,
-                                    });
-                                    if let GameType::Registered(_, host_id, joined_id) = game_type {
-                                        let (winner, loser) =
-                                            winner.select_both(*host_id, *joined_id);
-                                        println!("{} won against {}", winner, loser);
-                                        let game_info = PlayedGameInfo::new(winner, loser);
-                                        self.lobby_mgr
-                                            .do_send(LobbyManagerMsg::PlayedGame(game_info));
-                                    }
-                                }
-
-                                Ok(())
+                                send_to_all(&self.spectators, ServerMessage::PlaceChip(column));
+                                maybe_result
                             }
                             Err(srvmsgerr) => {
-                                let placing_addr = msg_named.sender.select(host_addr, joined_addr);
                                 placing_addr.do_send(ServerMessage::Error(srvmsgerr));
-                                Err(())
+                                return Err(());
                             }
                         }
                     }
-                },
-                LobbyState::TwoPlayersWaitingForPing {
-                    ref host_info,
-                    ref joined_info,
-                    ..
-                } => {
-                    host_info
-                        .addr
-                        .do_send(ServerMessage::Error(Some(SrvMsgError::GameNotStarted)));
-                    joined_info
-                        .addr
-                        .do_send(ServerMessage::Error(Some(SrvMsgError::GameNotStarted)));
-                    Err(())
+                    LobbyState::TwoPlayersWaitingForPing {
+                        ref host_info,
+                        ref joined_info,
+                        ..
+                    } => {
+                        host_info
+                            .addr
+                            .do_send(ServerMessage::Error(Some(SrvMsgError::GameNotStarted)));
+                        joined_info
+                            .addr
+                            .do_send(ServerMessage::Error(Some(SrvMsgError::GameNotStarted)));
+                        return Err(());
+                    }
+                    LobbyState::OnePlayer { ref host_info } => {
+                        host_info
+                            .addr
+                            .do_send(ServerMessage::Error(Some(SrvMsgError::GameNotStarted)));
+                        return Err(());
+                    }
+                };
+                if let Some(result) = maybe_result {
+                    self.end_game(result, GameEndReason::Regular, ctx);
+                } else {
+                    self.send_clocks();
+                    self.schedule_clock_check(ctx);
                 }
-                LobbyState::OnePlayer { ref host_info } => {
-                    host_info
-                        .addr
-                        .do_send(ServerMessage::Error(Some(SrvMsgError::GameNotStarted)));
-                    Err(())
+                Ok(())
+            }
+            Resign => {
+                if self.running_game(msg_named.sender).is_none() {
+                    return Err(());
                 }
-            },
+                self.send_to_player(msg_named.sender.other(), ServerMessage::OpponentResigned);
+                self.end_game(
+                    GameResult::Winner(msg_named.sender.other()),
+                    GameEndReason::Resigned,
+                    ctx,
+                );
+                Ok(())
+            }
+            OfferDraw => {
+                let game_info = match self.running_game(msg_named.sender) {
+                    Some(game_info) => game_info,
+                    None => return Err(()),
+                };
+                match game_info.draw_offer {
+                    // Both offered a draw at the same time
+                    Some(offering) if offering != msg_named.sender => {
+                        self.end_game(GameResult::Draw, GameEndReason::DrawAgreed, ctx);
+                    }
+                    Some(_) => {}
+                    None => {
+                        game_info.draw_offer = Some(msg_named.sender);
+                        self.send_to_player(msg_named.sender.other(), ServerMessage::DrawOffered);
+                    }
+                }
+                Ok(())
+            }
+            AcceptDraw | DeclineDraw => {
+                let accept = matches!(msg_named.msg, AcceptDraw);
+                let game_info = match self.running_game(msg_named.sender) {
+                    Some(game_info) => game_info,
+                    None => return Err(()),
+                };
+                if game_info.draw_offer != Some(msg_named.sender.other()) {
+                    self.send_to_player(
+                        msg_named.sender,
+                        ServerMessage::Error(Some(SrvMsgError::NoDrawOffered)),
+                    );
+                    return Err(());
+                }
+                if accept {
+                    self.end_game(GameResult::Draw, GameEndReason::DrawAgreed, ctx);
+                } else {
+                    game_info.draw_offer = None;
+                    self.send_to_player(msg_named.sender.other(), ServerMessage::DrawDeclined);
+                }
+                Ok(())
+            }
             ChatMessage(msg, sender) => match self.lobby_state {
                 LobbyState::TwoPlayers {
-                    game_oid: _,
                     game_type: _,
                     ref host_addr,
                     ref joined_addr,
@@ -288,7 +430,6 @@ impl Handler<ClientLobbyMessageNamed> for Lobby {
             },
             ChatRead => match self.lobby_state {
                 LobbyState::TwoPlayers {
-                    game_oid: _,
                     game_type: _,
                     ref host_addr,
                     ref joined_addr,
@@ -299,6 +440,79 @@ impl Handler<ClientLobbyMessageNamed> for Lobby {
                 }
                 _ => Err(()),
             },
+            ConnectionLost => {
+                if self.reconnecting.is_some() || !self.game_running() {
+                    return Ok(());
+                }
+                if let Some(handle) = self.clock_handle.take() {
+                    ctx.cancel_future(handle);
+                }
+                if let LobbyState::TwoPlayers { game_type, .. } = &mut self.lobby_state {
+                    game_type.info_mut().pause(now_millis());
+                }
+                let sender = msg_named.sender;
+                let grace_handle =
+                    ctx.run_later(Duration::from_secs(RECONNECT_GRACE_S), move |_, ctx| {
+                        ctx.notify(ClientLobbyMessageNamed {
+                            sender,
+                            msg: PlayerLeaving {
+                                reason: PlayerLeaveReason::Disconnect,
+                            },
+                        });
+                    });
+                self.reconnecting = Some((sender, grace_handle));
+                self.send_to_player(
+                    sender.other(),
+                    ServerMessage::OpponentReconnecting(RECONNECT_GRACE_S),
+                );
+                self.send_clocks();
+                Ok(())
+            }
+            BoardStateRequest => {
+                if let LobbyState::TwoPlayers { .. } = self.lobby_state {
+                    self.send_board_state(msg_named.sender);
+                    Ok(())
+                } else {
+                    self.send_to_player(
+                        msg_named.sender,
+                        ServerMessage::Error(Some(SrvMsgError::GameNotStarted)),
+                    );
+                    Err(())
+                }
+            }
+            Reconnected => {
+                match self.reconnecting {
+                    Some((player, grace_handle)) if player == msg_named.sender => {
+                        ctx.cancel_future(grace_handle);
+                        self.reconnecting = None;
+                        self.send_to_player(player.other(), ServerMessage::OpponentReconnected);
+                        if self.absent.is_empty() {
+                            self.resume_game(ctx);
+                        }
+                    }
+                    _ => {}
+                }
+                if let Some(index) = self.absent.iter().position(|p| *p == msg_named.sender) {
+                    self.absent.remove(index);
+                    if self.absent.is_empty() {
+                        self.send_to_player(
+                            msg_named.sender.other(),
+                            ServerMessage::OpponentReconnected,
+                        );
+                        if self.reconnecting.is_none() {
+                            self.resume_game(ctx);
+                        }
+                    } else {
+                        self.send_to_player(
+                            msg_named.sender,
+                            ServerMessage::OpponentReconnecting(RECONNECT_GRACE_S),
+                        );
+                    }
+                }
+                // The client might have lost its local state, e.g. when the app was restarted
+                self.send_board_state(msg_named.sender);
+                Ok(())
+            }
         }
     }
 }
@@ -307,10 +521,17 @@ pub enum LobbyMessage {
     ReceivedReadyForGamePong,
     GameStart,
     LobbyClose,
+    Drain, // Sent by the `LobbyManager` before the server restarts
     PlayerJoined {
-        joined_addr: Addr<ClientState>,
+        joined_addr: Participant,
         maybe_uid: Option<UserId>,
     },
+    /// Refused unless one of the spectator's friends plays in the lobby
+    SpectatorJoined {
+        addr: Addr<ClientState>,
+        friends: Vec<UserId>,
+    },
+    SpectatorLeft(Addr<ClientState>),
 }
 
 impl Handler<LobbyMessage> for Lobby {
@@ -321,48 +542,121 @@ impl Handler<LobbyMessage> for Lobby {
                 ctx.stop();
                 Ok(())
             }
+            LobbyMessage::Drain => {
+                self.draining = true;
+                if let LobbyState::OnePlayer { .. } = self.lobby_state {
+                    // Nobody can join anymore
+                    ctx.stop();
+                }
+                Ok(())
+            }
             LobbyMessage::GameStart => {
                 if let LobbyState::TwoPlayers {
-                    game_oid: _,
                     game_type,
                     ref host_addr,
                     ref joined_addr,
                 } = &mut self.lobby_state
                 {
+                    // Players take turns starting the games of a series
+                    let maybe_first = self.series.as_mut().map(|series| {
+                        if series.is_over() {
+                            // Rematch after a decided series, start over
+                            *series = Series::new(series.best_of());
+                        }
+                        series.start_game()
+                    });
+                    // Every game, including rematches, gets its own id
                     match game_type {
-                        GameType::Registered(game_info, _, _) | GameType::Anonymous(game_info) => {
-                            game_info.reset();
+                        GameType::Registered(game_info, _, _)
+                        | GameType::Anonymous(game_info)
+                        | GameType::Bot(game_info, _, _) => {
+                            game_info.reset(maybe_first);
                         }
                     }
+                    self.logger.do_send(GameLogEvent::StartGame {
+                        id: game_type.info().id.clone(),
+                        ranked: game_type.is_ranked(),
+                    });
                     match game_type {
-                        GameType::Anonymous(game_info) => {
+                        GameType::Anonymous(game_info) | GameType::Bot(game_info, _, _) => {
                             host_addr.do_send(ServerMessage::GameStart(
-                                game_info.turn == Player::One,
+                                game_info.turn() == Player::One,
                                 None,
+                                game_info.board().config(),
                             ));
                             joined_addr.do_send(ServerMessage::GameStart(
-                                game_info.turn == Player::Two,
+                                game_info.turn() == Player::Two,
                                 None,
+                                game_info.board().config(),
                             ));
                         }
                         GameType::Registered(game_info, host_id, joined_id) => {
                             // let user_mgr = self.user_mgr.clone();
                             host_addr.do_send(ServerMessage::GameStart(
-                                game_info.turn == Player::One,
+                                game_info.turn() == Player::One,
                                 Some(joined_id.to_string()),
+                                game_info.board().config(),
                             ));
                             joined_addr.do_send(ServerMessage::GameStart(
-                                game_info.turn == Player::Two,
+                                game_info.turn() == Player::Two,
                                 Some(host_id.to_string()),
+                                game_info.board().config(),
                             ));
                         }
                     }
+                    if let Some(series) = &self.series {
+                        let (host_wins, joined_wins) = series.score(Player::One);
+                        host_addr.do_send(ServerMessage::SeriesScore(
+                            host_wins,
+                            joined_wins,
+                            series.draws(),
+                            series.best_of(),
+                        ));
+                        joined_addr.do_send(ServerMessage::SeriesScore(
+                            joined_wins,
+                            host_wins,
+                            series.draws(),
+                            series.best_of(),
+                        ));
+                    }
+                    let board = game_type.info().board();
+                    send_to_all(
+                        &self.spectators,
+                        ServerMessage::SpectateGameStart(board.config(), board.first()),
+                    );
+                    self.send_clocks();
+                    self.schedule_clock_check(ctx);
 
                     Ok(())
                 } else {
                     Err(())
                 }
             }
+            LobbyMessage::SpectatorJoined { addr, friends } => {
+                if !self.player_ids().iter().any(|id| friends.contains(id)) {
+                    return Err(());
+                }
+                let snapshot = match &self.lobby_state {
+                    LobbyState::TwoPlayers { game_type, .. } => {
+                        let board = game_type.info().board();
+                        ServerMessage::SpectateBoard(
+                            board.config(),
+                            Some(board.first()),
+                            board.moves().to_vec(),
+                        )
+                    }
+                    _ => ServerMessage::SpectateBoard(self.settings.board, None, vec![]),
+                };
+                addr.do_send(snapshot);
+                self.spectators.push(addr);
+                self.send_spectator_count();
+                Ok(())
+            }
+            LobbyMessage::SpectatorLeft(addr) => {
+                self.spectators.retain(|spectator| *spectator != addr);
+                self.send_spectator_count();
+                Ok(())
+            }
             LobbyMessage::ReceivedReadyForGamePong => {
                 if let LobbyState::TwoPlayersWaitingForPing {
                     host_info,
@@ -371,38 +665,39 @@ impl Handler<LobbyMessage> for Lobby {
                 } = &mut self.lobby_state
                 {
                     ctx.cancel_future(*timeout_handle);
-                    joined_info.addr.do_send(LobbyRequestResponseReady);
+                    joined_info.addr.ready();
                     host_info.addr.do_send(ServerMessage::OpponentJoining);
                     joined_info.addr.do_send(ServerMessage::OpponentJoining);
-                    self.lobby_state = if let (Some(host_id), Some(joined_id)) =
+                    self.lobby_state = if let Participant::Bot(_, level) = joined_info.addr {
+                        let game_info =
+                            GameInfo::new(self.settings.board, self.settings.time_control);
+                        LobbyState::TwoPlayers {
+                            game_type: GameType::Bot(game_info, host_info.maybe_uid, level),
+                            host_addr: host_info.addr.clone(),
+                            joined_addr: joined_info.addr.clone(),
+                        }
+                    } else if let (Some(host_id), Some(joined_id)) =
                         (host_info.maybe_uid, joined_info.maybe_uid)
                     {
-                        let game_info = GameInfo::new();
-                        let game_oid = GameOId::new();
-                        self.logger.do_send(GameLogEvent::StartGame {
-                            id: game_oid.clone(),
-                            ranked: true,
-                        });
+                        let game_info =
+                            GameInfo::new(self.settings.board, self.settings.time_control);
                         LobbyState::TwoPlayers {
-                            game_oid: game_oid,
                             game_type: GameType::Registered(game_info, host_id, joined_id),
                             host_addr: host_info.addr.clone(),
                             joined_addr: joined_info.addr.clone(),
                         }
                     } else {
-                        let game_info = GameInfo::new();
-                        let game_oid = GameOId::new();
-                        self.logger.do_send(GameLogEvent::StartGame {
-                            id: game_oid.clone(),
-                            ranked: false,
-                        });
+                        let game_info =
+                            GameInfo::new(self.settings.board, self.settings.time_control);
                         LobbyState::TwoPlayers {
-                            game_oid: game_oid,
                             game_type: GameType::Anonymous(game_info),
                             host_addr: host_info.addr.clone(),
                             joined_addr: joined_info.addr.clone(),
                         }
                     };
+                    if !self.spectators.is_empty() {
+                        self.send_spectator_count();
+                    }
                     ctx.notify_later(
                         LobbyMessage::GameStart,
                         Duration::from_secs(GAME_START_DELAY_S),
@@ -449,7 +744,279 @@ impl Handler<LobbyMessage> for Lobby {
     }
 }
 
+fn send_to_all(addrs: &[Addr<ClientState>], msg: ServerMessage) {
+    for addr in addrs {
+        addr.do_send(msg.clone());
+    }
+}
+
 impl Lobby {
+    /// Ends the running game, tells everyone in the lobby and stores the game
+    fn end_game(&mut self, result: GameResult, reason: GameEndReason, ctx: &mut Context<Self>) {
+        if let LobbyState::TwoPlayers {
+            game_type,
+            host_addr,
+            joined_addr,
+        } = &mut self.lobby_state
+        {
+            game_type.info_mut().finish(result);
+            match result {
+                GameResult::Winner(winner) => {
+                    host_addr.do_send(ServerMessage::GameOver(winner == Player::One));
+                    joined_addr.do_send(ServerMessage::GameOver(winner == Player::Two));
+                    send_to_all(
+                        &self.spectators,
+                        ServerMessage::SpectateGameOver(Some(winner)),
+                    );
+                }
+                GameResult::Draw => {
+                    host_addr.do_send(ServerMessage::GameDraw);
+                    joined_addr.do_send(ServerMessage::GameDraw);
+                    send_to_all(&self.spectators, ServerMessage::SpectateGameOver(None));
+                }
+            }
+            self.logger.do_send(GameLogEvent::EndGame {
+                id: game_type.info().id.clone(),
+                reason,
+            });
+            if let GameType::Registered(_, host_id, joined_id) = game_type {
+                match result {
+                    GameResult::Winner(winner) => {
+                        let (winner, loser) = winner.select_both(*host_id, *joined_id);
+                        println!("{} won against {}", winner, loser);
+                    }
+                    GameResult::Draw => {
+                        println!("{} drew against {}", host_id, joined_id);
+                    }
+                }
+            }
+            let mut played_game_info = game_type.to_played_game_info(reason);
+            played_game_info.tournament_id = self.tournament.clone();
+            let mut maybe_series_info = None;
+            if let Some(series) = &mut self.series {
+                played_game_info.series_id = Some(series.id.clone());
+                series.record(played_game_info.id.clone(), result);
+                if let Some(series_result) = series.result() {
+                    if let GameResult::Winner(winner) = series_result {
+                        host_addr.do_send(ServerMessage::SeriesOver(winner == Player::One));
+                        joined_addr.do_send(ServerMessage::SeriesOver(winner == Player::Two));
+                    } else {
+                        host_addr.do_send(ServerMessage::SeriesDrawn);
+                        joined_addr.do_send(ServerMessage::SeriesDrawn);
+                    }
+                    let (host_id, joined_id) = game_type.player_ids();
+                    maybe_series_info = Some(series.to_played_series_info(host_id, joined_id));
+                }
+            }
+            self.lobby_mgr
+                .do_send(LobbyManagerMsg::PlayedGame(played_game_info));
+            if let Some(series_info) = maybe_series_info {
+                self.lobby_mgr
+                    .do_send(LobbyManagerMsg::PlayedSeries(series_info));
+            }
+            if self.tournament.is_some() {
+                ctx.notify_later(
+                    LobbyMessage::LobbyClose,
+                    Duration::from_secs(TOURNAMENT_LOBBY_CLOSE_S),
+                );
+            }
+        }
+    }
+
+    /// Continues the game after it was paused for missing players
+    fn resume_game(&mut self, ctx: &mut Context<Self>) {
+        if let LobbyState::TwoPlayers {
+            game_type,
+            joined_addr,
+            ..
+        } = &mut self.lobby_state
+        {
+            game_type.info_mut().resume(now_millis());
+            if let Participant::Bot(..) = joined_addr {
+                // The bot's last move may have been rejected during the pause, and after a
+                // restart it only learns about the game now
+                self.send_board_state(Player::Two);
+            }
+        }
+        self.send_clocks();
+        self.schedule_clock_check(ctx);
+    }
+
+    /// Players who are logged in, their friends may watch the lobby
+    fn player_ids(&self) -> Vec<UserId> {
+        let (maybe_host_id, maybe_joined_id) = match &self.lobby_state {
+            LobbyState::OnePlayer { host_info } => (host_info.maybe_uid, None),
+            LobbyState::TwoPlayersWaitingForPing {
+                host_info,
+                joined_info,
+                ..
+            } => (host_info.maybe_uid, joined_info.maybe_uid),
+            LobbyState::TwoPlayers { game_type, .. } => game_type.player_ids(),
+        };
+        maybe_host_id.into_iter().chain(maybe_joined_id).collect()
+    }
+
+    fn game_running(&self) -> bool {
+        match &self.lobby_state {
+            LobbyState::TwoPlayers { game_type, .. } => game_type.info().result.is_none(),
+            _ => false,
+        }
+    }
+
+    /// The game that is currently being played, otherwise tells the player why there is none
+    fn running_game(&mut self, player: Player) -> Option<&mut GameInfo> {
+        let error = match &self.lobby_state {
+            LobbyState::TwoPlayers { game_type, .. } => game_type
+                .info()
+                .result
+                .as_ref()
+                .map(|_| SrvMsgError::GameAlreadyOver),
+            _ => Some(SrvMsgError::GameNotStarted),
+        };
+        if let Some(error) = error {
+            self.send_to_player(player, ServerMessage::Error(Some(error)));
+            return None;
+        }
+        match &mut self.lobby_state {
+            LobbyState::TwoPlayers { game_type, .. } => Some(game_type.info_mut()),
+            _ => None,
+        }
+    }
+
+    fn send_to_player(&self, player: Player, msg: ServerMessage) {
+        match &self.lobby_state {
+            LobbyState::TwoPlayers {
+                host_addr,
+                joined_addr,
+                ..
+            } => player.select(host_addr, joined_addr).do_send(msg),
+            LobbyState::TwoPlayersWaitingForPing {
+                host_info,
+                joined_info,
+                ..
+            } => player.select(host_info, joined_info).addr.do_send(msg),
+            LobbyState::OnePlayer { host_info } => {
+                if player == Player::One {
+                    host_info.addr.do_send(msg);
+                }
+            }
+        }
+    }
+
+    /// Everything a player needs to redraw the game, e.g. after reconnecting
+    fn send_board_state(&self, player: Player) {
+        if let LobbyState::TwoPlayers { game_type, .. } = &self.lobby_state {
+            let game_info = game_type.info();
+            let board = game_info.board();
+            let opponent = match game_type {
+                GameType::Registered(_, host_id, joined_id) => {
+                    Some(player.other().select(host_id, joined_id).to_string())
+                }
+                GameType::Anonymous(_) | GameType::Bot(..) => None,
+            };
+            let clock = game_info.clock();
+            let now = now_millis();
+            self.send_to_player(
+                player,
+                ServerMessage::BoardState {
+                    board: board.config(),
+                    my_turn: game_info.turn() == player,
+                    move_count: board.move_count(),
+                    opponent,
+                    clock: if clock.is_unlimited() {
+                        None
+                    } else {
+                        Some((
+                            clock.remaining_ms(player, now),
+                            clock.remaining_ms(player.other(), now),
+                        ))
+                    },
+                    moves: board.moves().to_vec(),
+                },
+            );
+        }
+    }
+
+    /// Tells everyone how much time is left, if the game has a clock
+    fn send_clocks(&self) {
+        if let LobbyState::TwoPlayers {
+            game_type,
+            host_addr,
+            joined_addr,
+        } = &self.lobby_state
+        {
+            let clock = game_type.info().clock();
+            if clock.is_unlimited() {
+                return;
+            }
+            let now = now_millis();
+            let one = clock.remaining_ms(Player::One, now);
+            let two = clock.remaining_ms(Player::Two, now);
+            host_addr.do_send(ServerMessage::Clock(one, two));
+            joined_addr.do_send(ServerMessage::Clock(two, one));
+            send_to_all(&self.spectators, ServerMessage::SpectateClock(one, two));
+        }
+    }
+
+    /// Wakes the lobby up when the player to move runs out of time
+    fn schedule_clock_check(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.clock_handle.take() {
+            ctx.cancel_future(handle);
+        }
+        if let LobbyState::TwoPlayers { game_type, .. } = &self.lobby_state {
+            let game_info = game_type.info();
+            if game_info.clock().is_unlimited() || game_info.result.is_some() {
+                return;
+            }
+            let remaining = game_info
+                .clock()
+                .remaining_ms(game_info.turn(), now_millis());
+            self.clock_handle = Some(ctx.run_later(
+                Duration::from_millis(remaining as u64),
+                |act, ctx| {
+                    act.clock_handle = None;
+                    if let LobbyState::TwoPlayers { game_type, .. } = &act.lobby_state {
+                        if let Some(flagged) = game_type.info().flagged(now_millis()) {
+                            act.send_clocks();
+                            act.end_game(
+                                GameResult::Winner(flagged.other()),
+                                GameEndReason::Timeout,
+                                ctx,
+                            );
+                            return;
+                        }
+                    }
+                    act.schedule_clock_check(ctx);
+                },
+            ));
+        }
+    }
+
+    fn send_spectator_count(&self) {
+        let msg = ServerMessage::SpectatorCount(self.spectators.len());
+        match &self.lobby_state {
+            LobbyState::TwoPlayers {
+                host_addr,
+                joined_addr,
+                ..
+            } => {
+                host_addr.do_send(msg.clone());
+                joined_addr.do_send(msg.clone());
+            }
+            LobbyState::TwoPlayersWaitingForPing {
+                host_info,
+                joined_info,
+                ..
+            } => {
+                host_info.addr.do_send(msg.clone());
+                joined_info.addr.do_send(msg.clone());
+            }
+            LobbyState::OnePlayer { host_info } => host_info.addr.do_send(msg.clone()),
+        }
+        send_to_all(&self.spectators, msg);
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         lobby_id: LobbyId,
         game_id: GameId,
@@ -458,6 +1025,8 @@ impl Lobby {
         logger: Addr<Logger>,
         host_state: Addr<ClientState>,
         maybe_host_id: Option<UserId>,
+        settings: LobbySettings,
+        tournament: Option<TournamentId>,
     ) -> Lobby {
         Lobby {
             lobby_id,
@@ -467,13 +1036,94 @@ impl Lobby {
             logger,
             lobby_state: LobbyState::OnePlayer {
                 host_info: LobbyPlayerInfo {
-                    addr: host_state,
+                    addr: Participant::Client(host_state),
                     maybe_uid: maybe_host_id,
                 },
             },
+            spectators: Vec::new(),
+            settings,
+            clock_handle: None,
+            reconnecting: None,
+            absent: Vec::new(),
+            series: settings.best_of.map(Series::new),
+            tournament,
+            draining: false,
             last_hb: Instant::now(),
         }
     }
+
+    /// Continues a lobby after a restart. The game stays paused until every client is back,
+    /// a client that doesn't reconnect in time forfeits it. None if the snapshot is damaged
+    #[allow(clippy::too_many_arguments)]
+    pub fn restore(
+        snapshot: LobbySnapshot,
+        lobby_id: LobbyId,
+        lobby_mgr: Addr<LobbyManager>,
+        user_mgr: Addr<user_mgr::UserManager>,
+        logger: Addr<Logger>,
+        host_state: Addr<ClientState>,
+        maybe_joined_state: Option<Addr<ClientState>>, // None if the host plays against a bot
+    ) -> Option<Addr<Lobby>> {
+        let best_of = match snapshot.best_of {
+            Some(best_of) => Some(Series::check_best_of(best_of.max(0) as usize)?),
+            None => None,
+        };
+        let settings = LobbySettings {
+            board: snapshot.board,
+            time_control: snapshot.time_control,
+            best_of,
+        };
+        let series = match snapshot.series {
+            Some(series) => Some(Series::restore(series)?),
+            None => None,
+        };
+        let game_info = GameInfo::restore(snapshot.game, settings.board, settings.time_control)?;
+        let maybe_level = match snapshot.bot_level {
+            Some(level) => Some(BotLevel::new(level.max(0) as usize)?),
+            None => None,
+        };
+        let game_type = match (maybe_level, snapshot.host_id, snapshot.joined_id) {
+            (Some(level), maybe_host_id, _) => GameType::Bot(game_info, maybe_host_id, level),
+            (None, Some(host_id), Some(joined_id)) => {
+                GameType::Registered(game_info, host_id, joined_id)
+            }
+            (None, _, _) => GameType::Anonymous(game_info),
+        };
+        let mut absent = vec![Player::One];
+        let joined = match (maybe_joined_state, maybe_level) {
+            (Some(joined_state), None) => {
+                absent.push(Player::Two);
+                Ok(Participant::Client(joined_state))
+            }
+            (None, Some(level)) => Err(level),
+            _ => return None,
+        };
+        let game_id = snapshot.game_id;
+        Some(Lobby::create(move |ctx| Lobby {
+            lobby_id,
+            game_id,
+            lobby_mgr,
+            user_mgr,
+            logger,
+            lobby_state: LobbyState::TwoPlayers {
+                game_type,
+                host_addr: Participant::Client(host_state),
+                // The bot needs the address of the lobby
+                joined_addr: joined.unwrap_or_else(|level| {
+                    Participant::Bot(Bot::new(level, Player::Two, ctx.address()).start(), level)
+                }),
+            },
+            spectators: Vec::new(),
+            settings,
+            clock_handle: None,
+            reconnecting: None,
+            absent,
+            series,
+            tournament: None,
+            draining: false,
+            last_hb: Instant::now(),
+        }))
+    }
 }
 
 impl Actor for Lobby {
@@ -493,6 +1143,16 @@ impl Actor for Lobby {
 
     fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
         println!("Lobby ({}): closing.", self.game_id);
+        self.lobby_mgr
+            .do_send(LobbyManagerMsg::CloseLobbyMsg(self.game_id));
+        self.logger.do_send(LobbyLogEvent::LobbyClosed {
+            id: self.lobby_id.clone(),
+        });
+
+        for spectator in &self.spectators {
+            spectator.do_send(ClientStateMessage::Reset);
+            spectator.do_send(ServerMessage::LobbyClosing);
+        }
 
         match self.lobby_state {
             LobbyState::TwoPlayers {
@@ -500,10 +1160,10 @@ impl Actor for Lobby {
                 ref joined_addr,
                 ..
             } => {
-                host_addr.do_send(ClientStateMessage::Reset);
+                host_addr.reset();
                 host_addr.do_send(ServerMessage::LobbyClosing);
 
-                joined_addr.do_send(ClientStateMessage::Reset);
+                joined_addr.reset();
                 joined_addr.do_send(ServerMessage::LobbyClosing);
             }
             LobbyState::TwoPlayersWaitingForPing {
@@ -511,14 +1171,14 @@ impl Actor for Lobby {
                 ref joined_info,
                 ..
             } => {
-                host_info.addr.do_send(ClientStateMessage::Reset);
+                host_info.addr.reset();
                 host_info.addr.do_send(ServerMessage::LobbyClosing);
 
-                joined_info.addr.do_send(ClientStateMessage::Reset);
+                joined_info.addr.reset();
                 joined_info.addr.do_send(ServerMessage::LobbyClosing);
             }
             LobbyState::OnePlayer { ref host_info } => {
-                host_info.addr.do_send(ClientStateMessage::Reset);
+                host_info.addr.reset();
                 host_info.addr.do_send(ServerMessage::LobbyClosing);
             }
         }
@@ -529,3 +1189,173 @@ impl Actor for Lobby {
 impl Message for LobbyMessage {
     type Result = Result<(), ()>;
 }
+
+/// Whether a game is played or about to start in the lobby
+pub struct IsGameRunning;
+
+impl Message for IsGameRunning {
+    type Result = bool;
+}
+
+impl Handler<IsGameRunning> for Lobby {
+    type Result = bool;
+
+    fn handle(&mut self, _: IsGameRunning, _ctx: &mut Self::Context) -> Self::Result {
+        match self.lobby_state {
+            LobbyState::TwoPlayersWaitingForPing { .. } => true,
+            _ => self.game_running(),
+        }
+    }
+}
+
+/// `None` unless a game is played in the lobby, tournament games are left out
+pub struct SnapshotLobby;
+
+impl Message for SnapshotLobby {
+    type Result = Option<LobbySnapshot>;
+}
+
+impl Handler<SnapshotLobby> for Lobby {
+    type Result = Option<LobbySnapshot>;
+
+    fn handle(&mut self, _: SnapshotLobby, _ctx: &mut Self::Context) -> Self::Result {
+        if self.tournament.is_some() {
+            return None;
+        }
+        if let LobbyState::TwoPlayers { game_type, .. } = &self.lobby_state {
+            let (host_id, joined_id) = game_type.player_ids();
+            Some(LobbySnapshot {
+                game_id: self.game_id,
+                board: self.settings.board,
+                time_control: self.settings.time_control,
+                best_of: self.settings.best_of.map(|best_of| best_of as i32),
+                host_id,
+                joined_id,
+                bot_level: match game_type {
+                    GameType::Bot(_, _, level) => Some(level.number()),
+                    _ => None,
+                },
+                game: game_type.info().snapshot(now_millis()),
+                series: self.series.as_ref().map(Series::snapshot),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Address of an actor that never runs, messages to it are dropped
+    fn unused_addr<A: Actor>() -> Addr<A> {
+        Addr::new(dev::channel::channel(1).0)
+    }
+
+    fn moves_played(snapshot: Option<LobbySnapshot>) -> usize {
+        snapshot.unwrap().game.moves.len()
+    }
+
+    /// A lobby waiting for an opponent, the managers and the host's client never run
+    fn new_lobby(maybe_host_id: Option<UserId>, settings: LobbySettings) -> Lobby {
+        Lobby::new(
+            LobbyId::new(),
+            GameId::generate(&[]),
+            unused_addr(),
+            unused_addr(),
+            unused_addr(),
+            unused_addr(),
+            maybe_host_id,
+            settings,
+            None,
+        )
+    }
+
+    fn spectate(friends: Vec<UserId>) -> LobbyMessage {
+        LobbyMessage::SpectatorJoined {
+            addr: unused_addr(),
+            friends,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn bot_moves_after_the_opponent_reconnects() {
+        let settings = LobbySettings::default();
+        let lobby = Lobby::create(|ctx| {
+            let level = BotLevel::new(1).unwrap();
+            let bot = Bot::new(level, Player::Two, ctx.address()).start();
+            bot.do_send(ServerMessage::GameStart(false, None, settings.board));
+            let game_info =
+                GameInfo::with_first(settings.board, settings.time_control, Player::One);
+            let mut lobby = new_lobby(None, settings);
+            lobby.lobby_state = LobbyState::TwoPlayers {
+                game_type: GameType::Bot(game_info, None, level),
+                host_addr: Participant::Client(unused_addr()),
+                joined_addr: Participant::Bot(bot, level),
+            };
+            lobby
+        });
+        let from_host = |msg| ClientLobbyMessageNamed {
+            sender: Player::One,
+            msg,
+        };
+
+        // The connection drops while the bot is searching, its move is rejected
+        lobby.do_send(from_host(ClientLobbyMessage::PlaceChip(3)));
+        lobby.do_send(from_host(ClientLobbyMessage::ConnectionLost));
+        actix_rt::time::delay_for(Duration::from_millis(200)).await;
+        assert_eq!(moves_played(lobby.send(SnapshotLobby).await.unwrap()), 1);
+
+        lobby
+            .send(from_host(ClientLobbyMessage::Reconnected))
+            .await
+            .unwrap()
+            .unwrap();
+        actix_rt::time::delay_for(Duration::from_millis(200)).await;
+        assert_eq!(moves_played(lobby.send(SnapshotLobby).await.unwrap()), 2);
+    }
+
+    #[actix_rt::test]
+    async fn only_friends_of_a_player_can_spectate() {
+        let (host, joined, stranger) = (UserId::new(), UserId::new(), UserId::new());
+        let settings = LobbySettings::default();
+
+        let waiting = new_lobby(Some(host), settings).start();
+        assert!(waiting.send(spectate(vec![])).await.unwrap().is_err());
+        assert!(waiting
+            .send(spectate(vec![stranger]))
+            .await
+            .unwrap()
+            .is_err());
+        assert!(waiting
+            .send(spectate(vec![stranger, host]))
+            .await
+            .unwrap()
+            .is_ok());
+
+        let running = Lobby::create(move |_| {
+            let mut lobby = new_lobby(Some(host), settings);
+            lobby.lobby_state = LobbyState::TwoPlayers {
+                game_type: GameType::Registered(
+                    GameInfo::new(settings.board, settings.time_control),
+                    host,
+                    joined,
+                ),
+                host_addr: Participant::Client(unused_addr()),
+                joined_addr: Participant::Client(unused_addr()),
+            };
+            lobby
+        });
+        assert!(running
+            .send(spectate(vec![stranger]))
+            .await
+            .unwrap()
+            .is_err());
+        assert!(running.send(spectate(vec![joined])).await.unwrap().is_ok());
+
+        // Nobody is friends with a player who isn't logged in
+        let anonymous = new_lobby(None, settings).start();
+        assert!(anonymous.send(spectate(vec![host])).await.unwrap().is_err());
+    }
+}
@@ -1,27 +1,43 @@
+use super::bot::{Bot, BotLevel, QUEUE_FALLBACK_LEVEL};
 use super::client_state::{ClientState, ClientStateMessage};
+use super::clock::TimeControl;
 use super::connection_mgr::{ConnectionManager, ConnectionManagerMsg};
-use super::game_info::GameId;
-use super::game_info::Player;
+use super::game_info::{BoardConfig, GameId, Player};
 use super::lobby::*;
+use super::matchmaking::{MatchmakingQueue, QueueEntry};
 use super::msg::*;
+use super::series::Series;
+use super::snapshot::LobbySnapshot;
+use super::tournament::TournamentId;
+use super::tournament_mgr::{TournamentGameRequest, TournamentManager, TournamentMsg};
 use crate::{
     api::users::{
-        user::{PlayedGameInfo, UserId},
+        user::{PlayedGameInfo, PlayedSeriesInfo, UserId},
         user_mgr,
     },
     logging::*,
 };
 
 use actix::*;
+use futures::future::join_all;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const QUEUE_MATCH_INTERVAL_S: u64 = 1;
+const QUEUE_TIMEOUT_S: u64 = 30 * 60; // same as an idle lobby
+const BOT_FALLBACK_DEFAULT_S: u64 = 60;
+const DRAIN_CHECK_INTERVAL_S: u64 = 1;
 
 pub struct LobbyManager {
-    open_lobby: Option<LobbyInfo>,
+    queue: MatchmakingQueue<QueuedPlayer>, // Players waiting for a public game
     open_lobby_map: LobbyMap,
     closed_lobby_map: LobbyMap,
     user_mgr: Addr<user_mgr::UserManager>,
     connection_mgr: Addr<ConnectionManager>,
     logger: Addr<Logger>,
+    tournament_mgr: Option<Addr<TournamentManager>>, // Set once the manager is started
+    bot_fallback_after: Option<Duration>, // None if players should wait for a human indefinitely
+    draining: bool,                       // No new games are started before the server restarts
 }
 
 impl LobbyManager {
@@ -30,16 +46,26 @@ impl LobbyManager {
         connection_mgr: Addr<ConnectionManager>,
         logger: Addr<Logger>,
     ) -> LobbyManager {
+        // Set BOT_FALLBACK_AFTER_S to 0 to disable the bot fallback
+        let bot_fallback_after_s = std::env::var("BOT_FALLBACK_AFTER_S")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(BOT_FALLBACK_DEFAULT_S);
         LobbyManager {
-            open_lobby: None,
+            queue: MatchmakingQueue::new(),
             open_lobby_map: HashMap::new(),
             closed_lobby_map: HashMap::new(),
             user_mgr,
             connection_mgr,
             logger,
+            tournament_mgr: None,
+            bot_fallback_after: Some(Duration::from_secs(bot_fallback_after_s))
+                .filter(|after| *after > Duration::from_secs(0)),
+            draining: false,
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn create_lobby(
         &mut self,
         host_addr: Addr<ClientState>,
@@ -47,6 +73,8 @@ impl LobbyManager {
         lobby_mgr_addr: Addr<LobbyManager>,
         user_mgr_addr: Addr<user_mgr::UserManager>,
         kind: LobbyKind,
+        settings: LobbySettings,
+        tournament: Option<TournamentId>,
     ) -> LobbyRequestResponse {
         let lobby_id = LobbyId::new();
         let game_id = GameId::generate(
@@ -65,24 +93,18 @@ impl LobbyManager {
             self.logger.clone(),
             host_addr,
             maybe_host_id,
+            settings,
+            tournament,
         )
         .start();
+        let lobby_info = LobbyInfo::new(lobby_id.clone(), lobby_addr.clone(), kind);
         match kind {
-            LobbyKind::Public => {
-                self.open_lobby = Some(LobbyInfo::new(
-                    lobby_id.clone(),
-                    game_id,
-                    lobby_addr.clone(),
-                    kind,
-                ));
+            // Public, bot and tournament lobbies are only created once both players are known
+            LobbyKind::Public | LobbyKind::Bot(_) | LobbyKind::Tournament => {
+                self.closed_lobby_map.insert(game_id, lobby_info)
             }
-            LobbyKind::Private => {
-                self.open_lobby_map.insert(
-                    game_id,
-                    LobbyInfo::new(lobby_id.clone(), game_id, lobby_addr.clone(), kind),
-                );
-            }
-        }
+            LobbyKind::Private => self.open_lobby_map.insert(game_id, lobby_info),
+        };
 
         LobbyRequestResponse {
             waiting: false,
@@ -91,22 +113,110 @@ impl LobbyManager {
             lobby_addr,
         }
     }
+
+    /// Creates a lobby hosted by the player who waited longer and lets the other one join it
+    fn start_public_game(
+        &mut self,
+        host: QueueEntry<QueuedPlayer>,
+        joined: QueuedPlayer,
+        ctx: &mut Context<Self>,
+    ) -> LobbyRequestResponse {
+        let response = self.create_lobby(
+            host.player.addr.clone(),
+            host.player.maybe_uid,
+            ctx.address(),
+            self.user_mgr.clone(),
+            LobbyKind::Public,
+            host.settings,
+            None,
+        );
+        host.player.addr.do_send(ClientStateMessage::QueueMatched(
+            Player::One,
+            response.lobby_addr.clone(),
+        ));
+        response.lobby_addr.do_send(LobbyMessage::PlayerJoined {
+            joined_addr: Participant::Client(joined.addr),
+            maybe_uid: joined.maybe_uid,
+        });
+        response
+    }
+
+    fn add_bot(&self, lobby_addr: Addr<Lobby>, level: BotLevel) {
+        let bot_addr = Bot::new(level, Player::Two, lobby_addr.clone()).start();
+        lobby_addr.do_send(LobbyMessage::PlayerJoined {
+            joined_addr: Participant::Bot(bot_addr, level),
+            maybe_uid: None,
+        });
+    }
+
+    fn match_queue(&mut self, ctx: &mut Context<Self>) {
+        let now = Instant::now();
+        let mut changed = false;
+        for entry in self
+            .queue
+            .remove_expired(now, Duration::from_secs(QUEUE_TIMEOUT_S))
+        {
+            entry.player.addr.do_send(ClientStateMessage::Reset);
+            entry.player.addr.do_send(ServerMessage::LobbyClosing);
+            changed = true;
+        }
+        for (host, joined) in self.queue.pop_matches(now) {
+            let joined_addr = joined.player.addr.clone();
+            let response = self.start_public_game(host, joined.player, ctx);
+            joined_addr.do_send(ClientStateMessage::QueueMatched(
+                Player::Two,
+                response.lobby_addr,
+            ));
+            changed = true;
+        }
+        if let Some(bot_fallback_after) = self.bot_fallback_after {
+            // Nobody found in time, play against a bot instead
+            for entry in self.queue.remove_expired(now, bot_fallback_after) {
+                let response = self.create_lobby(
+                    entry.player.addr.clone(),
+                    entry.player.maybe_uid,
+                    ctx.address(),
+                    self.user_mgr.clone(),
+                    LobbyKind::Bot(QUEUE_FALLBACK_LEVEL),
+                    entry.settings,
+                    None,
+                );
+                entry.player.addr.do_send(ClientStateMessage::QueueMatched(
+                    Player::One,
+                    response.lobby_addr.clone(),
+                ));
+                self.add_bot(response.lobby_addr, QUEUE_FALLBACK_LEVEL);
+                changed = true;
+            }
+        }
+        if changed {
+            self.send_queue_update();
+        }
+    }
+
+    fn send_queue_update(&self) {
+        self.connection_mgr
+            .do_send(ConnectionManagerMsg::Update(!self.queue.is_empty()));
+    }
 }
 
 pub type LobbyMap = HashMap<GameId, LobbyInfo>;
 
+pub struct QueuedPlayer {
+    addr: Addr<ClientState>,
+    maybe_uid: Option<UserId>,
+}
+
 #[derive(Clone)]
 pub struct LobbyInfo {
     lobby_id: LobbyId,
-    game_id: GameId,
     addr: Addr<Lobby>,
     kind: LobbyKind,
 }
 impl LobbyInfo {
-    fn new(lobby_id: LobbyId, game_id: GameId, addr: Addr<Lobby>, kind: LobbyKind) -> LobbyInfo {
+    fn new(lobby_id: LobbyId, addr: Addr<Lobby>, kind: LobbyKind) -> LobbyInfo {
         LobbyInfo {
             lobby_id,
-            game_id,
             addr,
             kind,
         }
@@ -117,15 +227,57 @@ impl LobbyInfo {
 pub enum LobbyKind {
     Private,
     Public,
+    Bot(BotLevel),
+    Tournament, // Created by the TournamentManager, nobody can join
+}
+
+/// Options chosen by the player creating a lobby. Public lobbies are only matched
+/// with players requesting the same settings.
+#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq)]
+pub struct LobbySettings {
+    pub board: BoardConfig,
+    pub time_control: TimeControl,
+    pub best_of: Option<usize>, // Plays a series instead of single games, private lobbies only
+}
+
+impl LobbySettings {
+    /// Parses the optional suffix of a lobby request: a list of `:KEY=VALUE` pairs,
+    /// e.g. ":BOARD=7X6X4:TIME=300+5". An empty string yields the default settings.
+    /// `TIME` sets initial seconds plus increment, `MOVE_TIME` a fixed time per move.
+    /// `BEST_OF=3` turns the lobby into a series.
+    pub fn parse(text: &str) -> Option<LobbySettings> {
+        let mut settings = LobbySettings::default();
+        for option in text.split(':').skip(1) {
+            let key_value: Vec<_> = option.splitn(2, '=').collect();
+            if key_value.len() != 2 {
+                return None;
+            }
+            match key_value[0] {
+                "BOARD" => settings.board = BoardConfig::parse(key_value[1])?,
+                "TIME" => settings.time_control = TimeControl::parse_fischer(key_value[1])?,
+                "MOVE_TIME" => settings.time_control = TimeControl::parse_per_move(key_value[1])?,
+                "BEST_OF" => settings.best_of = Some(Series::parse_best_of(key_value[1])?),
+                _ => return None,
+            }
+        }
+        Some(settings)
+    }
 }
 
 pub enum LobbyRequest {
-    NewLobby(Addr<ClientState>, Option<UserId>, LobbyKind),
+    NewLobby(
+        Addr<ClientState>,
+        Option<UserId>,
+        Option<i32>, // skill rating, used for matchmaking
+        LobbyKind,
+        LobbySettings,
+    ),
     JoinLobby(GameId, Addr<ClientState>, Option<UserId>, LobbyKind),
 }
 
+/// `None` if the player was put into the matchmaking queue
 impl Message for LobbyRequest {
-    type Result = Result<LobbyRequestResponse, Option<SrvMsgError>>;
+    type Result = Result<Option<LobbyRequestResponse>, Option<SrvMsgError>>;
 }
 
 pub struct LobbyRequestResponse {
@@ -142,47 +294,46 @@ impl Message for LobbyRequestResponseReady {
 }
 
 impl Handler<LobbyRequest> for LobbyManager {
-    type Result = Result<LobbyRequestResponse, Option<SrvMsgError>>;
+    type Result = Result<Option<LobbyRequestResponse>, Option<SrvMsgError>>;
     fn handle(&mut self, request: LobbyRequest, ctx: &mut Self::Context) -> Self::Result {
         // println!("lobby_mgr: got req");
+        if self.draining {
+            return Err(Some(SrvMsgError::ServerRestarting));
+        }
         match request {
-            LobbyRequest::NewLobby(requesting_addr, maybe_uid, kind) => {
+            LobbyRequest::NewLobby(requesting_addr, maybe_uid, maybe_rating, kind, settings) => {
                 // println!("got new lobby req");
+                if settings.best_of.is_some() && kind != LobbyKind::Private {
+                    return Err(Some(SrvMsgError::InvalidSettings));
+                }
                 match kind {
                     LobbyKind::Public => {
-                        if let Some(open_lobby) = self.open_lobby.clone() {
-                            self.open_lobby = None;
-
-                            open_lobby.addr.do_send(LobbyMessage::PlayerJoined {
-                                joined_addr: requesting_addr,
+                        let now = Instant::now();
+                        let newcomer = QueueEntry {
+                            player: QueuedPlayer {
+                                addr: requesting_addr,
                                 maybe_uid,
-                            });
-
-                            self.closed_lobby_map
-                                .insert(open_lobby.game_id, open_lobby.clone());
-
-                            self.connection_mgr
-                                .do_send(ConnectionManagerMsg::Update(self.open_lobby.is_some()));
-
-                            Ok(LobbyRequestResponse {
+                            },
+                            maybe_rating,
+                            settings,
+                            since: now,
+                        };
+                        let response = if let Some(waiting) = self.queue.find_match(&newcomer, now)
+                        {
+                            let host_response =
+                                self.start_public_game(waiting, newcomer.player, ctx);
+                            Some(LobbyRequestResponse {
                                 waiting: true,
                                 player: Player::Two,
-                                game_id: open_lobby.game_id,
-                                lobby_addr: open_lobby.addr.clone(),
+                                game_id: host_response.game_id,
+                                lobby_addr: host_response.lobby_addr,
                             })
                         } else {
-                            let response_success = self.create_lobby(
-                                requesting_addr,
-                                maybe_uid,
-                                ctx.address(),
-                                self.user_mgr.clone(),
-                                LobbyKind::Public,
-                            );
-                            self.connection_mgr
-                                .do_send(ConnectionManagerMsg::Update(self.open_lobby.is_some()));
-
-                            Ok(response_success)
-                        }
+                            self.queue.push(newcomer);
+                            None
+                        };
+                        self.send_queue_update();
+                        Ok(response)
                     }
                     LobbyKind::Private => {
                         let lobby_request_response = self.create_lobby(
@@ -191,9 +342,26 @@ impl Handler<LobbyRequest> for LobbyManager {
                             ctx.address(),
                             self.user_mgr.clone(),
                             LobbyKind::Private,
+                            settings,
+                            None,
+                        );
+
+                        Ok(Some(lobby_request_response))
+                    }
+                    LobbyKind::Tournament => Err(Some(SrvMsgError::InvalidSettings)),
+                    LobbyKind::Bot(level) => {
+                        let lobby_request_response = self.create_lobby(
+                            requesting_addr,
+                            maybe_uid,
+                            ctx.address(),
+                            self.user_mgr.clone(),
+                            kind,
+                            settings,
+                            None,
                         );
+                        self.add_bot(lobby_request_response.lobby_addr.clone(), level);
 
-                        Ok(lobby_request_response)
+                        Ok(Some(lobby_request_response))
                     }
                 }
             }
@@ -207,16 +375,16 @@ impl Handler<LobbyRequest> for LobbyManager {
                 if let Some(ref mut lobby_info) = self.open_lobby_map.get_mut(&id) {
                     if lobby_info.kind == kind {
                         lobby_info.addr.do_send(LobbyMessage::PlayerJoined {
-                            joined_addr: joining_addr,
+                            joined_addr: Participant::Client(joining_addr),
                             maybe_uid: maybe_user_id,
                         });
 
-                        Ok(LobbyRequestResponse {
+                        Ok(Some(LobbyRequestResponse {
                             waiting: false,
                             player: Player::Two,
                             game_id: id,
                             lobby_addr: lobby_info.addr.clone(),
-                        })
+                        }))
                     } else {
                         Err(Some(SrvMsgError::LobbyFull))
                     }
@@ -230,6 +398,189 @@ impl Handler<LobbyRequest> for LobbyManager {
     }
 }
 
+/// Watch a lobby in which a friend of the spectator plays
+pub struct SpectateRequest {
+    pub game_id: GameId,
+    pub addr: Addr<ClientState>,
+    pub user_id: UserId,
+}
+
+impl Message for SpectateRequest {
+    type Result = Result<Addr<Lobby>, SrvMsgError>;
+}
+
+impl Handler<SpectateRequest> for LobbyManager {
+    type Result = ResponseActFuture<Self, Result<Addr<Lobby>, SrvMsgError>>;
+
+    fn handle(&mut self, msg: SpectateRequest, _ctx: &mut Self::Context) -> Self::Result {
+        let maybe_lobby_addr = self
+            .open_lobby_map
+            .get(&msg.game_id)
+            .or_else(|| self.closed_lobby_map.get(&msg.game_id))
+            .map(|lobby_info| lobby_info.addr.clone());
+        let user_mgr = self.user_mgr.clone();
+        Box::pin(
+            async move {
+                let lobby_addr = maybe_lobby_addr.ok_or(SrvMsgError::LobbyNotFound)?;
+                let friends = user_mgr
+                    .send(user_mgr::msg::GetFriendIds(msg.user_id))
+                    .await
+                    .map_err(|_| SrvMsgError::Internal)?;
+                let joined = lobby_addr
+                    .send(LobbyMessage::SpectatorJoined {
+                        addr: msg.addr,
+                        friends,
+                    })
+                    .await;
+                match joined {
+                    Ok(Ok(())) => Ok(lobby_addr),
+                    // Strangers can't tell other people's games from ones that don't exist
+                    _ => Err(SrvMsgError::LobbyNotFound),
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+/// Sent by the `ConnectionManager` for every lobby of a snapshot once its clients are restored
+pub struct RestoreLobby {
+    pub snapshot: LobbySnapshot,
+    pub host: Addr<ClientState>,
+    pub joined: Option<Addr<ClientState>>, // None if the host plays against a bot
+}
+
+impl Message for RestoreLobby {
+    type Result = ();
+}
+
+impl Handler<RestoreLobby> for LobbyManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: RestoreLobby, ctx: &mut Self::Context) -> Self::Result {
+        let game_id = msg.snapshot.game_id;
+        if self.open_lobby_map.contains_key(&game_id)
+            || self.closed_lobby_map.contains_key(&game_id)
+        {
+            println!(
+                "LobbyMgr: Lobby {} already exists, not restoring it",
+                game_id
+            );
+            return;
+        }
+        // Full lobbies can't be joined, so private ones are kept as closed ones as well
+        let kind = match msg
+            .snapshot
+            .bot_level
+            .and_then(|level| BotLevel::new(level.max(0) as usize))
+        {
+            Some(level) => LobbyKind::Bot(level),
+            None => LobbyKind::Public,
+        };
+        let lobby_id = LobbyId::new();
+        let lobby_addr = match Lobby::restore(
+            msg.snapshot,
+            lobby_id.clone(),
+            ctx.address(),
+            self.user_mgr.clone(),
+            self.logger.clone(),
+            msg.host.clone(),
+            msg.joined.clone(),
+        ) {
+            Some(lobby_addr) => lobby_addr,
+            None => {
+                println!("LobbyMgr: Failed to restore lobby {}", game_id);
+                return;
+            }
+        };
+        self.closed_lobby_map
+            .insert(game_id, LobbyInfo::new(lobby_id, lobby_addr.clone(), kind));
+        msg.host.do_send(ClientStateMessage::RestoredLobby(
+            Player::One,
+            lobby_addr.clone(),
+        ));
+        if let Some(joined) = msg.joined {
+            joined.do_send(ClientStateMessage::RestoredLobby(Player::Two, lobby_addr));
+        }
+        println!("LobbyMgr: Restored lobby {}", game_id);
+    }
+}
+
+/// Stops starting new games before the server restarts: the queue is cleared, lobbies without
+/// a game are closed and no rematches are played. Resolves with the number of games that are
+/// still running once all others are over or the deadline has passed.
+pub struct Drain {
+    pub deadline: Instant,
+}
+
+impl Message for Drain {
+    type Result = usize;
+}
+
+impl Handler<Drain> for LobbyManager {
+    type Result = ResponseActFuture<Self, usize>;
+
+    fn handle(&mut self, msg: Drain, _ctx: &mut Self::Context) -> Self::Result {
+        self.draining = true;
+        for entry in self.queue.clear() {
+            entry.player.addr.do_send(ClientStateMessage::Reset);
+            entry.player.addr.do_send(ServerMessage::LobbyClosing);
+        }
+        self.send_queue_update();
+        let lobbies: Vec<Addr<Lobby>> = self
+            .open_lobby_map
+            .values()
+            .chain(self.closed_lobby_map.values())
+            .map(|lobby_info| lobby_info.addr.clone())
+            .collect();
+        println!("LobbyMgr: Draining {} lobbies", lobbies.len());
+        for lobby in &lobbies {
+            lobby.do_send(LobbyMessage::Drain);
+        }
+        Box::pin(
+            async move {
+                loop {
+                    // Closed lobbies can't be reached anymore and count as finished
+                    let running = join_all(lobbies.iter().map(|lobby| lobby.send(IsGameRunning)))
+                        .await
+                        .into_iter()
+                        .filter(|res| matches!(res, Ok(true)))
+                        .count();
+                    if running == 0 || Instant::now() >= msg.deadline {
+                        return running;
+                    }
+                    actix_rt::time::delay_for(Duration::from_secs(DRAIN_CHECK_INTERVAL_S)).await;
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+/// Resolves once the results of all finished games are stored. Lobbies report them through
+/// the `LobbyManager`, so this passes through its mailbox before asking the `UserManager`.
+pub struct WaitForResults;
+
+impl Message for WaitForResults {
+    type Result = ();
+}
+
+impl Handler<WaitForResults> for LobbyManager {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, _: WaitForResults, _ctx: &mut Self::Context) -> Self::Result {
+        let stored = self.user_mgr.send(user_mgr::msg::WaitForStoredGames);
+        Box::pin(
+            async move {
+                if stored.await.is_err() {
+                    println!("LobbyMgr: Failed to wait for stored games");
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
 pub struct GetIsPlayerWaitingMsg;
 
 impl Message for GetIsPlayerWaitingMsg {
@@ -240,14 +591,16 @@ impl Handler<GetIsPlayerWaitingMsg> for LobbyManager {
     type Result = bool;
 
     fn handle(&mut self, _: GetIsPlayerWaitingMsg, _ctx: &mut Self::Context) -> Self::Result {
-        self.open_lobby.is_some()
+        !self.queue.is_empty()
     }
 }
 
 pub enum LobbyManagerMsg {
     CloseLobbyMsg(GameId),
+    LeaveQueue(Addr<ClientState>),
     PlayedGame(PlayedGameInfo),
-    // Shutdown,
+    PlayedSeries(PlayedSeriesInfo),
+    TournamentBacklink(Addr<TournamentManager>),
 }
 impl Message for LobbyManagerMsg {
     type Result = ();
@@ -259,37 +612,43 @@ impl Handler<LobbyManagerMsg> for LobbyManager {
         match msg {
             CloseLobbyMsg(game_id) => {
                 println!("LobbyMgr: Removed lobby {}", game_id);
-                if let Some(LobbyInfo {
-                    game_id: open_game_id,
-                    ..
-                }) = self.open_lobby
-                {
-                    if open_game_id == game_id {
-                        self.open_lobby = None;
+                self.open_lobby_map.remove(&game_id);
+                if let Some(lobby_info) = self.closed_lobby_map.remove(&game_id) {
+                    if let (LobbyKind::Tournament, Some(tournament_mgr)) =
+                        (lobby_info.kind, &self.tournament_mgr)
+                    {
+                        tournament_mgr.do_send(TournamentMsg::LobbyClosed(game_id));
                     }
                 }
-
-                self.open_lobby_map.remove(&game_id);
-                self.closed_lobby_map.remove(&game_id);
-                self.connection_mgr
-                    .do_send(ConnectionManagerMsg::Update(self.open_lobby.is_some()));
+            }
+            LeaveQueue(addr) => {
+                if self
+                    .queue
+                    .remove_where(|player| player.addr == addr)
+                    .is_some()
+                {
+                    self.send_queue_update();
+                }
             }
 
             PlayedGame(game_info) => {
+                if let (Some(_), Some(tournament_mgr)) =
+                    (&game_info.tournament_id, &self.tournament_mgr)
+                {
+                    tournament_mgr.do_send(TournamentMsg::GameOver(game_info.clone()));
+                }
                 self.user_mgr.do_send(user_mgr::msg::IntUserMgrMsg::Game(
                     user_mgr::msg::GameMsg::PlayedGame(game_info),
                 ));
-            } /*LobbyManagerMsg::Shutdown => {
-              println!(
-              "LobbyMgr: Shutting down ({} active lobbies).",
-              self.lobby_map.len()
-              );
-              for (game_id, lobby_info) in self.lobby_map.drain() {
-              println!("LobbyMgr: Sending close command to lobby {}", game_id);
-              lobby_info.addr.do_send(LobbyMessage::LobbyClose);
-              }
-              ctx.stop();
-              }*/
+            }
+            PlayedSeries(series_info) => {
+                self.user_mgr.do_send(user_mgr::msg::IntUserMgrMsg::Game(
+                    user_mgr::msg::GameMsg::PlayedSeries(series_info),
+                ));
+            }
+            TournamentBacklink(addr) => {
+                self.tournament_mgr = Some(addr);
+            }
         }
     }
 }
@@ -345,12 +704,19 @@ impl Handler<BattleReq> for LobbyManager {
         // })
         // .wait(ctx);
         // msg.sender.0.do_send(ServerMessage::Okay);
+        if self.draining {
+            msg.sender_addr
+                .do_send(ServerMessage::Error(Some(SrvMsgError::ServerRestarting)));
+            return;
+        }
         let lobby_info = self.create_lobby(
             msg.sender_addr.clone(),
             Some(msg.sender_uid),
             ctx.address(),
             self.user_mgr.clone(),
             LobbyKind::Private,
+            LobbySettings::default(),
+            None,
         );
 
         msg.sender_addr
@@ -363,6 +729,68 @@ impl Handler<BattleReq> for LobbyManager {
     }
 }
 
+impl Handler<TournamentGameRequest> for LobbyManager {
+    type Result = ResponseActFuture<Self, Result<GameId, Vec<UserId>>>;
+
+    fn handle(&mut self, msg: TournamentGameRequest, _ctx: &mut Self::Context) -> Self::Result {
+        let user_mgr = self.user_mgr.clone();
+        let (one, two) = (msg.one, msg.two);
+        Box::pin(
+            async move {
+                let one_addr = user_mgr.send(user_mgr::msg::GetPlayingAddr(one)).await;
+                let two_addr = user_mgr.send(user_mgr::msg::GetPlayingAddr(two)).await;
+                (one_addr.ok().flatten(), two_addr.ok().flatten())
+            }
+            .into_actor(self)
+            .map(move |addrs, act, ctx| {
+                if act.draining {
+                    return Err(Vec::new());
+                }
+                let (one_addr, two_addr) = match addrs {
+                    (Some(one_addr), Some(two_addr)) => (one_addr, two_addr),
+                    (one_addr, two_addr) => {
+                        let mut absent = Vec::new();
+                        if one_addr.is_none() {
+                            absent.push(one);
+                        }
+                        if two_addr.is_none() {
+                            absent.push(two);
+                        }
+                        return Err(absent);
+                    }
+                };
+                let response = act.create_lobby(
+                    one_addr.clone(),
+                    Some(one),
+                    ctx.address(),
+                    act.user_mgr.clone(),
+                    LobbyKind::Tournament,
+                    msg.settings,
+                    Some(msg.tournament_id.clone()),
+                );
+                let tournament_id = msg.tournament_id.to_string();
+                one_addr.do_send(ClientStateMessage::TournamentMatched(
+                    Player::One,
+                    response.lobby_addr.clone(),
+                    tournament_id.clone(),
+                    response.game_id,
+                ));
+                response.lobby_addr.do_send(LobbyMessage::PlayerJoined {
+                    joined_addr: Participant::Client(two_addr.clone()),
+                    maybe_uid: Some(two),
+                });
+                two_addr.do_send(ClientStateMessage::TournamentMatched(
+                    Player::Two,
+                    response.lobby_addr,
+                    tournament_id,
+                    response.game_id,
+                ));
+                Ok(response.game_id)
+            }),
+        )
+    }
+}
+
 impl Actor for LobbyManager {
     type Context = Context<Self>;
 
@@ -372,5 +800,9 @@ impl Actor for LobbyManager {
 
         self.connection_mgr
             .do_send(ConnectionManagerMsg::Backlink(ctx.address()));
+
+        ctx.run_interval(Duration::from_secs(QUEUE_MATCH_INTERVAL_S), |act, ctx| {
+            act.match_queue(ctx)
+        });
     }
 }
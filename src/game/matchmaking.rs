@@ -0,0 +1,214 @@
+//! Queue of players waiting for a public game.
+//! Registered players are paired by skill rating, the accepted rating difference
+//! grows the longer someone waits. Anonymous players are paired with each other
+//! right away and after `MATCH_ANY_AFTER_S` anyone is a valid opponent.
+
+use super::lobby_mgr::LobbySettings;
+
+use std::time::{Duration, Instant};
+
+const INITIAL_RATING_WINDOW: i32 = 100;
+const RATING_WINDOW_GROWTH_PER_S: i32 = 10;
+const MATCH_ANY_AFTER_S: u64 = 30;
+
+pub struct QueueEntry<T> {
+    pub player: T,
+    pub maybe_rating: Option<i32>, // None for anonymous players
+    pub settings: LobbySettings,
+    pub since: Instant,
+}
+
+pub struct MatchmakingQueue<T> {
+    entries: Vec<QueueEntry<T>>, // Ordered by waiting time, longest first
+}
+
+impl<T> MatchmakingQueue<T> {
+    pub fn new() -> Self {
+        MatchmakingQueue {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn push(&mut self, entry: QueueEntry<T>) {
+        self.entries.push(entry);
+    }
+
+    /// Removes and returns the best waiting opponent for a player who just arrived, if any
+    pub fn find_match(&mut self, newcomer: &QueueEntry<T>, now: Instant) -> Option<QueueEntry<T>> {
+        let best = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| Self::match_cost(entry, newcomer, now).map(|cost| (i, cost)))
+            .min_by_key(|(_, cost)| *cost)
+            .map(|(i, _)| i)?;
+        Some(self.entries.remove(best))
+    }
+
+    /// Pairs up waiting players whose search windows have grown enough to overlap.
+    /// The first player of each pair is the one who waited longer.
+    pub fn pop_matches(&mut self, now: Instant) -> Vec<(QueueEntry<T>, QueueEntry<T>)> {
+        let mut pairs = Vec::new();
+        let mut i = 0;
+        while i < self.entries.len() {
+            let best = self
+                .entries
+                .iter()
+                .enumerate()
+                .skip(i + 1)
+                .filter_map(|(j, entry)| {
+                    Self::match_cost(&self.entries[i], entry, now).map(|cost| (j, cost))
+                })
+                .min_by_key(|(_, cost)| *cost)
+                .map(|(j, _)| j);
+            if let Some(j) = best {
+                let second = self.entries.remove(j);
+                let first = self.entries.remove(i);
+                pairs.push((first, second));
+            } else {
+                i += 1;
+            }
+        }
+        pairs
+    }
+
+    pub fn remove_where<F: Fn(&T) -> bool>(&mut self, predicate: F) -> Option<QueueEntry<T>> {
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| predicate(&entry.player))?;
+        Some(self.entries.remove(index))
+    }
+
+    /// Removes everyone, e.g. when no games can be started anymore
+    pub fn clear(&mut self) -> Vec<QueueEntry<T>> {
+        self.entries.drain(..).collect()
+    }
+
+    /// Removes everyone who has been waiting for at least `timeout`
+    pub fn remove_expired(&mut self, now: Instant, timeout: Duration) -> Vec<QueueEntry<T>> {
+        let (expired, waiting) = self
+            .entries
+            .drain(..)
+            .partition(|entry| now.duration_since(entry.since) >= timeout);
+        self.entries = waiting;
+        expired
+    }
+
+    /// `None` if the two players may not be paired (yet), otherwise lower is better
+    fn match_cost(a: &QueueEntry<T>, b: &QueueEntry<T>, now: Instant) -> Option<i32> {
+        if a.settings != b.settings {
+            return None;
+        }
+        let waited = now.duration_since(a.since.min(b.since));
+        let diff = match (a.maybe_rating, b.maybe_rating) {
+            (Some(rating_a), Some(rating_b)) => Some((rating_a - rating_b).abs()),
+            (None, None) => Some(0),
+            _ => None,
+        };
+        if waited >= Duration::from_secs(MATCH_ANY_AFTER_S) {
+            return Some(diff.unwrap_or(i32::MAX));
+        }
+        let window = INITIAL_RATING_WINDOW + RATING_WINDOW_GROWTH_PER_S * waited.as_secs() as i32;
+        diff.filter(|diff| *diff <= window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::game_info::BoardConfig;
+
+    fn entry(
+        player: u32,
+        maybe_rating: Option<i32>,
+        waited_s: u64,
+        now: Instant,
+    ) -> QueueEntry<u32> {
+        QueueEntry {
+            player,
+            maybe_rating,
+            settings: LobbySettings::default(),
+            since: now - Duration::from_secs(waited_s),
+        }
+    }
+
+    #[test]
+    fn anonymous_players_are_paired_immediately() {
+        let now = Instant::now();
+        let mut queue = MatchmakingQueue::new();
+        queue.push(entry(1, None, 0, now));
+        queue.push(entry(2, Some(1000), 0, now));
+        let found = queue.find_match(&entry(3, None, 0, now), now);
+        assert_eq!(found.map(|e| e.player), Some(1));
+    }
+
+    #[test]
+    fn closest_rating_is_preferred() {
+        let now = Instant::now();
+        let mut queue = MatchmakingQueue::new();
+        queue.push(entry(1, Some(1080), 0, now));
+        queue.push(entry(2, Some(1010), 0, now));
+        queue.push(entry(3, Some(1500), 0, now));
+        let found = queue.find_match(&entry(4, Some(1000), 0, now), now);
+        assert_eq!(found.map(|e| e.player), Some(2));
+    }
+
+    #[test]
+    fn window_widens_over_time() {
+        let now = Instant::now();
+        let mut queue = MatchmakingQueue::new();
+        queue.push(entry(1, Some(1300), 0, now));
+        queue.push(entry(2, Some(1000), 0, now));
+        assert!(queue.pop_matches(now).is_empty());
+
+        let later = now + Duration::from_secs(20);
+        let pairs = queue.pop_matches(later);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].0.player, pairs[0].1.player), (1, 2));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn anyone_is_accepted_after_timeout() {
+        let now = Instant::now();
+        let mut queue = MatchmakingQueue::new();
+        queue.push(entry(1, Some(2000), 0, now));
+        assert!(queue.find_match(&entry(2, None, 0, now), now).is_none());
+
+        let later = now + Duration::from_secs(MATCH_ANY_AFTER_S);
+        assert!(queue.find_match(&entry(2, None, 0, later), later).is_some());
+    }
+
+    #[test]
+    fn different_settings_are_never_paired() {
+        let now = Instant::now();
+        let mut queue = MatchmakingQueue::new();
+        queue.push(entry(1, None, 600, now));
+        let mut other = entry(2, None, 600, now);
+        other.settings.board = BoardConfig::new(7, 6, 4).unwrap();
+        queue.push(other);
+        assert!(queue.pop_matches(now).is_empty());
+    }
+
+    #[test]
+    fn longest_waiting_player_is_first() {
+        let now = Instant::now();
+        let mut queue = MatchmakingQueue::new();
+        queue.push(entry(2, Some(1200), 40, now));
+        queue.push(entry(1, Some(1000), 10, now));
+        queue.push(entry(3, Some(1010), 5, now));
+        let expired = queue.remove_expired(now, Duration::from_secs(30));
+        assert_eq!(
+            expired.iter().map(|e| e.player).collect::<Vec<_>>(),
+            vec![2]
+        );
+        let pairs = queue.pop_matches(now);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].0.player, pairs[0].1.player), (1, 3));
+    }
+}
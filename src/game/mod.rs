@@ -2,11 +2,20 @@ pub mod client_adapter;
 pub mod client_connection;
 pub mod client_state;
 pub mod connection_mgr;
+pub mod game_info;
 pub mod lobby_mgr;
 pub mod msg;
+pub mod msg_json;
+pub mod snapshot;
+pub mod tournament;
+pub mod tournament_mgr;
 
-mod game_info;
+mod board;
+mod bot;
+mod clock;
 mod lobby;
+mod matchmaking;
+mod series;
 
 use crate::api::users::user_mgr::UserManager;
 pub use client_connection::ClientConnection;
@@ -1,14 +1,20 @@
-use super::lobby_mgr::LobbyKind;
+use super::lobby_mgr::{LobbyKind, LobbySettings};
 use super::{
+    bot::BotLevel,
     connection_mgr::WSSessionToken,
-    game_info::{GameId, GAME_ID_LEN},
+    game_info::{BoardConfig, GameId, Player, GAME_ID_LEN},
+    msg_json,
 };
 use crate::api::users::{session_token::SessionToken, user::UserId};
 use actix::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone)]
 pub enum ReliablePacketIn {
-    Ack(usize),                // Acknowledge the server's message with that id
+    Ack(usize), // Acknowledge the server's message with that id
+    /// Acknowledge all messages up to that id plus the listed ones
+    CumulativeAck(usize, Vec<usize>),
     Msg(usize, PlayerMessage), // Actual message with id and content
 }
 
@@ -26,6 +32,17 @@ impl ReliablePacketIn {
             } else {
                 Err(ReliabilityError::InvalidFormat)
             }
+        } else if (parts.len() == 2 || parts.len() == 3) && uppercase == "ACK_UPTO" {
+            // ACK_UPTO::<id>, optionally followed by ::<id>,<id>,...
+            let selective: Result<Vec<usize>, _> = match parts.get(2) {
+                Some(ids) => ids.split(',').map(str::parse).collect(),
+                None => Ok(Vec::new()),
+            };
+            if let (Ok(id), Ok(selective)) = (parts[1].parse::<usize>(), selective) {
+                Ok(ReliablePacketIn::CumulativeAck(id, selective))
+            } else {
+                Err(ReliabilityError::InvalidFormat)
+            }
         } else if parts.len() == 3 && uppercase == "MSG" {
             if let Ok(id) = parts[1].parse::<usize>() {
                 if let Some(player_msg) = PlayerMessage::parse(parts[2]) {
@@ -40,15 +57,21 @@ impl ReliablePacketIn {
             Err(ReliabilityError::UnknownMessage)
         };
     }
+
+    /// Protocol version 5 and newer, see `msg_json`
+    pub fn parse_json(orig: &str) -> Result<ReliablePacketIn, ReliabilityError> {
+        msg_json::parse_packet(orig)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum ReliablePacketOut {
     Ack(usize), // Acknowledge the client's message with that id
+    /// Acknowledge all messages up to that id plus the listed ones
+    CumulativeAck(usize, Vec<usize>),
     Msg {
         id: usize,
         msg: ServerMessage,
-        retry_count: usize,
     },
     Err(ReliabilityError),
 }
@@ -62,17 +85,28 @@ impl ReliablePacketOut {
         use ReliablePacketOut::*;
         match self {
             Ack(id) => format!("ACK::{}", id),
-            Msg {
+            CumulativeAck(id, selective) if selective.is_empty() => format!("ACK_UPTO::{}", id),
+            CumulativeAck(id, selective) => format!(
+                "ACK_UPTO::{}::{}",
                 id,
-                msg,
-                retry_count: _,
-            } => format!("MSG::{}::{}", id, msg.serialize()),
+                selective
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            Msg { id, msg } => format!("MSG::{}::{}", id, msg.serialize()),
             Err(err) => format!("ERR::{}", err.serialize()),
         }
     }
+
+    pub fn serialize_json(self) -> String {
+        msg_json::serialize_packet(self)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ReliabilityError {
     InvalidContent, // Message content could not be parsed
     InvalidFormat,  // ReliableMessage could not be parsed
@@ -100,13 +134,18 @@ impl Into<ReliablePacketOut> for ReliabilityError {
     }
 }
 
+#[derive(Debug)]
 pub struct HelloIn {
     pub protocol_version: usize,
     pub maybe_session_token: Option<WSSessionToken>,
 }
 
 impl HelloIn {
+    /// Accepts the text hello of all versions as well as the JSON hello of version 5
     pub fn parse(orig: &str) -> Option<Self> {
+        if orig.starts_with('{') {
+            return msg_json::parse_hello(orig);
+        }
         //let uppercase = orig.to_uppercase();
         let parts: Vec<_> = orig.split("::").collect();
         if parts.len() == 3 && parts[0] == "HELLO" {
@@ -149,26 +188,65 @@ impl HelloOut {
             OutDated => "HELLO::OUTDATED".to_string(),
         }
     }
+
+    pub fn serialize_json(self) -> String {
+        msg_json::serialize_hello(self)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ServerMessage {
     PlaceChip(usize),
     OpponentLeaving,
     OpponentJoining,
-    GameStart(bool, Option<String>),
-    GameOver(bool), // true if recipient won
+    GameStart(bool, Option<String>, BoardConfig), // my turn, opponent name, board
+    GameOver(bool),                               // true if recipient won
+    GameDraw,
+    OpponentResigned, // Followed by GameOver
+    DrawOffered,
+    DrawDeclined,
+    OpponentReconnecting(u64), // seconds until the game is forfeited
+    OpponentReconnected,
+    /// Everything a client needs to redraw a running game
+    BoardState {
+        board: BoardConfig,
+        my_turn: bool,
+        move_count: usize,
+        opponent: Option<String>,
+        clock: Option<(i64, i64)>, // your remaining millis, opponent's remaining millis
+        moves: Vec<usize>,
+    },
+    RatingChange(i32, i32), // change from the last game, new skill rating
+    /// Sent after GameStart in series lobbies
+    SeriesScore(usize, usize, usize, usize), // your wins, opponent's wins, draws, best of
+    /// true if recipient won the series
+    SeriesOver(bool),
+    SeriesDrawn, // Both players have the same points after all games of the series
     LobbyClosing,
     ReadyForGamePing,
     LoginResponse { success: bool },
     Error(Option<SrvMsgError>),
     BattleReq(UserId, GameId),
+    /// The next tournament game is about to start in this lobby
+    TournamentMatch(String, GameId), // tournament id, game id
     CurrentServerState(usize, bool), // connected players, someone wants to play
     ChatMessage(bool, String, Option<String>), // is_global, message, sender_name
     ChatRead(bool),                  // is_global
+    SpectatorCount(usize),           // Sent to everyone in the lobby when it changes
+    Clock(i64, i64),                 // your remaining millis, opponent's remaining millis
+
+    // Spectators get these instead of the player's view of GameStart and GameOver
+    SpectateBoard(BoardConfig, Option<Player>, Vec<usize>), // board, first player if started, moves so far
+    SpectateGameStart(BoardConfig, Player),                 // board, first player
+    SpectateGameOver(Option<Player>),                       // winner, None if drawn
+    SpectateClock(i64, i64), // remaining millis of player one and two
     
     /// Sent when another client logs into an account which is authenticated on this connection -> this one is closed
     CloseOtherClientLogin,
+    /// The session of this connection was revoked, the client is logged out
+    SessionRevoked,
+    /// No new games can be started, running ones may be finished
+    ServerRestarting(u64), // seconds until the server goes down
 }
 
 impl ServerMessage {
@@ -178,16 +256,65 @@ impl ServerMessage {
             PlaceChip(row) => format!("PC:{}", row),
             OpponentLeaving => "OPP_LEAVING".to_owned(),
             OpponentJoining => "OPP_JOINED".to_owned(),
-            GameStart(your_turn, maybe_username) => format!(
-                "GAME_START:{}{}",
-                if your_turn { "YOU" } else { "OPP" },
-                if let Some(username) = maybe_username {
-                    format!(":{}", username)
+            GameStart(your_turn, maybe_username, board) => {
+                let turn = if your_turn { "YOU" } else { "OPP" };
+                if board.is_default() {
+                    // Keep the old format so that clients which only know 7x7 keep working
+                    format!(
+                        "GAME_START:{}{}",
+                        turn,
+                        if let Some(username) = maybe_username {
+                            format!(":{}", username)
+                        } else {
+                            "".to_owned()
+                        }
+                    )
+                } else {
+                    format!(
+                        "GAME_START:{}:{}:{}",
+                        turn,
+                        maybe_username.unwrap_or_default(),
+                        board
+                    )
+                }
+            }
+            GameOver(you_win) => format!("GAME_OVER:{}", if you_win { "YOU" } else { "OPP" }),
+            GameDraw => "GAME_OVER:DRAW".to_owned(),
+            OpponentResigned => "OPP_RESIGNED".to_owned(),
+            DrawOffered => "DRAW_OFFERED".to_owned(),
+            DrawDeclined => "DRAW_DECLINED".to_owned(),
+            OpponentReconnecting(seconds) => format!("OPP_RECONNECTING:{}", seconds),
+            OpponentReconnected => "OPP_RECONNECTED".to_owned(),
+            BoardState {
+                board,
+                my_turn,
+                move_count,
+                opponent,
+                clock,
+                moves,
+            } => format!(
+                "BOARD_STATE:{}:{}:{}:{}:{}{}",
+                board,
+                if my_turn { "YOU" } else { "OPP" },
+                move_count,
+                opponent.unwrap_or_default(),
+                moves
+                    .iter()
+                    .map(|column| column.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                if let Some((mine, opponent)) = clock {
+                    format!(":{}:{}", mine, opponent)
                 } else {
                     "".to_owned()
                 }
             ),
-            GameOver(you_win) => format!("GAME_OVER:{}", if you_win { "YOU" } else { "OPP" }),
+            RatingChange(change, rating) => format!("RATING_CHANGE:{}:{}", change, rating),
+            SeriesScore(mine, opponent, draws, best_of) => {
+                format!("SERIES:{}:{}:{}:{}", mine, opponent, draws, best_of)
+            }
+            SeriesOver(you_win) => format!("SERIES_OVER:{}", if you_win { "YOU" } else { "OPP" }),
+            SeriesDrawn => "SERIES_OVER:DRAW".to_owned(),
             LobbyClosing => "LOBBY_CLOSING".to_owned(),
             ReadyForGamePing => "READY_FOR_GAME_PING".to_owned(),
             LoginResponse { success } => format!("LOGIN_RESPONSE:{}", success.to_string()),
@@ -201,6 +328,9 @@ impl ServerMessage {
             BattleReq(requesting_id, lobby_id) => {
                 format!("BATTLE_REQ:{}:{}", requesting_id, lobby_id)
             }
+            TournamentMatch(tournament_id, game_id) => {
+                format!("TOURNAMENT_MATCH:{}:{}", tournament_id, game_id)
+            }
             CurrentServerState(connected_players, player_waiting) => format!(
                 "CURRENT_SERVER_STATE:{}:{}",
                 connected_players, player_waiting
@@ -215,12 +345,188 @@ impl ServerMessage {
                 format!("CHAT_MSG:{}:{}:{}", is_global, encoded_message, sender_name)
             }
             ChatRead(is_global) => format!("CHAT_READ:{}", is_global),
+            SpectatorCount(count) => format!("SPECTATORS:{}", count),
+            Clock(mine, opponent) => format!("CLOCK:{}:{}", mine, opponent),
+            SpectateBoard(board, maybe_first, moves) => format!(
+                "SPECTATE_BOARD:{}:{}:{}",
+                board,
+                maybe_first.map_or("NONE", serialize_player),
+                moves
+                    .iter()
+                    .map(|column| column.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            SpectateGameStart(board, first) => {
+                format!("SPECTATE_GAME_START:{}:{}", board, serialize_player(first))
+            }
+            SpectateGameOver(maybe_winner) => format!(
+                "SPECTATE_GAME_OVER:{}",
+                maybe_winner.map_or("DRAW", serialize_player)
+            ),
+            SpectateClock(one, two) => format!("SPECTATE_CLOCK:{}:{}", one, two),
             CloseOtherClientLogin => "CLOSE_OTHER_CLIENT_LOGIN".to_owned(),
+            SessionRevoked => "SESSION_REVOKED".to_owned(),
+            ServerRestarting(seconds) => format!("SERVER_RESTARTING:{}", seconds),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+fn serialize_player(player: Player) -> &'static str {
+    player.select("ONE", "TWO")
+}
+
+/// How a message looks in the text protocol, served with the protocol schema for clients
+/// below protocol version 5. `tag` is the message's type in the JSON protocol, `format` a
+/// readable description and `pattern` a regex matching every encoding of the message.
+pub struct TextFormat {
+    pub tag: &'static str,
+    pub format: &'static str,
+    pub pattern: &'static str,
+}
+
+const fn text(tag: &'static str, format: &'static str, pattern: &'static str) -> TextFormat {
+    TextFormat {
+        tag,
+        format,
+        pattern,
+    }
+}
+
+/// Everything `ServerMessage::serialize` produces. Keep in sync, the tests check every
+/// message against it.
+pub const SERVER_TEXT_FORMATS: &[TextFormat] = &[
+    text("place_chip", "PC:<column>", r"^PC:[0-9]+$"),
+    text("opponent_leaving", "OPP_LEAVING", r"^OPP_LEAVING$"),
+    text("opponent_joining", "OPP_JOINED", r"^OPP_JOINED$"),
+    text(
+        "game_start",
+        "GAME_START:<YOU|OPP>[:<opponent>] on the default board, \
+         GAME_START:<YOU|OPP>:[<opponent>]:<board> otherwise",
+        r"^GAME_START:(YOU|OPP)(:[^:]*(:[0-9]+X[0-9]+X[0-9]+)?)?$",
+    ),
+    text("game_over", "GAME_OVER:<YOU|OPP>", r"^GAME_OVER:(YOU|OPP)$"),
+    text("game_draw", "GAME_OVER:DRAW", r"^GAME_OVER:DRAW$"),
+    text("opponent_resigned", "OPP_RESIGNED", r"^OPP_RESIGNED$"),
+    text("draw_offered", "DRAW_OFFERED", r"^DRAW_OFFERED$"),
+    text("draw_declined", "DRAW_DECLINED", r"^DRAW_DECLINED$"),
+    text(
+        "opponent_reconnecting",
+        "OPP_RECONNECTING:<seconds>",
+        r"^OPP_RECONNECTING:[0-9]+$",
+    ),
+    text(
+        "opponent_reconnected",
+        "OPP_RECONNECTED",
+        r"^OPP_RECONNECTED$",
+    ),
+    text(
+        "board_state",
+        "BOARD_STATE:<board>:<YOU|OPP>:<move count>:[<opponent>]:<comma separated columns>\
+         [:<my millis>:<opponent's millis>]",
+        r"^BOARD_STATE:[0-9]+X[0-9]+X[0-9]+:(YOU|OPP):[0-9]+:[^:]*:([0-9]+(,[0-9]+)*)?(:-?[0-9]+:-?[0-9]+)?$",
+    ),
+    text(
+        "rating_change",
+        "RATING_CHANGE:<change>:<rating>",
+        r"^RATING_CHANGE:-?[0-9]+:-?[0-9]+$",
+    ),
+    text(
+        "series_score",
+        "SERIES:<wins>:<opponent's wins>:<draws>:<best of>",
+        r"^SERIES:[0-9]+:[0-9]+:[0-9]+:[0-9]+$",
+    ),
+    text(
+        "series_over",
+        "SERIES_OVER:<YOU|OPP>",
+        r"^SERIES_OVER:(YOU|OPP)$",
+    ),
+    text("series_drawn", "SERIES_OVER:DRAW", r"^SERIES_OVER:DRAW$"),
+    text("lobby_closing", "LOBBY_CLOSING", r"^LOBBY_CLOSING$"),
+    text(
+        "ready_for_game_ping",
+        "READY_FOR_GAME_PING",
+        r"^READY_FOR_GAME_PING$",
+    ),
+    text(
+        "login_response",
+        "LOGIN_RESPONSE:<true|false>",
+        r"^LOGIN_RESPONSE:(true|false)$",
+    ),
+    text(
+        "error",
+        "ERROR[:<SrvMsgError code>]",
+        r"^ERROR(:[A-Za-z]+)?$",
+    ),
+    text(
+        "battle_req",
+        "BATTLE_REQ:<user id>:<game id>",
+        r"^BATTLE_REQ:[0-9a-f]+:[A-Z]+$",
+    ),
+    text(
+        "tournament_match",
+        "TOURNAMENT_MATCH:<tournament id>:<game id>",
+        r"^TOURNAMENT_MATCH:[0-9a-f]+:[A-Z]+$",
+    ),
+    text(
+        "current_server_state",
+        "CURRENT_SERVER_STATE:<connected players>:<true|false, someone is waiting>",
+        r"^CURRENT_SERVER_STATE:[0-9]+:(true|false)$",
+    ),
+    text(
+        "chat_message",
+        "CHAT_MSG:<true|false, global>:<base64 message>:[<sender>]",
+        r"^CHAT_MSG:(true|false):[A-Za-z0-9+/=]*:[^:]*$",
+    ),
+    text(
+        "chat_read",
+        "CHAT_READ:<true|false, global>",
+        r"^CHAT_READ:(true|false)$",
+    ),
+    text(
+        "spectator_count",
+        "SPECTATORS:<count>",
+        r"^SPECTATORS:[0-9]+$",
+    ),
+    text(
+        "clock",
+        "CLOCK:<my millis>:<opponent's millis>",
+        r"^CLOCK:-?[0-9]+:-?[0-9]+$",
+    ),
+    text(
+        "spectate_board",
+        "SPECTATE_BOARD:<board>:<ONE|TWO|NONE, first player>:<comma separated columns>",
+        r"^SPECTATE_BOARD:[0-9]+X[0-9]+X[0-9]+:(ONE|TWO|NONE):([0-9]+(,[0-9]+)*)?$",
+    ),
+    text(
+        "spectate_game_start",
+        "SPECTATE_GAME_START:<board>:<ONE|TWO, first player>",
+        r"^SPECTATE_GAME_START:[0-9]+X[0-9]+X[0-9]+:(ONE|TWO)$",
+    ),
+    text(
+        "spectate_game_over",
+        "SPECTATE_GAME_OVER:<ONE|TWO|DRAW>",
+        r"^SPECTATE_GAME_OVER:(ONE|TWO|DRAW)$",
+    ),
+    text(
+        "spectate_clock",
+        "SPECTATE_CLOCK:<player one's millis>:<player two's millis>",
+        r"^SPECTATE_CLOCK:-?[0-9]+:-?[0-9]+$",
+    ),
+    text(
+        "close_other_client_login",
+        "CLOSE_OTHER_CLIENT_LOGIN",
+        r"^CLOSE_OTHER_CLIENT_LOGIN$",
+    ),
+    text("session_revoked", "SESSION_REVOKED", r"^SESSION_REVOKED$"),
+    text(
+        "server_restarting",
+        "SERVER_RESTARTING:<seconds>",
+        r"^SERVER_RESTARTING:[0-9]+$",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum SrvMsgError {
     Internal,
     // InvalidMessage,
@@ -232,9 +538,15 @@ pub enum SrvMsgError {
     AlreadyInLobby,
     GameNotStarted,
     GameNotOver,
+    GameAlreadyOver,
+    NoDrawOffered,
+    GamePaused,
+    NoRematchInTournament,
+    InvalidSettings,
     NotLoggedIn,
     UserNotPlaying,
     NoSuchUser,
+    ServerRestarting,
 }
 
 impl SrvMsgError {
@@ -250,21 +562,33 @@ impl SrvMsgError {
             GameNotStarted => "GameNotStarted".to_owned(),
             AlreadyInLobby => "AlreadyInLobby".to_owned(),
             GameNotOver => "GameNotOver".to_owned(),
+            GameAlreadyOver => "GameAlreadyOver".to_owned(),
+            NoDrawOffered => "NoDrawOffered".to_owned(),
+            GamePaused => "GamePaused".to_owned(),
+            NoRematchInTournament => "NoRematchInTournament".to_owned(),
+            InvalidSettings => "InvalidSettings".to_owned(),
             NotLoggedIn => "NotLoggedIn".to_owned(),
             UserNotPlaying => "UserNotPlaying".to_owned(),
             NoSuchUser => "NoSuchUser".to_owned(),
+            ServerRestarting => "ServerRestarting".to_owned(),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PlayerMessage {
     PlaceChip(usize),
     PlayAgainRequest,
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    BoardStateRequest,
     Leaving,
     ReadyForGamePong,
-    LobbyRequest(LobbyKind),
+    LobbyRequest(LobbyKind, LobbySettings),
     LobbyJoin(GameId),
+    Spectate(GameId),
     Login(SessionToken),
     Logout,
     BattleReq(UserId),
@@ -284,22 +608,48 @@ impl PlayerMessage {
             if let Ok(row) = s[3..4].parse() {
                 return Some(PlaceChip(row));
             }
-        } else if s == "REQ_LOBBY" {
-            return Some(LobbyRequest(LobbyKind::Private));
-        } else if s == "REQ_WW" {
-            return Some(LobbyRequest(LobbyKind::Public));
+        } else if s == "REQ_LOBBY" || s.starts_with("REQ_LOBBY:") {
+            if let Some(settings) = LobbySettings::parse(&s[9..]) {
+                return Some(LobbyRequest(LobbyKind::Private, settings));
+            }
+        } else if s == "REQ_WW" || s.starts_with("REQ_WW:") {
+            if let Some(settings) = LobbySettings::parse(&s[6..]) {
+                return Some(LobbyRequest(LobbyKind::Public, settings));
+            }
+        } else if let Some(rest) = s.strip_prefix("REQ_BOT:") {
+            // REQ_BOT:<level>, optionally followed by lobby settings
+            let (level, settings) = rest.split_at(rest.find(':').unwrap_or(rest.len()));
+            if let (Some(level), Some(settings)) =
+                (BotLevel::parse(level), LobbySettings::parse(settings))
+            {
+                return Some(LobbyRequest(LobbyKind::Bot(level), settings));
+            }
         // } else if s == "PlayerLeaving" {
         //     return Some(PlayerLeaving);
         } else if s.starts_with("JOIN_LOBBY:") && s.len() == 11 + GAME_ID_LEN {
             if let Some(id) = GameId::parse(&s[11..11 + GAME_ID_LEN]) {
                 return Some(LobbyJoin(id));
             }
+        } else if s.starts_with("SPECTATE:") && s.len() == 9 + GAME_ID_LEN {
+            if let Some(id) = GameId::parse(&s[9..9 + GAME_ID_LEN]) {
+                return Some(Spectate(id));
+            }
         } else if s == "LEAVE" {
             return Some(Leaving);
         } else if s == "READY_FOR_GAME_PONG" {
             return Some(ReadyForGamePong);
         } else if s == "PLAY_AGAIN" {
             return Some(PlayAgainRequest);
+        } else if s == "RESIGN" {
+            return Some(Resign);
+        } else if s == "DRAW_OFFER" {
+            return Some(OfferDraw);
+        } else if s == "DRAW_ACCEPT" {
+            return Some(AcceptDraw);
+        } else if s == "DRAW_DECLINE" {
+            return Some(DeclineDraw);
+        } else if s == "BOARD_STATE_REQ" {
+            return Some(BoardStateRequest);
         } else if s.starts_with("LOGIN:") {
             let split: Vec<&str> = orig.split(":").collect();
             if split.len() == 2 {
@@ -332,6 +682,53 @@ impl PlayerMessage {
     }
 }
 
+/// Everything `PlayerMessage::parse` accepts, keywords are case insensitive.
+/// Keep in sync, the tests check every message against it.
+pub const PLAYER_TEXT_FORMATS: &[TextFormat] = &[
+    text("place_chip", "PC:<column>", r"^PC:[0-9]$"),
+    text("play_again", "PLAY_AGAIN", r"^PLAY_AGAIN$"),
+    text("resign", "RESIGN", r"^RESIGN$"),
+    text("offer_draw", "DRAW_OFFER", r"^DRAW_OFFER$"),
+    text("accept_draw", "DRAW_ACCEPT", r"^DRAW_ACCEPT$"),
+    text("decline_draw", "DRAW_DECLINE", r"^DRAW_DECLINE$"),
+    text(
+        "board_state_request",
+        "BOARD_STATE_REQ",
+        r"^BOARD_STATE_REQ$",
+    ),
+    text("leave", "LEAVE", r"^LEAVE$"),
+    text(
+        "ready_for_game_pong",
+        "READY_FOR_GAME_PONG",
+        r"^READY_FOR_GAME_PONG$",
+    ),
+    text(
+        "lobby_request",
+        "REQ_LOBBY, REQ_WW for the public queue or REQ_BOT:<level>, followed by any of \
+         :BOARD=<board>, :TIME=<initial s>[+<increment s>], :MOVE_TIME=<s>, :BEST_OF=<games>",
+        r"^(REQ_LOBBY|REQ_WW|REQ_BOT:[0-9]+)(:(BOARD=[0-9]+X[0-9]+X[0-9]+|TIME=[0-9]+(\+[0-9]+)?|MOVE_TIME=[0-9]+|BEST_OF=[0-9]+))*$",
+    ),
+    text(
+        "join_lobby",
+        "JOIN_LOBBY:<game id>",
+        r"^JOIN_LOBBY:[A-Z]{4}$",
+    ),
+    text("spectate", "SPECTATE:<game id>", r"^SPECTATE:[A-Z]{4}$"),
+    text("login", "LOGIN:<session token>", r"^LOGIN:[^:]+$"),
+    text("logout", "LOGOUT", r"^LOGOUT$"),
+    text(
+        "battle_req",
+        "BATTLE_REQ:<user id>",
+        r"^BATTLE_REQ:[0-9a-f]+$",
+    ),
+    text(
+        "chat_message",
+        "CHAT_MSG:<base64 message>",
+        r"^CHAT_MSG:[A-Za-z0-9+/=]*$",
+    ),
+    text("chat_read", "CHAT_READ", r"^CHAT_READ$"),
+];
+
 impl Message for PlayerMessage {
     type Result = Result<(), ()>;
 }
@@ -0,0 +1,1350 @@
+//! JSON encoding of the reliable protocol, used by clients announcing protocol version 5 or newer.
+//! Every packet and every message inside it is an object whose "type" field names the variant,
+//! e.g. `{"type":"msg","id":3,"msg":{"type":"place_chip","column":4}}`.
+//! Unknown fields are ignored so that fields can be added without breaking older clients.
+
+use super::lobby_mgr::{LobbyKind, LobbySettings};
+use super::msg::{HelloIn, HelloOut, PlayerMessage, ReliabilityError};
+use super::msg::{ReliablePacketIn, ReliablePacketOut, ServerMessage, SrvMsgError};
+use super::msg::{TextFormat, PLAYER_TEXT_FORMATS, SERVER_TEXT_FORMATS};
+use super::{
+    bot::BotLevel,
+    client_adapter::JSON_VERSION,
+    clock::TimeControl,
+    connection_mgr::WSSessionToken,
+    game_info::{BoardConfig, GameId, Player},
+    series::Series,
+};
+use crate::api::users::{session_token::SessionToken, user::UserId};
+
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Same limit as for the text protocol, chat messages might be long
+const MAX_PACKET_LEN: usize = 2000;
+
+/// First message of a client, answered with a `HelloOut`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(rename = "HelloIn")]
+pub enum JsonHelloIn {
+    Hello {
+        protocol_version: usize,
+        /// The token of the last hello to resume a session, null to start a new one
+        session_token: Option<WSSessionToken>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(rename = "HelloOut")]
+pub enum JsonHelloOut {
+    Hello {
+        session_token: WSSessionToken,
+        /// false if the requested session was resumed
+        is_new: bool,
+    },
+    /// The client's protocol version is no longer supported, the connection is closed
+    Outdated,
+}
+
+/// Sent by the client after the hello. Every message is acked by the server.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(rename = "PacketIn")]
+pub enum JsonPacketIn {
+    /// Acknowledges the server's message with that id
+    Ack { id: usize },
+    /// Acknowledges all of the server's messages up to `up_to` and the ones in `selective`
+    CumulativeAck {
+        up_to: usize,
+        #[serde(default)]
+        selective: Vec<usize>,
+    },
+    /// Ids start at 1 and increase by one for every message
+    Msg { id: usize, msg: JsonPlayerMessage },
+}
+
+/// Sent by the server after the hello. Every message has to be acked by the client.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(rename = "PacketOut")]
+pub enum JsonPacketOut {
+    /// Acknowledges the client's message with that id, only sent with the text protocol
+    Ack { id: usize },
+    /// Acknowledges all of the client's messages up to `up_to` and the ones in `selective`,
+    /// which arrived out of order. Sent after every message of the client.
+    CumulativeAck { up_to: usize, selective: Vec<usize> },
+    /// Ids start at 1 and increase by one for every message.
+    /// At most 32 messages are unacknowledged at a time, unacknowledged ones are resent
+    /// with an increasing delay.
+    Msg { id: usize, msg: JsonServerMessage },
+    /// The client's packet could not be processed
+    Err { error: ReliabilityError },
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(rename = "PlayerMessage")]
+pub enum JsonPlayerMessage {
+    PlaceChip {
+        column: usize,
+    },
+    PlayAgain,
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    /// Answered with a board_state message
+    BoardStateRequest,
+    Leave,
+    ReadyForGamePong,
+    LobbyRequest {
+        kind: JsonLobbyKind,
+        #[serde(default)]
+        settings: JsonLobbySettings,
+    },
+    JoinLobby {
+        game_id: GameId,
+    },
+    /// Only lobbies in which a friend plays can be watched
+    Spectate {
+        game_id: GameId,
+    },
+    Login {
+        session_token: SessionToken,
+    },
+    Logout,
+    BattleReq {
+        user_id: UserId,
+    },
+    ChatMessage {
+        message: String,
+    },
+    ChatRead,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[schemars(rename = "LobbyKind")]
+pub enum JsonLobbyKind {
+    Private,
+    /// Matched with a player requesting the same settings
+    Public,
+    Bot {
+        level: usize,
+    },
+}
+
+/// All fields are optional, missing ones keep their default
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+#[schemars(rename = "LobbySettings")]
+pub struct JsonLobbySettings {
+    pub board: Option<BoardConfig>,
+    /// Initial seconds of a chess clock, optionally with `increment_s`
+    pub initial_s: Option<i64>,
+    pub increment_s: Option<i64>,
+    /// Fixed time per move, can't be combined with `initial_s`
+    pub move_s: Option<i64>,
+    /// 3, 5 or 7 to play a series, private lobbies only
+    pub best_of: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(rename = "ServerMessage")]
+pub enum JsonServerMessage {
+    PlaceChip {
+        column: usize,
+    },
+    OpponentLeaving,
+    OpponentJoining,
+    GameStart {
+        my_turn: bool,
+        opponent: Option<String>,
+        board: BoardConfig,
+    },
+    GameOver {
+        you_won: bool,
+    },
+    GameDraw,
+    /// Followed by game_over
+    OpponentResigned,
+    DrawOffered,
+    DrawDeclined,
+    OpponentReconnecting {
+        /// Seconds until the game is forfeited
+        seconds: u64,
+    },
+    OpponentReconnected,
+    /// Everything a client needs to redraw a running game
+    BoardState {
+        board: BoardConfig,
+        my_turn: bool,
+        move_count: usize,
+        opponent: Option<String>,
+        clock: Option<JsonClock>,
+        /// Columns of all moves so far
+        moves: Vec<usize>,
+    },
+    RatingChange {
+        change: i32,
+        rating: i32,
+    },
+    /// Sent after game_start in series lobbies. Wins count one point, draws half a point.
+    SeriesScore {
+        mine: usize,
+        opponent: usize,
+        draws: usize,
+        best_of: usize,
+    },
+    SeriesOver {
+        you_won: bool,
+    },
+    /// Both players have the same points after all games of the series
+    SeriesDrawn,
+    LobbyClosing,
+    ReadyForGamePing,
+    LoginResponse {
+        success: bool,
+    },
+    Error {
+        error: Option<SrvMsgError>,
+    },
+    BattleReq {
+        user_id: UserId,
+        game_id: GameId,
+    },
+    /// The next tournament game is about to start in this lobby
+    TournamentMatch {
+        tournament_id: String,
+        game_id: GameId,
+    },
+    CurrentServerState {
+        connected_players: usize,
+        /// Someone is waiting in the public queue
+        player_waiting: bool,
+    },
+    ChatMessage {
+        global: bool,
+        message: String,
+        sender: Option<String>,
+    },
+    ChatRead {
+        global: bool,
+    },
+    /// Sent to everyone in the lobby when it changes
+    SpectatorCount {
+        count: usize,
+    },
+    /// Remaining millis
+    Clock {
+        mine: i64,
+        opponent: i64,
+    },
+    /// Sent to spectators instead of board_state
+    SpectateBoard {
+        board: BoardConfig,
+        /// null if the game hasn't started yet
+        first: Option<Player>,
+        moves: Vec<usize>,
+    },
+    SpectateGameStart {
+        board: BoardConfig,
+        first: Player,
+    },
+    SpectateGameOver {
+        /// null if drawn
+        winner: Option<Player>,
+    },
+    /// Remaining millis of player one and two
+    SpectateClock {
+        one: i64,
+        two: i64,
+    },
+    /// Another client logged into this account, this connection is closed
+    CloseOtherClientLogin,
+    /// The session of this connection was revoked, the client is logged out
+    SessionRevoked,
+    /// No new games can be started, running ones may be finished
+    ServerRestarting {
+        /// Seconds until the server goes down
+        seconds: u64,
+    },
+}
+
+/// Remaining millis
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "Clock")]
+pub struct JsonClock {
+    pub mine: i64,
+    pub opponent: i64,
+}
+
+/// JSON Schema of every frame sent over the websocket with protocol version 5,
+/// including the `SrvMsgError` and `ReliabilityError` codes. The messages of the older
+/// text protocol are described in the `TextServerMessage` and `TextPlayerMessage` definitions.
+pub fn protocol_schema() -> Value {
+    let mut gen = SchemaSettings::draft07().into_generator();
+    let frames = vec![
+        gen.subschema_for::<JsonHelloIn>(),
+        gen.subschema_for::<JsonHelloOut>(),
+        gen.subschema_for::<JsonPacketIn>(),
+        gen.subschema_for::<JsonPacketOut>(),
+    ];
+    let mut definitions = json!(gen.take_definitions());
+    definitions["TextServerMessage"] = text_formats_schema(
+        "Server messages of the text protocol, sent as MSG::<id>::<message>",
+        SERVER_TEXT_FORMATS,
+    );
+    definitions["TextPlayerMessage"] = text_formats_schema(
+        "Player messages of the text protocol, sent as MSG::<id>::<message>",
+        PLAYER_TEXT_FORMATS,
+    );
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Four in a row websocket protocol",
+        "description": "Clients send a hello first, followed by packets. Unknown fields have to be ignored.",
+        "protocol_version": JSON_VERSION,
+        "anyOf": frames,
+        "definitions": definitions,
+    })
+}
+
+/// One string schema per message, titled with the message's JSON type
+fn text_formats_schema(description: &str, formats: &[TextFormat]) -> Value {
+    let variants: Vec<_> = formats
+        .iter()
+        .map(|format| {
+            json!({
+                "title": format.tag,
+                "description": format.format,
+                "type": "string",
+                "pattern": format.pattern,
+            })
+        })
+        .collect();
+    json!({
+        "description": description,
+        "oneOf": variants,
+    })
+}
+
+pub fn parse_hello(orig: &str) -> Option<HelloIn> {
+    match serde_json::from_str(orig).ok()? {
+        JsonHelloIn::Hello {
+            protocol_version,
+            session_token,
+        } => Some(HelloIn {
+            protocol_version,
+            maybe_session_token: session_token.filter(|token| token.len() == 32),
+        }),
+    }
+}
+
+pub fn serialize_hello(hello: HelloOut) -> String {
+    let json_hello = match hello {
+        HelloOut::Ok(session_token, is_new) => JsonHelloOut::Hello {
+            session_token,
+            is_new,
+        },
+        HelloOut::OutDated => JsonHelloOut::Outdated,
+    };
+    serde_json::to_string(&json_hello).expect("protocol messages always serialize")
+}
+
+pub fn parse_packet(orig: &str) -> Result<ReliablePacketIn, ReliabilityError> {
+    if orig.len() > MAX_PACKET_LEN {
+        return Err(ReliabilityError::InvalidContent);
+    }
+    let value: Value = serde_json::from_str(orig).map_err(|_| ReliabilityError::InvalidFormat)?;
+    let is_msg = match value.get("type").and_then(Value::as_str) {
+        Some("ack") | Some("cumulative_ack") => false,
+        Some("msg") => true,
+        Some(_) => return Err(ReliabilityError::UnknownMessage),
+        None => return Err(ReliabilityError::InvalidFormat),
+    };
+    let has_id = value.get("id").and_then(Value::as_u64).is_some();
+    match serde_json::from_value(value) {
+        Ok(JsonPacketIn::Ack { id }) => Ok(ReliablePacketIn::Ack(id)),
+        Ok(JsonPacketIn::CumulativeAck { up_to, selective }) => {
+            Ok(ReliablePacketIn::CumulativeAck(up_to, selective))
+        }
+        Ok(JsonPacketIn::Msg { id, msg }) => msg
+            .into_player_message()
+            .map(|player_msg| ReliablePacketIn::Msg(id, player_msg))
+            .ok_or(ReliabilityError::InvalidContent),
+        // Distinguish a broken envelope from a message we don't understand
+        Err(_) if is_msg && has_id => Err(ReliabilityError::InvalidContent),
+        Err(_) => Err(ReliabilityError::InvalidFormat),
+    }
+}
+
+pub fn serialize_packet(packet: ReliablePacketOut) -> String {
+    let json_packet = match packet {
+        ReliablePacketOut::Ack(id) => JsonPacketOut::Ack { id },
+        ReliablePacketOut::CumulativeAck(up_to, selective) => {
+            JsonPacketOut::CumulativeAck { up_to, selective }
+        }
+        ReliablePacketOut::Msg { id, msg } => JsonPacketOut::Msg {
+            id,
+            msg: msg.into(),
+        },
+        ReliablePacketOut::Err(error) => JsonPacketOut::Err { error },
+    };
+    serde_json::to_string(&json_packet).expect("protocol messages always serialize")
+}
+
+impl JsonPlayerMessage {
+    /// None if a value is out of range, e.g. an unknown bot level
+    pub fn into_player_message(self) -> Option<PlayerMessage> {
+        use JsonPlayerMessage::*;
+        Some(match self {
+            PlaceChip { column } => PlayerMessage::PlaceChip(column),
+            PlayAgain => PlayerMessage::PlayAgainRequest,
+            Resign => PlayerMessage::Resign,
+            OfferDraw => PlayerMessage::OfferDraw,
+            AcceptDraw => PlayerMessage::AcceptDraw,
+            DeclineDraw => PlayerMessage::DeclineDraw,
+            BoardStateRequest => PlayerMessage::BoardStateRequest,
+            Leave => PlayerMessage::Leaving,
+            ReadyForGamePong => PlayerMessage::ReadyForGamePong,
+            LobbyRequest { kind, settings } => {
+                let kind = match kind {
+                    JsonLobbyKind::Private => LobbyKind::Private,
+                    JsonLobbyKind::Public => LobbyKind::Public,
+                    JsonLobbyKind::Bot { level } => LobbyKind::Bot(BotLevel::new(level)?),
+                };
+                PlayerMessage::LobbyRequest(kind, settings.into_settings()?)
+            }
+            JoinLobby { game_id } => PlayerMessage::LobbyJoin(game_id),
+            Spectate { game_id } => PlayerMessage::Spectate(game_id),
+            Login { session_token } => PlayerMessage::Login(session_token),
+            Logout => PlayerMessage::Logout,
+            BattleReq { user_id } => PlayerMessage::BattleReq(user_id),
+            ChatMessage { message } => PlayerMessage::ChatMessage(message),
+            ChatRead => PlayerMessage::ChatRead,
+        })
+    }
+}
+
+impl JsonLobbySettings {
+    fn into_settings(self) -> Option<LobbySettings> {
+        let time_control = match (self.initial_s, self.increment_s, self.move_s) {
+            (None, None, None) => TimeControl::Unlimited,
+            (Some(initial_s), increment_s, None) => {
+                TimeControl::fischer(initial_s, increment_s.unwrap_or(0))?
+            }
+            (None, None, Some(move_s)) => TimeControl::per_move(move_s)?,
+            _ => return None,
+        };
+        let best_of = match self.best_of {
+            Some(best_of) => Some(Series::check_best_of(best_of)?),
+            None => None,
+        };
+        Some(LobbySettings {
+            board: self.board.unwrap_or_default(),
+            time_control,
+            best_of,
+        })
+    }
+}
+
+impl From<ServerMessage> for JsonServerMessage {
+    fn from(msg: ServerMessage) -> Self {
+        use ServerMessage::*;
+        match msg {
+            PlaceChip(column) => JsonServerMessage::PlaceChip { column },
+            OpponentLeaving => JsonServerMessage::OpponentLeaving,
+            OpponentJoining => JsonServerMessage::OpponentJoining,
+            GameStart(my_turn, opponent, board) => JsonServerMessage::GameStart {
+                my_turn,
+                opponent,
+                board,
+            },
+            GameOver(you_won) => JsonServerMessage::GameOver { you_won },
+            GameDraw => JsonServerMessage::GameDraw,
+            OpponentResigned => JsonServerMessage::OpponentResigned,
+            DrawOffered => JsonServerMessage::DrawOffered,
+            DrawDeclined => JsonServerMessage::DrawDeclined,
+            OpponentReconnecting(seconds) => JsonServerMessage::OpponentReconnecting { seconds },
+            OpponentReconnected => JsonServerMessage::OpponentReconnected,
+            BoardState {
+                board,
+                my_turn,
+                move_count,
+                opponent,
+                clock,
+                moves,
+            } => JsonServerMessage::BoardState {
+                board,
+                my_turn,
+                move_count,
+                opponent,
+                clock: clock.map(|(mine, opponent)| JsonClock { mine, opponent }),
+                moves,
+            },
+            RatingChange(change, rating) => JsonServerMessage::RatingChange { change, rating },
+            SeriesScore(mine, opponent, draws, best_of) => JsonServerMessage::SeriesScore {
+                mine,
+                opponent,
+                draws,
+                best_of,
+            },
+            SeriesOver(you_won) => JsonServerMessage::SeriesOver { you_won },
+            SeriesDrawn => JsonServerMessage::SeriesDrawn,
+            LobbyClosing => JsonServerMessage::LobbyClosing,
+            ReadyForGamePing => JsonServerMessage::ReadyForGamePing,
+            LoginResponse { success } => JsonServerMessage::LoginResponse { success },
+            Error(error) => JsonServerMessage::Error { error },
+            BattleReq(user_id, game_id) => JsonServerMessage::BattleReq { user_id, game_id },
+            TournamentMatch(tournament_id, game_id) => JsonServerMessage::TournamentMatch {
+                tournament_id,
+                game_id,
+            },
+            CurrentServerState(connected_players, player_waiting) => {
+                JsonServerMessage::CurrentServerState {
+                    connected_players,
+                    player_waiting,
+                }
+            }
+            ChatMessage(global, message, sender) => JsonServerMessage::ChatMessage {
+                global,
+                message,
+                sender,
+            },
+            ChatRead(global) => JsonServerMessage::ChatRead { global },
+            SpectatorCount(count) => JsonServerMessage::SpectatorCount { count },
+            Clock(mine, opponent) => JsonServerMessage::Clock { mine, opponent },
+            SpectateBoard(board, first, moves) => JsonServerMessage::SpectateBoard {
+                board,
+                first,
+                moves,
+            },
+            SpectateGameStart(board, first) => {
+                JsonServerMessage::SpectateGameStart { board, first }
+            }
+            SpectateGameOver(winner) => JsonServerMessage::SpectateGameOver { winner },
+            SpectateClock(one, two) => JsonServerMessage::SpectateClock { one, two },
+            CloseOtherClientLogin => JsonServerMessage::CloseOtherClientLogin,
+            SessionRevoked => JsonServerMessage::SessionRevoked,
+            ServerRestarting(seconds) => JsonServerMessage::ServerRestarting { seconds },
+        }
+    }
+}
+
+/// Used to restore messages that were still waiting for an ack, see `snapshot`
+impl From<JsonServerMessage> for ServerMessage {
+    fn from(msg: JsonServerMessage) -> Self {
+        use JsonServerMessage::*;
+        match msg {
+            PlaceChip { column } => ServerMessage::PlaceChip(column),
+            OpponentLeaving => ServerMessage::OpponentLeaving,
+            OpponentJoining => ServerMessage::OpponentJoining,
+            GameStart {
+                my_turn,
+                opponent,
+                board,
+            } => ServerMessage::GameStart(my_turn, opponent, board),
+            GameOver { you_won } => ServerMessage::GameOver(you_won),
+            GameDraw => ServerMessage::GameDraw,
+            OpponentResigned => ServerMessage::OpponentResigned,
+            DrawOffered => ServerMessage::DrawOffered,
+            DrawDeclined => ServerMessage::DrawDeclined,
+            OpponentReconnecting { seconds } => ServerMessage::OpponentReconnecting(seconds),
+            OpponentReconnected => ServerMessage::OpponentReconnected,
+            BoardState {
+                board,
+                my_turn,
+                move_count,
+                opponent,
+                clock,
+                moves,
+            } => ServerMessage::BoardState {
+                board,
+                my_turn,
+                move_count,
+                opponent,
+                clock: clock.map(|clock| (clock.mine, clock.opponent)),
+                moves,
+            },
+            RatingChange { change, rating } => ServerMessage::RatingChange(change, rating),
+            SeriesScore {
+                mine,
+                opponent,
+                draws,
+                best_of,
+            } => ServerMessage::SeriesScore(mine, opponent, draws, best_of),
+            SeriesOver { you_won } => ServerMessage::SeriesOver(you_won),
+            SeriesDrawn => ServerMessage::SeriesDrawn,
+            LobbyClosing => ServerMessage::LobbyClosing,
+            ReadyForGamePing => ServerMessage::ReadyForGamePing,
+            LoginResponse { success } => ServerMessage::LoginResponse { success },
+            Error { error } => ServerMessage::Error(error),
+            BattleReq { user_id, game_id } => ServerMessage::BattleReq(user_id, game_id),
+            TournamentMatch {
+                tournament_id,
+                game_id,
+            } => ServerMessage::TournamentMatch(tournament_id, game_id),
+            CurrentServerState {
+                connected_players,
+                player_waiting,
+            } => ServerMessage::CurrentServerState(connected_players, player_waiting),
+            ChatMessage {
+                global,
+                message,
+                sender,
+            } => ServerMessage::ChatMessage(global, message, sender),
+            ChatRead { global } => ServerMessage::ChatRead(global),
+            SpectatorCount { count } => ServerMessage::SpectatorCount(count),
+            Clock { mine, opponent } => ServerMessage::Clock(mine, opponent),
+            SpectateBoard {
+                board,
+                first,
+                moves,
+            } => ServerMessage::SpectateBoard(board, first, moves),
+            SpectateGameStart { board, first } => ServerMessage::SpectateGameStart(board, first),
+            SpectateGameOver { winner } => ServerMessage::SpectateGameOver(winner),
+            SpectateClock { one, two } => ServerMessage::SpectateClock(one, two),
+            CloseOtherClientLogin => ServerMessage::CloseOtherClientLogin,
+            SessionRevoked => ServerMessage::SessionRevoked,
+            ServerRestarting { seconds } => ServerMessage::ServerRestarting(seconds),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonschema::JSONSchema;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn hello() {
+        let token = "a".repeat(32);
+        let hello = parse_hello(&format!(
+            r#"{{"type":"hello","protocol_version":5,"session_token":"{}"}}"#,
+            token
+        ))
+        .unwrap();
+        assert_eq!(hello.protocol_version, 5);
+        assert_eq!(hello.maybe_session_token, Some(token.clone()));
+        let hello = parse_hello(r#"{"type":"hello","protocol_version":5,"session_token":null}"#);
+        assert_eq!(hello.unwrap().maybe_session_token, None);
+        assert_eq!(
+            serialize_hello(HelloOut::Ok(token.clone(), false)),
+            format!(
+                r#"{{"type":"hello","session_token":"{}","is_new":false}}"#,
+                token
+            )
+        );
+    }
+
+    #[test]
+    fn parses_packets() {
+        assert!(matches!(
+            parse_packet(r#"{"type":"ack","id":7}"#),
+            Ok(ReliablePacketIn::Ack(7))
+        ));
+        assert!(matches!(
+            parse_packet(r#"{"type":"msg","id":2,"msg":{"type":"place_chip","column":3}}"#),
+            Ok(ReliablePacketIn::Msg(2, PlayerMessage::PlaceChip(3)))
+        ));
+        // Unknown fields are ignored
+        assert!(matches!(
+            parse_packet(r#"{"type":"msg","id":2,"msg":{"type":"resign","reason":"tired"}}"#),
+            Ok(ReliablePacketIn::Msg(2, PlayerMessage::Resign))
+        ));
+    }
+
+    #[test]
+    fn parses_lobby_requests() {
+        let packet = r#"{"type":"msg","id":1,"msg":{"type":"lobby_request",
+            "kind":{"kind":"bot","level":2},"settings":{"board":"7X6X4","initial_s":300,"increment_s":5}}}"#;
+        match parse_packet(packet) {
+            Ok(ReliablePacketIn::Msg(1, PlayerMessage::LobbyRequest(kind, settings))) => {
+                assert_eq!(kind, LobbyKind::Bot(BotLevel::new(2).unwrap()));
+                assert_eq!(settings.board, BoardConfig::new(7, 6, 4).unwrap());
+                assert_eq!(settings.time_control, TimeControl::fischer(300, 5).unwrap());
+                assert_eq!(settings.best_of, None);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let packet =
+            r#"{"type":"msg","id":1,"msg":{"type":"lobby_request","kind":{"kind":"private"}}}"#;
+        assert!(matches!(
+            parse_packet(packet),
+            Ok(ReliablePacketIn::Msg(
+                _,
+                PlayerMessage::LobbyRequest(LobbyKind::Private, _)
+            ))
+        ));
+        // Fischer and per move time controls exclude each other
+        let packet = r#"{"type":"msg","id":1,"msg":{"type":"lobby_request",
+            "kind":{"kind":"public"},"settings":{"initial_s":300,"move_s":10}}}"#;
+        assert!(matches!(
+            parse_packet(packet),
+            Err(ReliabilityError::InvalidContent)
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_packets() {
+        assert!(matches!(
+            parse_packet("ACK::1"),
+            Err(ReliabilityError::InvalidFormat)
+        ));
+        assert!(matches!(
+            parse_packet(r#"{"type":"syn","id":1}"#),
+            Err(ReliabilityError::UnknownMessage)
+        ));
+        assert!(matches!(
+            parse_packet(r#"{"type":"msg","id":"one","msg":{"type":"resign"}}"#),
+            Err(ReliabilityError::InvalidFormat)
+        ));
+        assert!(matches!(
+            parse_packet(r#"{"type":"msg","id":1,"msg":{"type":"place_chip"}}"#),
+            Err(ReliabilityError::InvalidContent)
+        ));
+        assert!(matches!(
+            parse_packet(r#"{"type":"msg","id":1,"msg":{"type":"dance"}}"#),
+            Err(ReliabilityError::InvalidContent)
+        ));
+    }
+
+    #[test]
+    fn serializes_packets() {
+        let packet = ReliablePacketOut::Msg {
+            id: 4,
+            msg: ServerMessage::BoardState {
+                board: BoardConfig::default(),
+                my_turn: true,
+                move_count: 2,
+                opponent: None,
+                clock: Some((1000, 2000)),
+                moves: vec![3, 4],
+            },
+        };
+        let value: Value = serde_json::from_str(&serialize_packet(packet)).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "type": "msg",
+                "id": 4,
+                "msg": {
+                    "type": "board_state",
+                    "board": "7X7X4",
+                    "my_turn": true,
+                    "move_count": 2,
+                    "opponent": null,
+                    "clock": {"mine": 1000, "opponent": 2000},
+                    "moves": [3, 4]
+                }
+            })
+        );
+        let packet = ReliablePacketOut::Msg {
+            id: 5,
+            msg: ServerMessage::Error(Some(SrvMsgError::NotYourTurn)),
+        };
+        assert_eq!(
+            serialize_packet(packet),
+            r#"{"type":"msg","id":5,"msg":{"type":"error","error":"NotYourTurn"}}"#
+        );
+        assert_eq!(
+            serialize_packet(ReliabilityError::InvalidContent.into()),
+            r#"{"type":"err","error":"INVALID_CONTENT"}"#
+        );
+    }
+
+    /// Validates `value` against one of the definitions of the served schema
+    fn validate(definition: &str, value: &Value) {
+        let mut schema = protocol_schema();
+        schema.as_object_mut().unwrap().remove("anyOf");
+        schema["$ref"] = json!(format!("#/definitions/{}", definition));
+        let compiled = JSONSchema::compile(&schema).expect("invalid schema");
+        if let Err(errors) = compiled.validate(value) {
+            let errors: Vec<_> = errors.map(|err| err.to_string()).collect();
+            panic!("{} doesn't match {}: {:?}", value, definition, errors);
+        };
+    }
+
+    /// Values of the "type" field of all variants of a message definition
+    fn schema_tags(definition: &str) -> BTreeSet<String> {
+        protocol_schema()["definitions"][definition]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variant| {
+                variant["properties"]["type"]["enum"][0]
+                    .as_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect()
+    }
+
+    fn tag(value: &Value) -> String {
+        value["type"].as_str().unwrap().to_owned()
+    }
+
+    /// JSON tag of every variant. There is no wildcard, so a new variant doesn't compile
+    /// until it's listed here, and the round trip tests fail until it has an example.
+    fn server_message_tag(msg: &ServerMessage) -> &'static str {
+        use ServerMessage::*;
+        match msg {
+            PlaceChip(_) => "place_chip",
+            OpponentLeaving => "opponent_leaving",
+            OpponentJoining => "opponent_joining",
+            GameStart(..) => "game_start",
+            GameOver(_) => "game_over",
+            GameDraw => "game_draw",
+            OpponentResigned => "opponent_resigned",
+            DrawOffered => "draw_offered",
+            DrawDeclined => "draw_declined",
+            OpponentReconnecting(_) => "opponent_reconnecting",
+            OpponentReconnected => "opponent_reconnected",
+            BoardState { .. } => "board_state",
+            RatingChange(..) => "rating_change",
+            SeriesScore(..) => "series_score",
+            SeriesOver(_) => "series_over",
+            SeriesDrawn => "series_drawn",
+            LobbyClosing => "lobby_closing",
+            ReadyForGamePing => "ready_for_game_ping",
+            LoginResponse { .. } => "login_response",
+            Error(_) => "error",
+            BattleReq(..) => "battle_req",
+            TournamentMatch(..) => "tournament_match",
+            CurrentServerState(..) => "current_server_state",
+            ChatMessage(..) => "chat_message",
+            ChatRead(_) => "chat_read",
+            SpectatorCount(_) => "spectator_count",
+            Clock(..) => "clock",
+            SpectateBoard(..) => "spectate_board",
+            SpectateGameStart(..) => "spectate_game_start",
+            SpectateGameOver(_) => "spectate_game_over",
+            SpectateClock(..) => "spectate_clock",
+            CloseOtherClientLogin => "close_other_client_login",
+            SessionRevoked => "session_revoked",
+            ServerRestarting(_) => "server_restarting",
+        }
+    }
+
+    /// As `server_message_tag`
+    fn player_message_tag(msg: &PlayerMessage) -> &'static str {
+        use PlayerMessage::*;
+        match msg {
+            PlaceChip(_) => "place_chip",
+            PlayAgainRequest => "play_again",
+            Resign => "resign",
+            OfferDraw => "offer_draw",
+            AcceptDraw => "accept_draw",
+            DeclineDraw => "decline_draw",
+            BoardStateRequest => "board_state_request",
+            Leaving => "leave",
+            ReadyForGamePong => "ready_for_game_pong",
+            LobbyRequest(..) => "lobby_request",
+            LobbyJoin(_) => "join_lobby",
+            Spectate(_) => "spectate",
+            Login(_) => "login",
+            Logout => "logout",
+            BattleReq(_) => "battle_req",
+            ChatMessage(_) => "chat_message",
+            ChatRead => "chat_read",
+        }
+    }
+
+    fn server_message_examples() -> Vec<ServerMessage> {
+        use ServerMessage::*;
+        let board = BoardConfig::new(9, 7, 5).unwrap();
+        let game_id = GameId::generate(&[]);
+        vec![
+            PlaceChip(3),
+            OpponentLeaving,
+            OpponentJoining,
+            GameStart(true, Some("anna".to_owned()), BoardConfig::default()),
+            GameStart(false, None, board),
+            GameOver(false),
+            GameDraw,
+            OpponentResigned,
+            DrawOffered,
+            DrawDeclined,
+            OpponentReconnecting(30),
+            OpponentReconnected,
+            BoardState {
+                board,
+                my_turn: false,
+                move_count: 3,
+                opponent: Some("anna".to_owned()),
+                clock: Some((1000, 2000)),
+                moves: vec![0, 8, 4],
+            },
+            RatingChange(-12, 1488),
+            SeriesScore(1, 0, 1, 3),
+            SeriesOver(true),
+            SeriesDrawn,
+            LobbyClosing,
+            ReadyForGamePing,
+            LoginResponse { success: true },
+            Error(Some(SrvMsgError::GamePaused)),
+            Error(None),
+            BattleReq(UserId::new(), game_id),
+            TournamentMatch("5f8f1c2e9d3b4a0012345678".to_owned(), game_id),
+            CurrentServerState(12, true),
+            ChatMessage(true, "gg: well played".to_owned(), Some("abcde".to_owned())),
+            ChatRead(false),
+            SpectatorCount(2),
+            Clock(1000, 2000),
+            SpectateBoard(board, Some(Player::One), vec![3]),
+            SpectateBoard(board, None, vec![]),
+            SpectateGameStart(board, Player::Two),
+            SpectateGameOver(None),
+            SpectateClock(5000, 6000),
+            CloseOtherClientLogin,
+            SessionRevoked,
+            ServerRestarting(240),
+        ]
+    }
+
+    fn player_message_examples() -> Vec<PlayerMessage> {
+        use PlayerMessage::*;
+        let settings = |board, time_control, best_of| LobbySettings {
+            board,
+            time_control,
+            best_of,
+        };
+        vec![
+            PlaceChip(6),
+            PlayAgainRequest,
+            Resign,
+            OfferDraw,
+            AcceptDraw,
+            DeclineDraw,
+            BoardStateRequest,
+            Leaving,
+            ReadyForGamePong,
+            LobbyRequest(LobbyKind::Public, LobbySettings::default()),
+            LobbyRequest(
+                LobbyKind::Private,
+                settings(
+                    BoardConfig::new(6, 5, 4).unwrap(),
+                    TimeControl::per_move(20).unwrap(),
+                    Some(5),
+                ),
+            ),
+            LobbyRequest(
+                LobbyKind::Bot(BotLevel::new(4).unwrap()),
+                settings(
+                    BoardConfig::default(),
+                    TimeControl::fischer(180, 2).unwrap(),
+                    None,
+                ),
+            ),
+            LobbyJoin(GameId::generate(&[])),
+            Spectate(GameId::generate(&[])),
+            Login(SessionToken::new()),
+            Logout,
+            BattleReq(UserId::new()),
+            ChatMessage("hi: {\"type\":\"resign\"}".to_owned()),
+            ChatRead,
+        ]
+    }
+
+    /// Inverse of `into_player_message`, what a client sends
+    fn json_player_message(msg: PlayerMessage) -> JsonPlayerMessage {
+        use PlayerMessage::*;
+        match msg {
+            PlaceChip(column) => JsonPlayerMessage::PlaceChip { column },
+            PlayAgainRequest => JsonPlayerMessage::PlayAgain,
+            Resign => JsonPlayerMessage::Resign,
+            OfferDraw => JsonPlayerMessage::OfferDraw,
+            AcceptDraw => JsonPlayerMessage::AcceptDraw,
+            DeclineDraw => JsonPlayerMessage::DeclineDraw,
+            BoardStateRequest => JsonPlayerMessage::BoardStateRequest,
+            Leaving => JsonPlayerMessage::Leave,
+            ReadyForGamePong => JsonPlayerMessage::ReadyForGamePong,
+            LobbyRequest(kind, settings) => {
+                let kind = match kind {
+                    LobbyKind::Private => JsonLobbyKind::Private,
+                    LobbyKind::Public => JsonLobbyKind::Public,
+                    LobbyKind::Bot(level) => JsonLobbyKind::Bot {
+                        level: level.number() as usize,
+                    },
+                    LobbyKind::Tournament => panic!("tournament lobbies can't be requested"),
+                };
+                let (initial_s, increment_s, move_s) = match settings.time_control {
+                    TimeControl::Unlimited => (None, None, None),
+                    TimeControl::Fischer {
+                        initial_s,
+                        increment_s,
+                    } => (Some(initial_s), Some(increment_s), None),
+                    TimeControl::PerMove { move_s } => (None, None, Some(move_s)),
+                };
+                JsonPlayerMessage::LobbyRequest {
+                    kind,
+                    settings: JsonLobbySettings {
+                        board: Some(settings.board),
+                        initial_s,
+                        increment_s,
+                        move_s,
+                        best_of: settings.best_of,
+                    },
+                }
+            }
+            LobbyJoin(game_id) => JsonPlayerMessage::JoinLobby { game_id },
+            Spectate(game_id) => JsonPlayerMessage::Spectate { game_id },
+            Login(session_token) => JsonPlayerMessage::Login { session_token },
+            Logout => JsonPlayerMessage::Logout,
+            BattleReq(user_id) => JsonPlayerMessage::BattleReq { user_id },
+            ChatMessage(message) => JsonPlayerMessage::ChatMessage { message },
+            ChatRead => JsonPlayerMessage::ChatRead,
+        }
+    }
+
+    #[test]
+    fn every_server_message_round_trips() {
+        let mut tags = BTreeSet::new();
+        for (id, msg) in server_message_examples().into_iter().enumerate() {
+            let text = serialize_packet(ReliablePacketOut::Msg {
+                id,
+                msg: msg.clone(),
+            });
+            let value: Value = serde_json::from_str(&text).unwrap();
+            validate("PacketOut", &value);
+            assert_eq!(tag(&value["msg"]), server_message_tag(&msg));
+            tags.insert(tag(&value["msg"]));
+            match serde_json::from_str(&text).unwrap() {
+                JsonPacketOut::Msg {
+                    id: parsed_id,
+                    msg: parsed,
+                } => {
+                    assert_eq!(parsed_id, id);
+                    assert_eq!(ServerMessage::from(parsed), msg);
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(tags, schema_tags("ServerMessage"));
+    }
+
+    #[test]
+    fn every_player_message_round_trips() {
+        let mut tags = BTreeSet::new();
+        for (id, msg) in player_message_examples().into_iter().enumerate() {
+            let packet = JsonPacketIn::Msg {
+                id,
+                msg: json_player_message(msg.clone()),
+            };
+            let text = serde_json::to_string(&packet).unwrap();
+            let value: Value = serde_json::from_str(&text).unwrap();
+            validate("PacketIn", &value);
+            assert_eq!(tag(&value["msg"]), player_message_tag(&msg));
+            tags.insert(tag(&value["msg"]));
+            match parse_packet(&text) {
+                Ok(ReliablePacketIn::Msg(parsed_id, parsed)) => {
+                    assert_eq!(parsed_id, id);
+                    assert_eq!(parsed, msg);
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(tags, schema_tags("PlayerMessage"));
+    }
+
+    /// Titles of the variants of a text message definition whose pattern matches `text`
+    fn matching_text_tags(definition: &str, text: &str) -> Vec<String> {
+        protocol_schema()["definitions"][definition]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|variant| {
+                let compiled = JSONSchema::compile(variant).expect("invalid pattern");
+                compiled.is_valid(&json!(text))
+            })
+            .map(|variant| variant["title"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    fn text_tags(definition: &str) -> BTreeSet<String> {
+        protocol_schema()["definitions"][definition]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variant| variant["title"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    fn parse_turn(text: &str) -> Option<bool> {
+        match text {
+            "YOU" => Some(true),
+            "OPP" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn parse_player(text: &str) -> Option<Player> {
+        match text {
+            "ONE" => Some(Player::One),
+            "TWO" => Some(Player::Two),
+            _ => None,
+        }
+    }
+
+    fn parse_name(text: &str) -> Option<String> {
+        Some(text.to_owned()).filter(|name| !name.is_empty())
+    }
+
+    fn parse_columns(text: &str) -> Option<Vec<usize>> {
+        if text.is_empty() {
+            return Some(Vec::new());
+        }
+        text.split(',').map(|column| column.parse().ok()).collect()
+    }
+
+    /// Inverse of `ServerMessage::serialize`, what a client of the text protocol reads
+    fn parse_server_text(text: &str) -> Option<ServerMessage> {
+        use ServerMessage::*;
+        let parts: Vec<_> = text.split(':').collect();
+        Some(match parts.as_slice() {
+            ["PC", column] => PlaceChip(column.parse().ok()?),
+            ["OPP_LEAVING"] => OpponentLeaving,
+            ["OPP_JOINED"] => OpponentJoining,
+            ["GAME_START", my_turn] => {
+                GameStart(parse_turn(my_turn)?, None, BoardConfig::default())
+            }
+            ["GAME_START", my_turn, opponent] => GameStart(
+                parse_turn(my_turn)?,
+                parse_name(opponent),
+                BoardConfig::default(),
+            ),
+            ["GAME_START", my_turn, opponent, board] => GameStart(
+                parse_turn(my_turn)?,
+                parse_name(opponent),
+                BoardConfig::parse(board)?,
+            ),
+            ["GAME_OVER", "DRAW"] => GameDraw,
+            ["GAME_OVER", winner] => GameOver(parse_turn(winner)?),
+            ["OPP_RESIGNED"] => OpponentResigned,
+            ["DRAW_OFFERED"] => DrawOffered,
+            ["DRAW_DECLINED"] => DrawDeclined,
+            ["OPP_RECONNECTING", seconds] => OpponentReconnecting(seconds.parse().ok()?),
+            ["OPP_RECONNECTED"] => OpponentReconnected,
+            ["BOARD_STATE", board, my_turn, move_count, opponent, columns, clock @ ..] => {
+                BoardState {
+                    board: BoardConfig::parse(board)?,
+                    my_turn: parse_turn(my_turn)?,
+                    move_count: move_count.parse().ok()?,
+                    opponent: parse_name(opponent),
+                    clock: match clock {
+                        [] => None,
+                        [mine, opponent] => Some((mine.parse().ok()?, opponent.parse().ok()?)),
+                        _ => return None,
+                    },
+                    moves: parse_columns(columns)?,
+                }
+            }
+            ["RATING_CHANGE", change, rating] => {
+                RatingChange(change.parse().ok()?, rating.parse().ok()?)
+            }
+            ["SERIES", mine, opponent, draws, best_of] => SeriesScore(
+                mine.parse().ok()?,
+                opponent.parse().ok()?,
+                draws.parse().ok()?,
+                best_of.parse().ok()?,
+            ),
+            ["SERIES_OVER", "DRAW"] => SeriesDrawn,
+            ["SERIES_OVER", winner] => SeriesOver(parse_turn(winner)?),
+            ["LOBBY_CLOSING"] => LobbyClosing,
+            ["READY_FOR_GAME_PING"] => ReadyForGamePing,
+            ["LOGIN_RESPONSE", success] => LoginResponse {
+                success: success.parse().ok()?,
+            },
+            ["ERROR"] => Error(None),
+            ["ERROR", code] => Error(Some(serde_json::from_value(json!(code)).ok()?)),
+            ["BATTLE_REQ", user_id, game_id] => {
+                BattleReq(UserId::from_str(user_id).ok()?, GameId::parse(game_id)?)
+            }
+            ["TOURNAMENT_MATCH", tournament_id, game_id] => {
+                TournamentMatch(tournament_id.to_string(), GameId::parse(game_id)?)
+            }
+            ["CURRENT_SERVER_STATE", players, waiting] => {
+                CurrentServerState(players.parse().ok()?, waiting.parse().ok()?)
+            }
+            ["CHAT_MSG", global, message, sender] => ChatMessage(
+                global.parse().ok()?,
+                String::from_utf8(base64::decode(message).ok()?).ok()?,
+                parse_name(sender),
+            ),
+            ["CHAT_READ", global] => ChatRead(global.parse().ok()?),
+            ["SPECTATORS", count] => SpectatorCount(count.parse().ok()?),
+            ["CLOCK", mine, opponent] => Clock(mine.parse().ok()?, opponent.parse().ok()?),
+            ["SPECTATE_BOARD", board, "NONE", columns] => {
+                SpectateBoard(BoardConfig::parse(board)?, None, parse_columns(columns)?)
+            }
+            ["SPECTATE_BOARD", board, first, columns] => SpectateBoard(
+                BoardConfig::parse(board)?,
+                Some(parse_player(first)?),
+                parse_columns(columns)?,
+            ),
+            ["SPECTATE_GAME_START", board, first] => {
+                SpectateGameStart(BoardConfig::parse(board)?, parse_player(first)?)
+            }
+            ["SPECTATE_GAME_OVER", "DRAW"] => SpectateGameOver(None),
+            ["SPECTATE_GAME_OVER", winner] => SpectateGameOver(Some(parse_player(winner)?)),
+            ["SPECTATE_CLOCK", one, two] => SpectateClock(one.parse().ok()?, two.parse().ok()?),
+            ["CLOSE_OTHER_CLIENT_LOGIN"] => CloseOtherClientLogin,
+            ["SESSION_REVOKED"] => SessionRevoked,
+            ["SERVER_RESTARTING", seconds] => ServerRestarting(seconds.parse().ok()?),
+            _ => return None,
+        })
+    }
+
+    /// Inverse of `PlayerMessage::parse`, what a client of the text protocol sends
+    fn player_text(msg: PlayerMessage) -> String {
+        use PlayerMessage::*;
+        match msg {
+            PlaceChip(column) => format!("PC:{}", column),
+            PlayAgainRequest => "PLAY_AGAIN".to_owned(),
+            Resign => "RESIGN".to_owned(),
+            OfferDraw => "DRAW_OFFER".to_owned(),
+            AcceptDraw => "DRAW_ACCEPT".to_owned(),
+            DeclineDraw => "DRAW_DECLINE".to_owned(),
+            BoardStateRequest => "BOARD_STATE_REQ".to_owned(),
+            Leaving => "LEAVE".to_owned(),
+            ReadyForGamePong => "READY_FOR_GAME_PONG".to_owned(),
+            LobbyRequest(kind, settings) => {
+                let mut text = match kind {
+                    LobbyKind::Private => "REQ_LOBBY".to_owned(),
+                    LobbyKind::Public => "REQ_WW".to_owned(),
+                    LobbyKind::Bot(level) => format!("REQ_BOT:{}", level.number()),
+                    LobbyKind::Tournament => panic!("tournament lobbies can't be requested"),
+                };
+                text += &format!(":BOARD={}", settings.board);
+                match settings.time_control {
+                    TimeControl::Unlimited => {}
+                    TimeControl::Fischer {
+                        initial_s,
+                        increment_s,
+                    } => text += &format!(":TIME={}+{}", initial_s, increment_s),
+                    TimeControl::PerMove { move_s } => text += &format!(":MOVE_TIME={}", move_s),
+                }
+                if let Some(best_of) = settings.best_of {
+                    text += &format!(":BEST_OF={}", best_of);
+                }
+                text
+            }
+            LobbyJoin(game_id) => format!("JOIN_LOBBY:{}", game_id),
+            Spectate(game_id) => format!("SPECTATE:{}", game_id),
+            Login(session_token) => format!("LOGIN:{}", session_token),
+            Logout => "LOGOUT".to_owned(),
+            BattleReq(user_id) => format!("BATTLE_REQ:{}", user_id),
+            ChatMessage(message) => format!("CHAT_MSG:{}", base64::encode(message)),
+            ChatRead => "CHAT_READ".to_owned(),
+        }
+    }
+
+    #[test]
+    fn every_server_message_round_trips_as_text() {
+        let mut tags = BTreeSet::new();
+        for msg in server_message_examples() {
+            let text = msg.serialize();
+            let tag = server_message_tag(&msg);
+            assert_eq!(
+                matching_text_tags("TextServerMessage", &text),
+                vec![tag],
+                "{}",
+                text
+            );
+            tags.insert(tag.to_owned());
+            assert_eq!(parse_server_text(&text), Some(msg), "{}", text);
+        }
+        assert_eq!(tags, text_tags("TextServerMessage"));
+        assert_eq!(tags, schema_tags("ServerMessage"));
+    }
+
+    #[test]
+    fn every_player_message_round_trips_as_text() {
+        let mut tags = BTreeSet::new();
+        for msg in player_message_examples() {
+            let text = player_text(msg.clone());
+            let tag = player_message_tag(&msg);
+            assert_eq!(
+                matching_text_tags("TextPlayerMessage", &text),
+                vec![tag],
+                "{}",
+                text
+            );
+            tags.insert(tag.to_owned());
+            assert_eq!(PlayerMessage::parse(&text), Some(msg.clone()), "{}", text);
+            // The same packet as sent by a text client
+            match ReliablePacketIn::parse(&format!("MSG::7::{}", text)) {
+                Ok(ReliablePacketIn::Msg(7, parsed)) => assert_eq!(parsed, msg),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(tags, text_tags("TextPlayerMessage"));
+        assert_eq!(tags, schema_tags("PlayerMessage"));
+    }
+
+    #[test]
+    fn every_packet_and_hello_round_trips() {
+        let ack = serialize_packet(ReliablePacketOut::Ack(3));
+        validate("PacketOut", &serde_json::from_str(&ack).unwrap());
+        let ack = serde_json::to_string(&JsonPacketIn::Ack { id: 3 }).unwrap();
+        validate("PacketIn", &serde_json::from_str(&ack).unwrap());
+        assert!(matches!(parse_packet(&ack), Ok(ReliablePacketIn::Ack(3))));
+        let ack = serialize_packet(ReliablePacketOut::CumulativeAck(4, vec![6, 9]));
+        validate("PacketOut", &serde_json::from_str(&ack).unwrap());
+        let ack = r#"{"type":"cumulative_ack","up_to":4}"#;
+        validate("PacketIn", &serde_json::from_str(ack).unwrap());
+        match parse_packet(ack) {
+            Ok(ReliablePacketIn::CumulativeAck(4, selective)) => assert!(selective.is_empty()),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let token = "b".repeat(32);
+        for hello in [HelloOut::Ok(token.clone(), true), HelloOut::OutDated] {
+            validate(
+                "HelloOut",
+                &serde_json::from_str(&serialize_hello(hello)).unwrap(),
+            );
+        }
+        let hello = JsonHelloIn::Hello {
+            protocol_version: JSON_VERSION,
+            session_token: Some(token.clone()),
+        };
+        let hello = serde_json::to_string(&hello).unwrap();
+        validate("HelloIn", &serde_json::from_str(&hello).unwrap());
+        let parsed = HelloIn::parse(&hello).unwrap();
+        assert_eq!(parsed.protocol_version, JSON_VERSION);
+        assert_eq!(parsed.maybe_session_token, Some(token));
+    }
+
+    #[test]
+    fn error_codes_round_trip() {
+        let schema = protocol_schema();
+        let codes = schema["definitions"]["SrvMsgError"]["enum"]
+            .as_array()
+            .unwrap();
+        assert!(!codes.is_empty());
+        for code in codes {
+            let error: SrvMsgError = serde_json::from_value(code.clone()).unwrap();
+            assert_eq!(&serde_json::to_value(error).unwrap(), code);
+            // Same code as in the text protocol
+            assert_eq!(
+                ServerMessage::Error(Some(error)).serialize(),
+                format!("ERROR:{}", code.as_str().unwrap())
+            );
+        }
+        let codes = schema["definitions"]["ReliabilityError"]["enum"]
+            .as_array()
+            .unwrap();
+        assert!(!codes.is_empty());
+        for code in codes {
+            let error: ReliabilityError = serde_json::from_value(code.clone()).unwrap();
+            assert_eq!(&serde_json::to_value(error.clone()).unwrap(), code);
+            assert_eq!(error.serialize(), code.as_str().unwrap());
+        }
+    }
+}
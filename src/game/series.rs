@@ -0,0 +1,226 @@
+//! Best-of-N series played in one private lobby. The players take turns starting the
+//! games. Wins count one point and draws half a point each, the series ends as soon as
+//! one player has more than half of the N points or after N games, drawn if tied.
+
+use super::game_info::{now_millis, GameResult, Player};
+use super::snapshot::SeriesSnapshot;
+use crate::api::users::user::{PlayedSeriesInfo, UserId};
+use crate::logging::GameOId;
+
+use rand::{thread_rng, Rng};
+
+const SERIES_LENGTHS: [usize; 3] = [3, 5, 7];
+
+#[derive(Debug, Clone)]
+pub struct Series {
+    pub id: GameOId,
+    best_of: usize,
+    wins: [usize; 2],
+    draws: usize,
+    games: Vec<GameOId>,
+    next_first: Player,
+}
+
+impl Series {
+    pub fn new(best_of: usize) -> Series {
+        Series {
+            id: GameOId::new(),
+            best_of,
+            wins: [0; 2],
+            draws: 0,
+            games: Vec::new(),
+            next_first: [Player::One, Player::Two][thread_rng().gen_range(0, 2)],
+        }
+    }
+
+    /// "3", "5" or "7"
+    pub fn parse_best_of(text: &str) -> Option<usize> {
+        Series::check_best_of(text.parse().ok()?)
+    }
+
+    pub fn check_best_of(best_of: usize) -> Option<usize> {
+        if SERIES_LENGTHS.contains(&best_of) {
+            Some(best_of)
+        } else {
+            None
+        }
+    }
+
+    pub fn best_of(&self) -> usize {
+        self.best_of
+    }
+
+    /// (wins of `player`, wins of the opponent)
+    pub fn score(&self, player: Player) -> (usize, usize) {
+        player.select_both(self.wins[0], self.wins[1])
+    }
+
+    pub fn draws(&self) -> usize {
+        self.draws
+    }
+
+    /// The player who starts the next game, alternating with every call
+    pub fn start_game(&mut self) -> Player {
+        let first = self.next_first;
+        self.next_first = first.other();
+        first
+    }
+
+    /// Counts a finished game
+    pub fn record(&mut self, game_id: GameOId, result: GameResult) {
+        self.games.push(game_id);
+        match result {
+            GameResult::Winner(winner) => self.wins[winner.select(0, 1)] += 1,
+            GameResult::Draw => self.draws += 1,
+        }
+    }
+
+    pub fn has_games(&self) -> bool {
+        !self.games.is_empty()
+    }
+
+    /// Twice the points of both players, so that draws stay whole numbers
+    fn double_points(&self) -> [usize; 2] {
+        [2 * self.wins[0] + self.draws, 2 * self.wins[1] + self.draws]
+    }
+
+    /// None while the series is still running
+    pub fn result(&self) -> Option<GameResult> {
+        let [one, two] = self.double_points();
+        // More than half of all points can't be caught up with anymore
+        let decided = one.max(two) > self.best_of;
+        if !decided && self.games.len() < self.best_of {
+            return None;
+        }
+        Some(if one > two {
+            GameResult::Winner(Player::One)
+        } else if two > one {
+            GameResult::Winner(Player::Two)
+        } else {
+            GameResult::Draw
+        })
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.result().is_some()
+    }
+
+    pub fn winner(&self) -> Option<Player> {
+        match self.result() {
+            Some(GameResult::Winner(winner)) => Some(winner),
+            _ => None,
+        }
+    }
+
+    /// Builds the record that is stored in the series collection.
+    /// Player one is always the lobby host.
+    pub fn to_played_series_info(
+        &self,
+        player_one: Option<UserId>,
+        player_two: Option<UserId>,
+    ) -> PlayedSeriesInfo {
+        PlayedSeriesInfo {
+            id: self.id.clone(),
+            player_one,
+            player_two,
+            best_of: self.best_of as i32,
+            wins_one: self.wins[0] as i32,
+            wins_two: self.wins[1] as i32,
+            draws: self.draws as i32,
+            winner: self.winner(),
+            games: self.games.clone(),
+            ended_at: now_millis(),
+        }
+    }
+
+    pub fn snapshot(&self) -> SeriesSnapshot {
+        SeriesSnapshot {
+            id: self.id.clone(),
+            best_of: self.best_of as i32,
+            wins: [self.wins[0] as i32, self.wins[1] as i32],
+            draws: self.draws as i32,
+            games: self.games.clone(),
+            next_first: self.next_first,
+        }
+    }
+
+    pub fn restore(snapshot: SeriesSnapshot) -> Option<Series> {
+        let count = |n: i32| if n >= 0 { Some(n as usize) } else { None };
+        Some(Series {
+            id: snapshot.id,
+            best_of: Series::check_best_of(count(snapshot.best_of)?)?,
+            wins: [count(snapshot.wins[0])?, count(snapshot.wins[1])?],
+            draws: count(snapshot.draws)?,
+            games: snapshot.games,
+            next_first: snapshot.next_first,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_series_lengths() {
+        assert_eq!(Series::parse_best_of("3"), Some(3));
+        assert_eq!(Series::parse_best_of("7"), Some(7));
+        assert_eq!(Series::parse_best_of("4"), None);
+        assert_eq!(Series::parse_best_of("x"), None);
+    }
+
+    #[test]
+    fn starting_player_alternates() {
+        let mut series = Series::new(5);
+        let first = series.start_game();
+        assert_eq!(series.start_game(), first.other());
+        assert_eq!(series.start_game(), first);
+    }
+
+    #[test]
+    fn majority_of_wins_decides() {
+        let mut series = Series::new(5);
+        series.record(GameOId::new(), GameResult::Winner(Player::Two));
+        series.record(GameOId::new(), GameResult::Draw);
+        series.record(GameOId::new(), GameResult::Winner(Player::One));
+        assert_eq!(series.winner(), None);
+        assert_eq!(series.score(Player::Two), (1, 1));
+        series.record(GameOId::new(), GameResult::Winner(Player::Two));
+        assert!(!series.is_over());
+        series.record(GameOId::new(), GameResult::Winner(Player::Two));
+        assert_eq!(series.winner(), Some(Player::Two));
+
+        let info = series.to_played_series_info(None, None);
+        assert_eq!((info.wins_one, info.wins_two, info.draws), (1, 3, 1));
+        assert_eq!(info.games.len(), 5);
+    }
+
+    #[test]
+    fn draws_count_half() {
+        let mut series = Series::new(3);
+        series.record(GameOId::new(), GameResult::Winner(Player::One));
+        series.record(GameOId::new(), GameResult::Draw);
+        assert!(!series.is_over());
+        // 2 of 3 points can't be caught up with
+        series.record(GameOId::new(), GameResult::Draw);
+        assert_eq!(series.winner(), Some(Player::One));
+    }
+
+    #[test]
+    fn series_ends_after_best_of_games() {
+        let mut series = Series::new(5);
+        for _ in 0..4 {
+            series.record(GameOId::new(), GameResult::Draw);
+            assert!(!series.is_over());
+        }
+        series.record(GameOId::new(), GameResult::Draw);
+        assert_eq!(series.result(), Some(GameResult::Draw));
+        assert_eq!(series.winner(), None);
+
+        let mut series = Series::new(3);
+        series.record(GameOId::new(), GameResult::Winner(Player::One));
+        series.record(GameOId::new(), GameResult::Winner(Player::Two));
+        series.record(GameOId::new(), GameResult::Draw);
+        assert_eq!(series.result(), Some(GameResult::Draw));
+    }
+}
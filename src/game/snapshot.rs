@@ -0,0 +1,207 @@
+//! State of the game server that survives a restart. On shutdown every session which can
+//! reconnect with its `WSSessionToken`, the lobbies they play in and the running games are
+//! written to the snapshots collection, the next start picks them up again.
+//! All counters are signed because mongodb does not store unsigned integers.
+
+use super::clock::TimeControl;
+use super::connection_mgr::WSSessionToken;
+use super::game_info::{now_millis, BoardConfig, GameId, GameResult, Player};
+use crate::api::users::{session_token::SessionId, user::UserId};
+use crate::logging::GameOId;
+
+use serde::{Deserialize, Serialize};
+
+/// Snapshots older than this are discarded, nobody waits that long for a game to continue
+const MAX_SNAPSHOT_AGE_MS: i64 = 10 * 60 * 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerSnapshot {
+    pub taken_at: i64,
+    pub sessions: Vec<SessionSnapshot>,
+    pub lobbies: Vec<LobbySnapshot>,
+}
+
+impl ServerSnapshot {
+    pub fn new() -> ServerSnapshot {
+        ServerSnapshot {
+            taken_at: now_millis(),
+            sessions: Vec::new(),
+            lobbies: Vec::new(),
+        }
+    }
+
+    pub fn is_outdated(&self) -> bool {
+        now_millis() - self.taken_at > MAX_SNAPSHOT_AGE_MS
+    }
+}
+
+/// A client connection, legacy clients can't reconnect and are left out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub token: WSSessionToken,
+    pub user_id: Option<UserId>,
+    #[serde(default)]
+    pub session_id: Option<SessionId>, // The login session, so that revoking it still logs out
+    pub game_id: Option<GameId>, // The lobby the client plays in, see `ServerSnapshot::lobbies`
+    pub player: Option<Player>,
+    pub reliability: ReliabilitySnapshot,
+}
+
+/// Message ids of a `ClientAdapter`, so that the client's ids still match after the restart
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReliabilitySnapshot {
+    pub player_msg_index: i64,
+    pub server_msg_index: i64,
+    pub unacked: Vec<PendingServerMessage>,
+    pub backlog: Vec<String>, // Not sent yet, same format as `PendingServerMessage::msg`
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingServerMessage {
+    pub id: i64,
+    pub msg: String, // The message as sent with protocol version 5
+}
+
+/// A lobby with two players, only these have a game worth continuing.
+/// Tournament lobbies are left out as the tournaments themselves don't survive a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LobbySnapshot {
+    pub game_id: GameId,
+    pub board: BoardConfig,
+    pub time_control: TimeControl,
+    pub best_of: Option<i32>,
+    pub host_id: Option<UserId>,
+    pub joined_id: Option<UserId>,
+    pub bot_level: Option<i32>, // The joined player is a bot
+    pub game: GameSnapshot,
+    pub series: Option<SeriesSnapshot>,
+}
+
+/// The moves are replayed to restore the board, the clock is stopped until the players are back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameSnapshot {
+    pub id: GameOId,
+    pub first: Player,
+    pub moves: Vec<i32>,
+    pub move_times: Vec<i64>,
+    pub started_at: i64,
+    pub remaining_ms: [i64; 2],
+    pub draw_offer: Option<Player>,
+    pub result: Option<GameResult>,
+    pub requesting_rematch: Option<Player>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeriesSnapshot {
+    pub id: GameOId,
+    pub best_of: i32,
+    pub wins: [i32; 2],
+    pub draws: i32,
+    pub games: Vec<GameOId>,
+    pub next_first: Player,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::game_info::GameInfo;
+    use crate::game::series::Series;
+    use mongodb::bson;
+
+    fn stored<T: Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+        bson::from_bson(bson::to_bson(value).unwrap()).unwrap()
+    }
+
+    fn game_with_moves(moves: &[usize]) -> (GameInfo, BoardConfig, TimeControl) {
+        let config = BoardConfig::new(7, 6, 4).unwrap();
+        let time_control = TimeControl::fischer(60, 2).unwrap();
+        let mut game = GameInfo::with_first(config, time_control, Player::One);
+        let mut player = Player::One;
+        for column in moves {
+            game.place_chip(*column, player).unwrap();
+            player = player.other();
+        }
+        (game, config, time_control)
+    }
+
+    #[test]
+    fn game_continues_where_it_stopped() {
+        let (game, config, time_control) = game_with_moves(&[3, 3, 4]);
+        let now = now_millis();
+        let snapshot = stored(&game.snapshot(now));
+        let mut restored = GameInfo::restore(snapshot, config, time_control).unwrap();
+        assert_eq!(restored.id, game.id);
+        assert_eq!(restored.board().moves(), game.board().moves());
+        assert_eq!(restored.turn(), Player::Two);
+        // Paused until the players are back
+        let remaining = game.clock().remaining_ms(Player::Two, now);
+        assert_eq!(
+            restored.clock().remaining_ms(Player::Two, now + 60_000),
+            remaining
+        );
+        restored.resume(now);
+        assert_eq!(
+            restored.clock().remaining_ms(Player::Two, now + 1_000),
+            remaining - 1_000
+        );
+        assert!(restored.place_chip(4, Player::Two).is_ok());
+    }
+
+    #[test]
+    fn damaged_games_are_rejected() {
+        let (game, config, time_control) = game_with_moves(&[0, 0, 0, 0, 0, 0]);
+        let mut snapshot = game.snapshot(now_millis());
+        snapshot.moves.push(0); // The column is full
+        snapshot.move_times.push(now_millis());
+        assert!(GameInfo::restore(snapshot, config, time_control).is_none());
+        let mut snapshot = game.snapshot(now_millis());
+        snapshot.move_times.pop();
+        assert!(GameInfo::restore(snapshot, config, time_control).is_none());
+    }
+
+    #[test]
+    fn server_snapshot_can_be_stored() {
+        let (game, config, time_control) = game_with_moves(&[1]);
+        let series = Series::new(3);
+        let mut snapshot = ServerSnapshot::new();
+        snapshot.lobbies.push(LobbySnapshot {
+            game_id: GameId::parse("ABCD").unwrap(),
+            board: config,
+            time_control,
+            best_of: Some(3),
+            host_id: None,
+            joined_id: None,
+            bot_level: Some(2),
+            game: game.snapshot(now_millis()),
+            series: Some(series.snapshot()),
+        });
+        snapshot.sessions.push(SessionSnapshot {
+            token: "a".repeat(32),
+            user_id: None,
+            session_id: None,
+            game_id: GameId::parse("ABCD"),
+            player: Some(Player::One),
+            reliability: ReliabilitySnapshot {
+                player_msg_index: 4,
+                server_msg_index: 7,
+                unacked: vec![PendingServerMessage {
+                    id: 7,
+                    msg: r#"{"type":"place_chip","column":1}"#.to_owned(),
+                }],
+                backlog: Vec::new(),
+            },
+        });
+        let restored = stored(&snapshot);
+        assert!(!restored.is_outdated());
+        assert_eq!(restored.sessions[0].reliability.unacked[0].id, 7);
+        let lobby = &restored.lobbies[0];
+        assert_eq!(lobby.time_control, time_control);
+        assert_eq!(lobby.game.moves, vec![1]);
+        assert_eq!(
+            Series::restore(lobby.series.clone().unwrap())
+                .unwrap()
+                .best_of(),
+            3
+        );
+    }
+}
@@ -0,0 +1,564 @@
+//! Pairings and standings of a tournament. This only keeps score, playing the games
+//! is up to the `TournamentManager`.
+//! Single elimination uses a seeded bracket in which the best seeds get the byes.
+//! Swiss pairs players with equal scores who have not met yet, a bye counts as a win.
+
+use super::game_info::{BoardConfig, Player};
+use super::lobby_mgr::LobbySettings;
+use crate::api::{users::user::UserId, ApiError};
+use crate::logging::GameOId;
+
+use serde::Serialize;
+
+pub type TournamentId = GameOId;
+
+const MAX_PLAYERS: usize = 64;
+pub const MAX_SWISS_ROUNDS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum TournamentFormat {
+    SingleElimination,
+    Swiss { rounds: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum TournamentState {
+    Registration,
+    Running,
+    Finished,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Outcome {
+    Winner(Player), // Player one is `Pairing::one`
+    Draw,
+    BothForfeited, // Neither player showed up
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Pairing {
+    pub one: UserId,
+    pub two: Option<UserId>, // None if player one has a bye
+    pub outcome: Option<Outcome>,
+    pub forfeit: bool, // Decided without the game being played to the end
+}
+
+impl Pairing {
+    fn new(one: UserId, two: UserId) -> Pairing {
+        Pairing {
+            one,
+            two: Some(two),
+            outcome: None,
+            forfeit: false,
+        }
+    }
+
+    fn bye(one: UserId) -> Pairing {
+        Pairing {
+            one,
+            two: None,
+            outcome: Some(Outcome::Winner(Player::One)),
+            forfeit: false,
+        }
+    }
+
+    /// Which side of the pairing the user is on
+    fn side(&self, user: UserId) -> Option<Player> {
+        if self.one == user {
+            Some(Player::One)
+        } else if self.two == Some(user) {
+            Some(Player::Two)
+        } else {
+            None
+        }
+    }
+
+    fn opponent(&self, user: UserId) -> Option<UserId> {
+        match self.side(user)? {
+            Player::One => self.two,
+            Player::Two => Some(self.one),
+        }
+    }
+
+    fn winner(&self) -> Option<UserId> {
+        match self.outcome? {
+            Outcome::Winner(Player::One) => Some(self.one),
+            Outcome::Winner(Player::Two) => self.two,
+            Outcome::Draw | Outcome::BothForfeited => None,
+        }
+    }
+
+    /// In half points: two for a win or a bye, one for a draw
+    fn half_points(&self, user: UserId) -> usize {
+        match (self.outcome, self.side(user)) {
+            (Some(Outcome::Winner(winner)), Some(side)) if winner == side => 2,
+            (Some(Outcome::Draw), Some(_)) => 1,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Standing {
+    pub user_id: UserId,
+    pub points: f32,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    pub buchholz: f32, // Sum of the opponents' points, breaks ties
+    pub eliminated: bool,
+}
+
+pub struct Tournament {
+    pub id: TournamentId,
+    pub name: String,
+    pub creator: UserId,
+    pub format: TournamentFormat,
+    pub settings: LobbySettings,
+    players: Vec<UserId>, // Seeded in the order they joined
+    rounds: Vec<Vec<Pairing>>,
+    state: TournamentState,
+}
+
+impl Tournament {
+    pub fn new(
+        name: String,
+        creator: UserId,
+        format: TournamentFormat,
+        settings: LobbySettings,
+    ) -> Tournament {
+        Tournament {
+            id: TournamentId::new(),
+            name,
+            creator,
+            format,
+            settings,
+            players: Vec::new(),
+            rounds: Vec::new(),
+            state: TournamentState::Registration,
+        }
+    }
+
+    pub fn state(&self) -> TournamentState {
+        self.state
+    }
+
+    pub fn board(&self) -> BoardConfig {
+        self.settings.board
+    }
+
+    pub fn players(&self) -> &[UserId] {
+        &self.players
+    }
+
+    pub fn rounds(&self) -> &[Vec<Pairing>] {
+        &self.rounds
+    }
+
+    pub fn join(&mut self, user: UserId) -> Result<(), ApiError> {
+        if self.state != TournamentState::Registration {
+            Err(ApiError::TournamentStarted)
+        } else if self.players.contains(&user) {
+            Err(ApiError::AlreadyJoined)
+        } else if self.players.len() >= MAX_PLAYERS {
+            Err(ApiError::TournamentFull)
+        } else {
+            self.players.push(user);
+            Ok(())
+        }
+    }
+
+    pub fn start(&mut self) -> Result<(), ApiError> {
+        if self.state != TournamentState::Registration {
+            return Err(ApiError::TournamentStarted);
+        }
+        if self.players.len() < 2 {
+            return Err(ApiError::NotEnoughPlayers);
+        }
+        self.state = TournamentState::Running;
+        self.next_round();
+        Ok(())
+    }
+
+    /// Starting at one, zero before the tournament started
+    pub fn round_number(&self) -> usize {
+        self.rounds.len()
+    }
+
+    /// Indices of the pairings of the current round that still need a result
+    pub fn pending_pairings(&self) -> Vec<usize> {
+        match (self.state, self.rounds.last()) {
+            (TournamentState::Running, Some(round)) => round
+                .iter()
+                .enumerate()
+                .filter(|(_, pairing)| pairing.outcome.is_none())
+                .map(|(i, _)| i)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn pairing(&self, index: usize) -> Option<&Pairing> {
+        self.rounds.last()?.get(index)
+    }
+
+    /// Records the result of a game between two players of the current round and starts the
+    /// next round once all results are in. Drawn elimination games have to be replayed.
+    /// `one` is the lobby host, which need not be `Pairing::one`.
+    pub fn record(&mut self, one: UserId, two: UserId, outcome: Outcome, forfeit: bool) -> bool {
+        if self.state != TournamentState::Running {
+            return false;
+        }
+        let elimination = self.format == TournamentFormat::SingleElimination;
+        let pairing = match self.rounds.last_mut().and_then(|round| {
+            round
+                .iter_mut()
+                .find(|pairing| pairing.outcome.is_none() && pairing.opponent(one) == Some(two))
+        }) {
+            Some(pairing) => pairing,
+            None => return false,
+        };
+        if elimination && outcome == Outcome::Draw {
+            return false;
+        }
+        pairing.outcome = Some(match outcome {
+            // Results are reported from the host's point of view
+            Outcome::Winner(winner) if pairing.one != one => Outcome::Winner(winner.other()),
+            outcome => outcome,
+        });
+        pairing.forfeit = forfeit;
+        if self.pending_pairings().is_empty() {
+            self.next_round();
+        }
+        true
+    }
+
+    /// The last remaining player or the leader of a finished Swiss tournament
+    pub fn winner(&self) -> Option<UserId> {
+        if self.state != TournamentState::Finished {
+            return None;
+        }
+        match self.format {
+            TournamentFormat::SingleElimination => {
+                let winners: Vec<_> = self
+                    .rounds
+                    .last()?
+                    .iter()
+                    .filter_map(Pairing::winner)
+                    .collect();
+                if winners.len() == 1 {
+                    Some(winners[0])
+                } else {
+                    None
+                }
+            }
+            TournamentFormat::Swiss { .. } => {
+                self.standings().first().map(|standing| standing.user_id)
+            }
+        }
+    }
+
+    /// Best first: by points, then by the opponents' points, then by seed
+    pub fn standings(&self) -> Vec<Standing> {
+        let half_points = |user: UserId| -> usize {
+            self.rounds
+                .iter()
+                .flatten()
+                .map(|pairing| pairing.half_points(user))
+                .sum()
+        };
+        let mut standings: Vec<_> = self
+            .players
+            .iter()
+            .enumerate()
+            .map(|(seed, &user)| {
+                let mut standing = Standing {
+                    user_id: user,
+                    points: half_points(user) as f32 / 2.0,
+                    wins: 0,
+                    draws: 0,
+                    losses: 0,
+                    buchholz: 0.0,
+                    eliminated: false,
+                };
+                for pairing in self.rounds.iter().flatten() {
+                    let side = match pairing.side(user) {
+                        Some(side) => side,
+                        None => continue,
+                    };
+                    match pairing.outcome {
+                        Some(Outcome::Winner(winner)) if winner == side => standing.wins += 1,
+                        Some(Outcome::Draw) => standing.draws += 1,
+                        Some(_) => {
+                            standing.losses += 1;
+                            standing.eliminated =
+                                self.format == TournamentFormat::SingleElimination;
+                        }
+                        None => {}
+                    }
+                    if let Some(opponent) = pairing.opponent(user) {
+                        standing.buchholz += half_points(opponent) as f32 / 2.0;
+                    }
+                }
+                (seed, standing)
+            })
+            .collect();
+        standings.sort_by(|(seed_a, a), (seed_b, b)| {
+            b.points
+                .partial_cmp(&a.points)
+                .unwrap()
+                .then(b.buchholz.partial_cmp(&a.buchholz).unwrap())
+                .then(seed_a.cmp(seed_b))
+        });
+        standings
+            .into_iter()
+            .map(|(_, standing)| standing)
+            .collect()
+    }
+
+    fn next_round(&mut self) {
+        let pairings = match self.format {
+            TournamentFormat::SingleElimination => self.elimination_pairings(),
+            TournamentFormat::Swiss { rounds } if self.rounds.len() < rounds => {
+                Some(self.swiss_pairings())
+            }
+            TournamentFormat::Swiss { .. } => None,
+        };
+        match pairings {
+            Some(pairings) => {
+                self.rounds.push(pairings);
+                // A round of byes only is decided right away
+                if self.pending_pairings().is_empty() {
+                    self.next_round();
+                }
+            }
+            None => self.state = TournamentState::Finished,
+        }
+    }
+
+    /// `None` once at most one player is left
+    fn elimination_pairings(&self) -> Option<Vec<Pairing>> {
+        let remaining: Vec<Option<UserId>> = match self.rounds.last() {
+            None => bracket_order(self.players.len())
+                .into_iter()
+                .map(|seed| self.players.get(seed).copied())
+                .collect(),
+            Some(round) => round.iter().map(Pairing::winner).collect(),
+        };
+        if remaining.iter().flatten().count() < 2 {
+            return None;
+        }
+        // Neighbours in the bracket meet, an empty slot means a bye
+        Some(
+            remaining
+                .chunks(2)
+                .filter_map(|slots| match slots {
+                    [Some(one), Some(two)] => Some(Pairing::new(*one, *two)),
+                    [Some(one), None] | [None, Some(one)] | [Some(one)] => Some(Pairing::bye(*one)),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    fn swiss_pairings(&self) -> Vec<Pairing> {
+        let mut unpaired: Vec<UserId> = self
+            .standings()
+            .into_iter()
+            .map(|standing| standing.user_id)
+            .collect();
+        let mut maybe_bye = None;
+        if unpaired.len() % 2 == 1 {
+            // The lowest ranked player who did not have a bye yet
+            let had_bye = |user: &UserId| {
+                self.rounds
+                    .iter()
+                    .flatten()
+                    .any(|pairing| pairing.one == *user && pairing.two.is_none())
+            };
+            let index = unpaired
+                .iter()
+                .rposition(|user| !had_bye(user))
+                .unwrap_or(unpaired.len() - 1);
+            maybe_bye = Some(Pairing::bye(unpaired.remove(index)));
+        }
+        let mut pairings = Vec::new();
+        while !unpaired.is_empty() {
+            let one = unpaired.remove(0);
+            let have_met = |other: &UserId| {
+                self.rounds
+                    .iter()
+                    .flatten()
+                    .any(|pairing| pairing.opponent(one) == Some(*other))
+            };
+            // Rematches only if everyone else is taken
+            let index = unpaired
+                .iter()
+                .position(|other| !have_met(other))
+                .unwrap_or(0);
+            let two = unpaired.remove(index);
+            pairings.push(Pairing::new(one, two));
+        }
+        pairings.extend(maybe_bye);
+        pairings
+    }
+}
+
+/// Seeds (starting at zero) in bracket order, so that the best seeds meet as late as
+/// possible. Seeds beyond the number of players are byes.
+fn bracket_order(players: usize) -> Vec<usize> {
+    let mut order = vec![0];
+    while order.len() < players {
+        let size = order.len() * 2;
+        order = order
+            .into_iter()
+            .flat_map(|seed| vec![seed, size - 1 - seed])
+            .collect();
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tournament(format: TournamentFormat, players: usize) -> (Tournament, Vec<UserId>) {
+        let mut tournament = Tournament::new(
+            "Test".to_owned(),
+            UserId::new(),
+            format,
+            LobbySettings::default(),
+        );
+        let users: Vec<_> = (0..players).map(|_| UserId::new()).collect();
+        for user in &users {
+            tournament.join(*user).unwrap();
+        }
+        tournament.start().unwrap();
+        (tournament, users)
+    }
+
+    /// Lets the better seed (lower index in `users`) win every pending game
+    fn play_round(tournament: &mut Tournament, users: &[UserId]) {
+        let seed = |user: &UserId| users.iter().position(|u| u == user).unwrap();
+        for index in tournament.pending_pairings() {
+            let pairing = tournament.pairing(index).unwrap().clone();
+            let two = pairing.two.unwrap();
+            let winner = if seed(&pairing.one) < seed(&two) {
+                Player::One
+            } else {
+                Player::Two
+            };
+            assert!(tournament.record(pairing.one, two, Outcome::Winner(winner), false));
+        }
+    }
+
+    #[test]
+    fn bracket_keeps_best_seeds_apart() {
+        assert_eq!(bracket_order(2), vec![0, 1]);
+        assert_eq!(bracket_order(8), vec![0, 7, 3, 4, 1, 6, 2, 5]);
+        assert_eq!(bracket_order(5).len(), 8);
+    }
+
+    #[test]
+    fn best_seeds_get_the_byes() {
+        let (mut tournament, users) = tournament(TournamentFormat::SingleElimination, 5);
+        let byes: Vec<_> = tournament.rounds()[0]
+            .iter()
+            .filter(|pairing| pairing.two.is_none())
+            .map(|pairing| pairing.one)
+            .collect();
+        assert_eq!(byes, vec![users[0], users[1], users[2]]);
+        assert_eq!(tournament.pending_pairings().len(), 1);
+
+        while tournament.state() == TournamentState::Running {
+            play_round(&mut tournament, &users);
+        }
+        assert_eq!(tournament.round_number(), 3);
+        assert_eq!(tournament.winner(), Some(users[0]));
+        let standings = tournament.standings();
+        assert_eq!(standings[0].user_id, users[0]);
+        assert!(standings[1..].iter().all(|standing| standing.eliminated));
+    }
+
+    #[test]
+    fn elimination_draws_are_replayed() {
+        let (mut tournament, users) = tournament(TournamentFormat::SingleElimination, 2);
+        assert!(!tournament.record(users[0], users[1], Outcome::Draw, false));
+        assert_eq!(tournament.pending_pairings(), vec![0]);
+        // Reported by the lobby host, who is the second seed here
+        assert!(tournament.record(users[1], users[0], Outcome::Winner(Player::One), false));
+        assert_eq!(tournament.winner(), Some(users[1]));
+    }
+
+    #[test]
+    fn swiss_avoids_rematches() {
+        let (mut tournament, users) = tournament(TournamentFormat::Swiss { rounds: 3 }, 4);
+        while tournament.state() == TournamentState::Running {
+            play_round(&mut tournament, &users);
+        }
+        assert_eq!(tournament.round_number(), 3);
+        let mut games: Vec<_> = tournament
+            .rounds()
+            .iter()
+            .flatten()
+            .map(|pairing| {
+                let two = pairing.two.unwrap();
+                if pairing.one < two {
+                    (pairing.one, two)
+                } else {
+                    (two, pairing.one)
+                }
+            })
+            .collect();
+        games.sort();
+        games.dedup();
+        assert_eq!(games.len(), 6);
+
+        let points: Vec<_> = tournament.standings().iter().map(|s| s.points).collect();
+        assert_eq!(points, vec![3.0, 2.0, 1.0, 0.0]);
+        assert_eq!(tournament.winner(), Some(users[0]));
+    }
+
+    #[test]
+    fn swiss_byes_rotate() {
+        let (mut tournament, users) = tournament(TournamentFormat::Swiss { rounds: 3 }, 3);
+        while tournament.state() == TournamentState::Running {
+            play_round(&mut tournament, &users);
+        }
+        let byes: Vec<_> = tournament
+            .rounds()
+            .iter()
+            .flatten()
+            .filter(|pairing| pairing.two.is_none())
+            .map(|pairing| pairing.one)
+            .collect();
+        assert_eq!(byes.len(), 3);
+        assert!(users.iter().all(|user| byes.contains(user)));
+    }
+
+    #[test]
+    fn registration_rules() {
+        let mut tournament = Tournament::new(
+            "Test".to_owned(),
+            UserId::new(),
+            TournamentFormat::SingleElimination,
+            LobbySettings::default(),
+        );
+        let user = UserId::new();
+        tournament.join(user).unwrap();
+        assert!(matches!(
+            tournament.join(user),
+            Err(ApiError::AlreadyJoined)
+        ));
+        assert!(matches!(
+            tournament.start(),
+            Err(ApiError::NotEnoughPlayers)
+        ));
+        tournament.join(UserId::new()).unwrap();
+        tournament.start().unwrap();
+        assert!(matches!(
+            tournament.join(UserId::new()),
+            Err(ApiError::TournamentStarted)
+        ));
+    }
+}
@@ -0,0 +1,553 @@
+//! Runs the tournaments created over the REST api. Every round the manager asks the
+//! `LobbyManager` for a lobby per pairing and records the games it stores afterwards.
+//! Pairings that could not be played after `no_show_after` are forfeited by whoever
+//! was not online.
+
+use super::game_info::{GameId, GameResult, Player};
+use super::lobby_mgr::LobbySettings;
+use super::tournament::*;
+use crate::api::{
+    users::user::{PlayedGameInfo, UserId},
+    ApiError,
+};
+use crate::logging::GameEndReason;
+
+use actix::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const TICK_S: u64 = 5;
+const NO_SHOW_DEFAULT_S: u64 = 5 * 60;
+
+pub struct TournamentManager {
+    tournaments: HashMap<TournamentId, RunningTournament>,
+    games: Recipient<TournamentGameRequest>,
+    tick: Duration,
+    no_show_after: Duration,
+}
+
+struct RunningTournament {
+    tournament: Tournament,
+    round_started: Instant,
+    pairings: HashMap<usize, PairingState>, // By index into the current round
+}
+
+enum PairingState {
+    Requested,
+    InLobby(GameId),
+    Absent(Vec<UserId>), // Players who were not online at the last attempt
+}
+
+impl TournamentManager {
+    pub fn new(games: Recipient<TournamentGameRequest>) -> TournamentManager {
+        let no_show_s = std::env::var("TOURNAMENT_NO_SHOW_S")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(NO_SHOW_DEFAULT_S);
+        TournamentManager {
+            tournaments: HashMap::new(),
+            games,
+            tick: Duration::from_secs(TICK_S),
+            no_show_after: Duration::from_secs(no_show_s),
+        }
+    }
+
+    /// Starts the games of the current rounds and forfeits pairings nobody showed up for
+    fn start_games(&mut self, ctx: &mut Context<Self>) {
+        let now = Instant::now();
+        for (id, running) in self.tournaments.iter_mut() {
+            let round = running.tournament.round_number();
+            for index in running.tournament.pending_pairings() {
+                if running.tournament.round_number() != round {
+                    break; // Forfeits completed the round
+                }
+                let absent = match running.pairings.get(&index) {
+                    Some(PairingState::Requested) | Some(PairingState::InLobby(_)) => continue,
+                    Some(PairingState::Absent(absent)) => absent.clone(),
+                    None => Vec::new(),
+                };
+                let pairing = match running.tournament.pairing(index) {
+                    Some(pairing) => pairing.clone(),
+                    None => continue,
+                };
+                let two = match pairing.two {
+                    Some(two) => two,
+                    None => continue,
+                };
+                let forfeit = match (absent.contains(&pairing.one), absent.contains(&two)) {
+                    (false, true) => Some(Outcome::Winner(Player::One)),
+                    (true, false) => Some(Outcome::Winner(Player::Two)),
+                    (true, true) => Some(Outcome::BothForfeited),
+                    // Both were online, e.g. a drawn elimination game is replayed
+                    (false, false) => None,
+                };
+                let overdue = now.duration_since(running.round_started) >= self.no_show_after;
+                if let Some(outcome) = forfeit.filter(|_| overdue) {
+                    println!(
+                        "TournamentMgr: {:?} in round {} of {} by forfeit",
+                        outcome, round, id
+                    );
+                    running.tournament.record(pairing.one, two, outcome, true);
+                    continue;
+                }
+                running.pairings.insert(index, PairingState::Requested);
+                let id = id.clone();
+                let request = self.games.send(TournamentGameRequest {
+                    tournament_id: id.clone(),
+                    one: pairing.one,
+                    two,
+                    settings: running.tournament.settings,
+                });
+                // `self.tournaments` is borrowed, so `into_actor` can't be used here
+                ctx.spawn(
+                    fut::wrap_future::<_, Self>(request).map(move |res, act, _| {
+                        if let Some(running) = act.tournaments.get_mut(&id) {
+                            if running.tournament.round_number() != round {
+                                return;
+                            }
+                            let state = match res {
+                                Ok(Ok(game_id)) => PairingState::InLobby(game_id),
+                                Ok(Err(absent)) => PairingState::Absent(absent),
+                                Err(_) => PairingState::Absent(Vec::new()),
+                            };
+                            running.pairings.insert(index, state);
+                        }
+                    }),
+                );
+            }
+            running.start_round_if_new(round);
+        }
+    }
+}
+
+impl RunningTournament {
+    /// Resets the attempts once the tournament moved on to the next round
+    fn start_round_if_new(&mut self, previous_round: usize) {
+        if self.tournament.round_number() != previous_round {
+            self.round_started = Instant::now();
+            self.pairings.clear();
+        }
+    }
+
+    fn to_public(&self) -> PublicTournament {
+        let tournament = &self.tournament;
+        PublicTournament {
+            id: tournament.id.to_string(),
+            name: tournament.name.clone(),
+            creator: tournament.creator,
+            format: tournament.format,
+            board: tournament.board(),
+            state: tournament.state(),
+            players: tournament.players().to_vec(),
+            rounds: tournament.rounds().to_vec(),
+            standings: tournament.standings(),
+            winner: tournament.winner(),
+        }
+    }
+}
+
+impl Actor for TournamentManager {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.tick, |act, ctx| act.start_games(ctx));
+    }
+}
+
+/// Sent to the `LobbyManager` to set up the game of a pairing.
+/// Player one hosts the lobby. Fails with the players who are not online.
+pub struct TournamentGameRequest {
+    pub tournament_id: TournamentId,
+    pub one: UserId,
+    pub two: UserId,
+    pub settings: LobbySettings,
+}
+
+impl Message for TournamentGameRequest {
+    type Result = Result<GameId, Vec<UserId>>;
+}
+
+/// What the REST api returns, standings are best first
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicTournament {
+    pub id: String,
+    pub name: String,
+    pub creator: UserId,
+    pub format: TournamentFormat,
+    pub board: super::game_info::BoardConfig,
+    pub state: TournamentState,
+    pub players: Vec<UserId>,
+    pub rounds: Vec<Vec<Pairing>>,
+    pub standings: Vec<Standing>,
+    pub winner: Option<UserId>,
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum TournamentMsg {
+    GameOver(PlayedGameInfo),
+    LobbyClosed(GameId),
+}
+
+impl Message for TournamentMsg {
+    type Result = ();
+}
+
+impl Handler<TournamentMsg> for TournamentManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: TournamentMsg, _ctx: &mut Self::Context) -> Self::Result {
+        match msg {
+            TournamentMsg::GameOver(game_info) => {
+                let running = match game_info
+                    .tournament_id
+                    .as_ref()
+                    .and_then(|id| self.tournaments.get_mut(id))
+                {
+                    Some(running) => running,
+                    None => return,
+                };
+                let (one, two) = match (game_info.player_one, game_info.player_two) {
+                    (Some(one), Some(two)) => (one, two),
+                    _ => return,
+                };
+                let outcome = match game_info.result {
+                    Some(GameResult::Winner(winner)) => Outcome::Winner(winner),
+                    Some(GameResult::Draw) => Outcome::Draw,
+                    None => return,
+                };
+                let forfeit = matches!(
+                    game_info.end_reason,
+                    GameEndReason::PlayerLeft | GameEndReason::PlayerDisconnected
+                );
+                let round = running.tournament.round_number();
+                running.tournament.record(one, two, outcome, forfeit);
+                running.start_round_if_new(round);
+                if running.tournament.state() == TournamentState::Finished {
+                    println!(
+                        "TournamentMgr: {} finished, winner: {:?}",
+                        running.tournament.id,
+                        running.tournament.winner()
+                    );
+                }
+            }
+            TournamentMsg::LobbyClosed(game_id) => {
+                // Without a result the pairing is tried again
+                for running in self.tournaments.values_mut() {
+                    running.pairings.retain(|_, state| match state {
+                        PairingState::InLobby(id) => *id != game_id,
+                        _ => true,
+                    });
+                }
+            }
+        }
+    }
+}
+
+pub struct CreateTournament {
+    pub creator: UserId,
+    pub name: String,
+    pub format: TournamentFormat,
+    pub settings: LobbySettings,
+}
+
+impl Message for CreateTournament {
+    type Result = Result<PublicTournament, ApiError>;
+}
+
+impl Handler<CreateTournament> for TournamentManager {
+    type Result = Result<PublicTournament, ApiError>;
+
+    fn handle(&mut self, msg: CreateTournament, _ctx: &mut Self::Context) -> Self::Result {
+        let tournament = Tournament::new(msg.name, msg.creator, msg.format, msg.settings);
+        let running = RunningTournament {
+            tournament,
+            round_started: Instant::now(),
+            pairings: HashMap::new(),
+        };
+        let public = running.to_public();
+        self.tournaments
+            .insert(running.tournament.id.clone(), running);
+        Ok(public)
+    }
+}
+
+pub enum TournamentRequest {
+    Join(TournamentId, UserId),
+    Start(TournamentId, UserId), // Only the creator may start the tournament
+    Get(TournamentId),
+}
+
+impl Message for TournamentRequest {
+    type Result = Result<PublicTournament, ApiError>;
+}
+
+impl Handler<TournamentRequest> for TournamentManager {
+    type Result = Result<PublicTournament, ApiError>;
+
+    fn handle(&mut self, msg: TournamentRequest, ctx: &mut Self::Context) -> Self::Result {
+        let id = match &msg {
+            TournamentRequest::Join(id, _)
+            | TournamentRequest::Start(id, _)
+            | TournamentRequest::Get(id) => id.clone(),
+        };
+        let running = self
+            .tournaments
+            .get_mut(&id)
+            .ok_or(ApiError::TournamentNotFound)?;
+        match msg {
+            TournamentRequest::Join(_, user) => running.tournament.join(user)?,
+            TournamentRequest::Start(_, user) => {
+                if running.tournament.creator != user {
+                    return Err(ApiError::NotTournamentCreator);
+                }
+                running.tournament.start()?;
+                running.round_started = Instant::now();
+                running.pairings.clear();
+            }
+            TournamentRequest::Get(_) => {}
+        }
+        let public = running.to_public();
+        if let TournamentRequest::Start(..) = msg {
+            self.start_games(ctx);
+        }
+        Ok(public)
+    }
+}
+
+pub struct ListTournaments;
+
+impl Message for ListTournaments {
+    type Result = Vec<PublicTournament>;
+}
+
+impl Handler<ListTournaments> for TournamentManager {
+    type Result = MessageResult<ListTournaments>;
+
+    fn handle(&mut self, _: ListTournaments, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(
+            self.tournaments
+                .values()
+                .map(RunningTournament::to_public)
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::game_info::{now_millis, BoardConfig};
+    use crate::logging::GameOId;
+    use std::collections::HashSet;
+
+    /// Stands in for the LobbyManager and the players' clients: games between online
+    /// players end right away and the stronger player wins, before `draws_until` they draw.
+    struct SimulatedLobbies {
+        online: HashSet<UserId>,
+        strength: HashMap<UserId, usize>,
+        draws_until: Instant,
+        tournament_mgr: Option<Addr<TournamentManager>>,
+    }
+
+    impl Actor for SimulatedLobbies {
+        type Context = Context<Self>;
+    }
+
+    struct Link(Addr<TournamentManager>);
+
+    impl Message for Link {
+        type Result = ();
+    }
+
+    impl Handler<Link> for SimulatedLobbies {
+        type Result = ();
+
+        fn handle(&mut self, msg: Link, _ctx: &mut Self::Context) {
+            self.tournament_mgr = Some(msg.0);
+        }
+    }
+
+    impl Handler<TournamentGameRequest> for SimulatedLobbies {
+        type Result = Result<GameId, Vec<UserId>>;
+
+        fn handle(&mut self, msg: TournamentGameRequest, ctx: &mut Self::Context) -> Self::Result {
+            let absent: Vec<_> = vec![msg.one, msg.two]
+                .into_iter()
+                .filter(|user| !self.online.contains(user))
+                .collect();
+            if !absent.is_empty() {
+                return Err(absent);
+            }
+            let result = if Instant::now() < self.draws_until {
+                GameResult::Draw
+            } else if self.strength[&msg.one] > self.strength[&msg.two] {
+                GameResult::Winner(Player::One)
+            } else {
+                GameResult::Winner(Player::Two)
+            };
+            let game_id = GameId::generate(&[]);
+            let game_info = PlayedGameInfo {
+                id: GameOId::new(),
+                player_one: Some(msg.one),
+                player_two: Some(msg.two),
+                starting_player: Player::One,
+                board: BoardConfig::default(),
+                moves: Vec::new(),
+                result: Some(result),
+                end_reason: GameEndReason::Regular,
+                started_at: now_millis(),
+                ended_at: now_millis(),
+                rating_change: None,
+                bot_level: None,
+                series_id: None,
+                tournament_id: Some(msg.tournament_id),
+            };
+            // The game ends after the manager learned about the lobby
+            ctx.run_later(Duration::from_millis(5), move |act, _| {
+                let tournament_mgr = act.tournament_mgr.as_ref().unwrap();
+                tournament_mgr.do_send(TournamentMsg::GameOver(game_info));
+                tournament_mgr.do_send(TournamentMsg::LobbyClosed(game_id));
+            });
+            Ok(game_id)
+        }
+    }
+
+    /// Players are created strongest first, `offline` of the weakest never show up.
+    /// All games are drawn for `draws_for`, forfeits are due after 100ms.
+    async fn run_tournament(
+        format: TournamentFormat,
+        players: usize,
+        offline: usize,
+        draws_for: Duration,
+    ) -> (PublicTournament, Vec<UserId>) {
+        let users: Vec<_> = (0..players).map(|_| UserId::new()).collect();
+        let lobbies = SimulatedLobbies {
+            online: users[..players - offline].iter().copied().collect(),
+            strength: users
+                .iter()
+                .enumerate()
+                .map(|(i, user)| (*user, players - i))
+                .collect(),
+            draws_until: Instant::now() + draws_for,
+            tournament_mgr: None,
+        }
+        .start();
+        let tournament_mgr = TournamentManager {
+            tournaments: HashMap::new(),
+            games: lobbies.clone().recipient(),
+            tick: Duration::from_millis(10),
+            no_show_after: Duration::from_millis(100),
+        }
+        .start();
+        lobbies.send(Link(tournament_mgr.clone())).await.unwrap();
+
+        let creator = users[0];
+        let id = tournament_mgr
+            .send(CreateTournament {
+                creator,
+                name: "Simulated".to_owned(),
+                format,
+                settings: LobbySettings::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+        let id = TournamentId::parse(&id).unwrap();
+        for user in &users {
+            tournament_mgr
+                .send(TournamentRequest::Join(id.clone(), *user))
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        assert!(matches!(
+            tournament_mgr
+                .send(TournamentRequest::Start(id.clone(), users[1]))
+                .await
+                .unwrap(),
+            Err(ApiError::NotTournamentCreator)
+        ));
+        tournament_mgr
+            .send(TournamentRequest::Start(id.clone(), creator))
+            .await
+            .unwrap()
+            .unwrap();
+        for _ in 0..200 {
+            let tournament = tournament_mgr
+                .send(TournamentRequest::Get(id.clone()))
+                .await
+                .unwrap()
+                .unwrap();
+            if tournament.state == TournamentState::Finished {
+                return (tournament, users);
+            }
+            actix_rt::time::delay_for(Duration::from_millis(10)).await;
+        }
+        panic!("tournament did not finish");
+    }
+
+    #[actix_rt::test]
+    async fn elimination_with_simulated_clients() {
+        let (tournament, users) = run_tournament(
+            TournamentFormat::SingleElimination,
+            5,
+            0,
+            Duration::default(),
+        )
+        .await;
+        assert_eq!(tournament.rounds.len(), 3);
+        assert_eq!(tournament.winner, Some(users[0]));
+        assert!(tournament
+            .rounds
+            .iter()
+            .flatten()
+            .all(|pairing| !pairing.forfeit));
+    }
+
+    #[actix_rt::test]
+    async fn swiss_with_simulated_clients() {
+        let rounds = 3;
+        let (tournament, users) = run_tournament(
+            TournamentFormat::Swiss { rounds },
+            6,
+            0,
+            Duration::default(),
+        )
+        .await;
+        assert_eq!(tournament.rounds.len(), rounds);
+        assert_eq!(tournament.winner, Some(users[0]));
+        assert_eq!(tournament.standings[0].points, rounds as f32);
+        let total: f32 = tournament.standings.iter().map(|s| s.points).sum();
+        assert_eq!(total, (rounds * 3) as f32);
+    }
+
+    #[actix_rt::test]
+    async fn no_shows_forfeit() {
+        let (tournament, users) = run_tournament(
+            TournamentFormat::SingleElimination,
+            2,
+            1,
+            Duration::default(),
+        )
+        .await;
+        let final_game = &tournament.rounds[0][0];
+        assert!(final_game.forfeit);
+        assert_eq!(tournament.winner, Some(users[0]));
+    }
+
+    #[actix_rt::test]
+    async fn drawn_games_are_replayed_after_the_deadline() {
+        let (tournament, users) = run_tournament(
+            TournamentFormat::SingleElimination,
+            2,
+            0,
+            Duration::from_millis(300),
+        )
+        .await;
+        let final_game = &tournament.rounds[0][0];
+        assert!(!final_game.forfeit);
+        assert_eq!(tournament.winner, Some(users[0]));
+    }
+}
@@ -1,18 +1,39 @@
 use actix::Message;
 use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct GameOId(ObjectId);
 
 impl GameOId {
     pub fn new() -> Self {
         GameOId(ObjectId::new())
     }
+
+    /// Parses the hex representation used in the REST api
+    pub fn parse(text: &str) -> Option<GameOId> {
+        ObjectId::with_string(text).ok().map(GameOId)
+    }
+
+    pub fn object_id(&self) -> ObjectId {
+        self.0.clone()
+    }
 }
+impl fmt::Display for GameOId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.to_hex())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum GameEndReason {
     Regular,
     PlayerLeft,
     PlayerDisconnected,
+    Timeout, // The loser ran out of time
+    Resigned,
+    DrawAgreed,
 }
 
 pub enum GameLogEvent {
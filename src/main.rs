@@ -2,11 +2,12 @@ mod api;
 mod database;
 mod game;
 mod logging;
+mod shutdown;
 
 use std::io;
 use std::sync::Arc;
 
-use actix::Actor;
+use actix::{Actor, Addr};
 use actix_cors::Cors;
 use actix_files as fs;
 use actix_web::{middleware, web, App, HttpResponse, HttpServer};
@@ -14,11 +15,14 @@ use actix_web::{middleware, web, App, HttpResponse, HttpServer};
 use api::users::user_mgr::UserManager;
 use database::DatabaseManager;
 use dotenv::dotenv;
-use game::connection_mgr::ConnectionManager;
-use game::lobby_mgr::LobbyManager;
+use game::connection_mgr::{ConnectionManager, RestoreSnapshot, TakeSnapshot};
+use game::lobby_mgr::{LobbyManager, LobbyManagerMsg};
+use game::tournament_mgr::TournamentManager;
 use logging::Logger;
+use shutdown::ShutdownTrigger;
 
 const DEFAULT_BIND_ADDR: &str = "127.0.0.1:40146";
+const SHUTDOWN_TIMEOUT_S: u64 = 5; // Websockets never finish on their own, games are saved instead
 
 #[actix_rt::main]
 async fn main() {
@@ -61,7 +65,26 @@ async fn start_server(bind_addr: &str) -> io::Result<()> {
         logger_addr.clone(),
     )
     .start();
-    HttpServer::new(move || {
+    let tournament_mgr_addr = TournamentManager::new(lobby_mgr_addr.clone().recipient()).start();
+    lobby_mgr_addr.do_send(LobbyManagerMsg::TournamentBacklink(
+        tournament_mgr_addr.clone(),
+    ));
+    if let Some(snapshot) = db_mgr.snapshots.take().await {
+        if snapshot.is_outdated() {
+            println!("Discarding outdated snapshot");
+        } else {
+            connection_mgr_addr.do_send(RestoreSnapshot {
+                snapshot,
+                lobby_mgr: lobby_mgr_addr.clone(),
+                user_mgr: user_mgr_addr.clone(),
+            });
+        }
+    }
+    let snapshot_db_mgr = db_mgr.clone();
+    let snapshot_connection_mgr_addr = connection_mgr_addr.clone();
+    let drain_lobby_mgr_addr = lobby_mgr_addr.clone();
+    let (shutdown_trigger, shutdown_requests) = ShutdownTrigger::new();
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
             .wrap(middleware::Compress::default())
@@ -69,6 +92,8 @@ async fn start_server(bind_addr: &str) -> io::Result<()> {
             .data(lobby_mgr_addr.clone())
             .data(connection_mgr_addr.clone())
             .data(user_mgr_addr.clone())
+            .data(tournament_mgr_addr.clone())
+            .data(shutdown_trigger.clone())
             .route(
                 "/",
                 web::get().to(|| {
@@ -103,8 +128,33 @@ async fn start_server(bind_addr: &str) -> io::Result<()> {
             .default_service(web::to(HttpResponse::NotFound))
     })
     .keep_alive(1)
+    .shutdown_timeout(SHUTDOWN_TIMEOUT_S)
+    .disable_signals() // The server is drained first, see `shutdown`
     .bind(bind_addr)
     .expect("Failed to bind address.")
-    .run()
-    .await
+    .run();
+    actix_rt::spawn(shutdown::shut_down_on_request(
+        server.clone(),
+        shutdown_requests,
+        drain_lobby_mgr_addr,
+        snapshot_connection_mgr_addr.clone(),
+    ));
+    let res = server.await;
+    // Running games continue after the next start
+    save_snapshot(&snapshot_db_mgr, &snapshot_connection_mgr_addr).await;
+    res
+}
+
+async fn save_snapshot(db_mgr: &DatabaseManager, connection_mgr_addr: &Addr<ConnectionManager>) {
+    match connection_mgr_addr.send(TakeSnapshot).await {
+        Ok(snapshot) => {
+            let (sessions, lobbies) = (snapshot.sessions.len(), snapshot.lobbies.len());
+            if db_mgr.snapshots.insert(snapshot).await {
+                println!("Saved {} sessions and {} lobbies", sessions, lobbies);
+            } else {
+                println!("Failed to save snapshot");
+            }
+        }
+        Err(_) => println!("Failed to take snapshot"),
+    }
 }
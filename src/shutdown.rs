@@ -0,0 +1,81 @@
+//! Drains the server before it exits. On SIGTERM or an admin request to `/api/shutdown` no new
+//! games are started and all clients are told when the server goes down. Running games may be
+//! finished until then, the ones that aren't over by then are kept in the snapshot.
+//! SIGINT stops the server right away, also while it is drained.
+
+use std::time::{Duration, Instant};
+
+use actix::Addr;
+use actix_rt::signal::{
+    self,
+    unix::{signal, SignalKind},
+};
+use actix_web::dev::Server;
+use futures::{
+    channel::mpsc,
+    future::{select, Either},
+    StreamExt,
+};
+
+use crate::game::connection_mgr::{ConnectionManager, ConnectionManagerMsg};
+use crate::game::lobby_mgr::{Drain, LobbyManager, WaitForResults};
+
+/// Should be shorter than the time docker waits before it kills the container
+const DRAIN_DEFAULT_S: u64 = 4 * 60;
+
+/// Starts draining the server, see `shut_down_on_request`
+#[derive(Clone)]
+pub struct ShutdownTrigger(mpsc::UnboundedSender<()>);
+
+impl ShutdownTrigger {
+    pub fn new() -> (ShutdownTrigger, mpsc::UnboundedReceiver<()>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (ShutdownTrigger(sender), receiver)
+    }
+
+    pub fn trigger(&self) {
+        let _ = self.0.unbounded_send(());
+    }
+}
+
+/// Waits for a signal or a `ShutdownTrigger` and stops the server once it is drained.
+/// The signals of the `HttpServer` itself have to be disabled.
+pub async fn shut_down_on_request(
+    server: Server,
+    mut triggered: mpsc::UnboundedReceiver<()>,
+    lobby_mgr: Addr<LobbyManager>,
+    connection_mgr: Addr<ConnectionManager>,
+) {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    let drain_requested = select(Box::pin(terminate.recv()), triggered.next());
+    if let Either::Right(_) = select(Box::pin(signal::ctrl_c()), drain_requested).await {
+        // Set SHUTDOWN_DRAIN_S to 0 to stop right away
+        let drain_s = std::env::var("SHUTDOWN_DRAIN_S")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DRAIN_DEFAULT_S);
+        println!("Draining the server for at most {} seconds", drain_s);
+        let deadline = Instant::now() + Duration::from_secs(drain_s);
+        let drained = drain(deadline, &lobby_mgr, &connection_mgr);
+        if let Either::Right(_) = select(Box::pin(drained), Box::pin(signal::ctrl_c())).await {
+            println!("Draining interrupted");
+        }
+    }
+    server.stop(true).await;
+}
+
+async fn drain(
+    deadline: Instant,
+    lobby_mgr: &Addr<LobbyManager>,
+    connection_mgr: &Addr<ConnectionManager>,
+) {
+    connection_mgr.do_send(ConnectionManagerMsg::ServerRestarting(deadline));
+    match lobby_mgr.send(Drain { deadline }).await {
+        Ok(0) => println!("All games are over"),
+        Ok(running) => println!("{} games continue after the restart", running),
+        Err(_) => println!("Failed to drain the lobbies"),
+    }
+    if lobby_mgr.send(WaitForResults).await.is_err() {
+        println!("Failed to wait for the game results");
+    }
+}